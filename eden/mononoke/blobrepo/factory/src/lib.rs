@@ -396,6 +396,7 @@ pub fn init_all_derived_data() -> DerivedDataConfig {
             TreeHandle::NAME.to_string(),
         },
         unode_version: UnodeVersion::V2,
+        fsnode_compression_level: None,
     }
 }
 
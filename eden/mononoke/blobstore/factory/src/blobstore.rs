@@ -10,6 +10,7 @@ use blobstore::{Blobstore, DisabledBlob, ErrorKind};
 use blobstore_sync_queue::SqlBlobstoreSyncQueue;
 use chaosblob::{ChaosBlobstore, ChaosOptions};
 use cloned::cloned;
+use fallbackblob::FallbackBlobstore;
 use fbinit::FacebookInit;
 use fileblob::Fileblob;
 use futures::{FutureExt, TryFutureExt};
@@ -19,7 +20,7 @@ use futures_old::{
     Future,
 };
 use metaconfig_types::{
-    BlobConfig, BlobstoreId, DatabaseConfig, MultiplexId, ScrubAction,
+    BlobConfig, DatabaseConfig, MultiplexId, MultiplexedBlobstoreConfig, ScrubAction,
     ShardableRemoteDatabaseConfig,
 };
 use multiplexedblob::{LoggingScrubHandler, MultiplexedBlobstore, ScrubBlobstore, ScrubHandler};
@@ -193,6 +194,32 @@ pub fn make_blobstore(
             )
         }
 
+        Fallback { primary, fallback } => {
+            has_components = true;
+            let primary = make_blobstore(
+                fb,
+                *primary,
+                mysql_options,
+                readonly_storage,
+                blobstore_options.clone(),
+                logger.clone(),
+            );
+            let fallback = make_blobstore(
+                fb,
+                *fallback,
+                mysql_options,
+                readonly_storage,
+                blobstore_options.clone(),
+                logger,
+            );
+            primary
+                .join(fallback)
+                .map(|(primary, fallback)| {
+                    Arc::new(FallbackBlobstore::new(primary, fallback)) as Arc<dyn Blobstore>
+                })
+                .boxify()
+        }
+
         Manifold { bucket, prefix } => {
             #[cfg(fbcode_build)]
             {
@@ -282,7 +309,7 @@ pub fn make_blobstore_multiplexed(
     queue_db: DatabaseConfig,
     scuba_table: Option<String>,
     scuba_sample_rate: NonZeroU64,
-    inner_config: Vec<(BlobstoreId, BlobConfig)>,
+    inner_config: Vec<MultiplexedBlobstoreConfig>,
     mysql_options: MysqlOptions,
     readonly_storage: ReadOnlyStorage,
     scrub_args: Option<(Arc<dyn ScrubHandler>, ScrubAction)>,
@@ -300,8 +327,11 @@ pub fn make_blobstore_multiplexed(
         .into_iter()
         .map({
             cloned!(logger);
-            move |(blobstoreid, config)| {
-                cloned!(blobstoreid, mut blobstore_options);
+            // Read-only components are still opened for writes; all the multiplex
+            // writes go to every component for now.
+            move |component: MultiplexedBlobstoreConfig| {
+                let blobstoreid = component.blobstore_id;
+                cloned!(mut blobstore_options);
                 if blobstore_options.chaos_options.has_chaos() {
                     if applied_chaos {
                         blobstore_options = BlobstoreOptions {
@@ -315,7 +345,7 @@ pub fn make_blobstore_multiplexed(
                 make_blobstore(
                     // force per line for easier merges
                     fb,
-                    config,
+                    component.blobstore,
                     mysql_options,
                     component_readonly,
                     blobstore_options,
@@ -0,0 +1,122 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use anyhow::Error;
+use blobstore::{Blobstore, BlobstoreGetData};
+use context::CoreContext;
+use futures::{future, Future};
+use futures_ext::{BoxFuture, FutureExt};
+use mononoke_types::BlobstoreBytes;
+
+/// A blobstore that reads from `primary` first and falls through to `fallback` for keys the
+/// primary doesn't have. Writes only go to `primary`, so this can be used to move a repo onto
+/// a new blobstore while the old one is still being copied over.
+#[derive(Clone, Debug)]
+pub struct FallbackBlobstore<T: Blobstore + Clone, U: Blobstore + Clone> {
+    primary: T,
+    fallback: U,
+}
+
+impl<T: Blobstore + Clone, U: Blobstore + Clone> FallbackBlobstore<T, U> {
+    pub fn new(primary: T, fallback: U) -> Self {
+        Self { primary, fallback }
+    }
+}
+
+impl<T: Blobstore + Clone, U: Blobstore + Clone> Blobstore for FallbackBlobstore<T, U> {
+    fn get(&self, ctx: CoreContext, key: String) -> BoxFuture<Option<BlobstoreGetData>, Error> {
+        let fallback = self.fallback.clone();
+        self.primary
+            .get(ctx.clone(), key.clone())
+            .and_then(move |value| match value {
+                Some(value) => future::ok(Some(value)).left_future(),
+                None => fallback.get(ctx, key).right_future(),
+            })
+            .boxify()
+    }
+
+    #[inline]
+    fn put(&self, ctx: CoreContext, key: String, value: BlobstoreBytes) -> BoxFuture<(), Error> {
+        self.primary.put(ctx, key, value)
+    }
+
+    fn is_present(&self, ctx: CoreContext, key: String) -> BoxFuture<bool, Error> {
+        let fallback = self.fallback.clone();
+        self.primary
+            .is_present(ctx.clone(), key.clone())
+            .and_then(move |present| {
+                if present {
+                    future::ok(true).left_future()
+                } else {
+                    fallback.is_present(ctx, key).right_future()
+                }
+            })
+            .boxify()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use fbinit::FacebookInit;
+
+    use memblob::EagerMemblob;
+
+    #[fbinit::test]
+    fn test_fallback(fb: FacebookInit) {
+        let ctx = CoreContext::test_mock(fb);
+        let primary = EagerMemblob::new();
+        let fallback = EagerMemblob::new();
+        let wrapper = FallbackBlobstore::new(primary.clone(), fallback.clone());
+        let get = |key: &str| {
+            wrapper
+                .get(ctx.clone(), key.to_string())
+                .wait()
+                .unwrap()
+                .map(|data| data.into_bytes())
+        };
+
+        // We're using EagerMemblob (immediate future completion) so calling wait() is fine.
+        fallback
+            .put(
+                ctx.clone(),
+                "old".to_string(),
+                BlobstoreBytes::from_bytes("old value"),
+            )
+            .wait()
+            .unwrap();
+        assert_eq!(get("old"), Some(BlobstoreBytes::from_bytes("old value")));
+        assert!(wrapper
+            .is_present(ctx.clone(), "old".to_string())
+            .wait()
+            .unwrap());
+        assert_eq!(get("missing"), None);
+
+        // Writes only go to the primary, which then shadows the fallback.
+        wrapper
+            .put(
+                ctx.clone(),
+                "old".to_string(),
+                BlobstoreBytes::from_bytes("new value"),
+            )
+            .wait()
+            .unwrap();
+        assert_eq!(get("old"), Some(BlobstoreBytes::from_bytes("new value")));
+        assert!(primary
+            .is_present(ctx.clone(), "old".to_string())
+            .wait()
+            .unwrap());
+        assert_eq!(
+            fallback
+                .get(ctx.clone(), "old".to_string())
+                .wait()
+                .unwrap()
+                .map(|data| data.into_bytes()),
+            Some(BlobstoreBytes::from_bytes("old value"))
+        );
+    }
+}
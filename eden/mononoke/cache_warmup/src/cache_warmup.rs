@@ -219,31 +219,32 @@ async fn do_cache_warmup(
     Ok(())
 }
 
-async fn microwave_preload(ctx: &CoreContext, repo: &BlobRepo, req: &CacheWarmupRequest) {
-    if req.microwave_preload {
-        match microwave::prime_cache(&ctx, &repo, SnapshotLocation::Blobstore).await {
-            Ok(_) => {
-                warn!(ctx.logger(), "microwave: successfully primed cached");
-            }
-            Err(e) => {
-                warn!(ctx.logger(), "microwave: cache warmup failed: {:#?}", e);
-            }
+async fn microwave_preload(ctx: &CoreContext, repo: &BlobRepo) {
+    match microwave::prime_cache(&ctx, &repo, SnapshotLocation::Blobstore).await {
+        Ok(_) => {
+            warn!(ctx.logger(), "microwave: successfully primed cached");
+        }
+        Err(e) => {
+            warn!(ctx.logger(), "microwave: cache warmup failed: {:#?}", e);
         }
     }
 }
 
-/// Fetch all manifest entries for a bookmark, and fetches up to `commit_warmup_limit`
-/// ancestors of the bookmark.
+/// Fetch all manifest entries for each requested bookmark, and fetches up to
+/// `commit_warmup_limit` ancestors of the bookmark.
 pub async fn cache_warmup<T: Into<CacheWarmupRequest>>(
     ctx: &CoreContext,
     repo: &BlobRepo,
-    cache_warmup: Option<T>,
+    cache_warmup: impl IntoIterator<Item = T>,
 ) -> Result<(), Error> {
-    if let Some(req) = cache_warmup {
-        let req = req.into();
+    let reqs: Vec<CacheWarmupRequest> = cache_warmup.into_iter().map(Into::into).collect();
 
-        microwave_preload(ctx, repo, &req).await;
+    // The microwave snapshot covers the whole repo, so only prime it once.
+    if reqs.iter().any(|req| req.microwave_preload) {
+        microwave_preload(ctx, repo).await;
+    }
 
+    for req in reqs {
         do_cache_warmup(ctx, repo, req.target, req.commit_limit)
             .await
             .with_context(|| format!("while warming up repo {}", repo.get_repoid()))?;
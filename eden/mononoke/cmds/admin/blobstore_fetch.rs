@@ -99,9 +99,9 @@ fn get_blobconfig(
                 let seeked_id = BlobstoreId::new(inner_blobstore_id);
                 blobstores
                     .into_iter()
-                    .find_map(|(blobstore_id, blobstore)| {
-                        if blobstore_id == seeked_id {
-                            Some(blobstore)
+                    .find_map(|component| {
+                        if component.blobstore_id == seeked_id {
+                            Some(component.blobstore)
                         } else {
                             None
                         }
@@ -240,6 +240,9 @@ async fn run_subcmd<'a>(
                 .transpose()
                 .map(|skip| skip.unwrap_or(0))?;
 
+            let (_, config) = args::get_config(fb, &matches)?;
+            let batch_size = config.derived_data_batch_size.unwrap_or(CHUNK_SIZE);
+
             let repo = open_repo_maybe_unredacted(fb, &logger, &matches, &derived_data_type)
                 .compat()
                 .await?;
@@ -304,6 +307,7 @@ async fn run_subcmd<'a>(
                 &derived_data_type,
                 regenerate,
                 changesets,
+                batch_size,
                 cleaner,
             )
             .await
@@ -413,6 +417,7 @@ async fn subcommand_backfill(
     derived_data_type: &String,
     regenerate: bool,
     changesets: Vec<ChangesetId>,
+    batch_size: usize,
     mut cleaner: Option<impl dry_run::Cleaner>,
 ) -> Result<(), Error> {
     let derived_utils = &derived_data_utils_unsafe(repo.clone(), derived_data_type.clone())?;
@@ -431,7 +436,7 @@ async fn subcommand_backfill(
         derived_utils.regenerate(&changesets);
     }
 
-    for chunk in changesets.chunks(CHUNK_SIZE) {
+    for chunk in changesets.chunks(batch_size) {
         let (stats, chunk_size) = async {
             let chunk = derived_utils
                 .pending(ctx.clone(), repo.clone(), chunk.to_vec())
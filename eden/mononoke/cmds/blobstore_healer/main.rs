@@ -146,6 +146,7 @@ async fn maybe_schedule_healer_for_storage(
 
     let blobstores = blobstore_configs
         .into_iter()
+        .map(|component| (component.blobstore_id, component.blobstore))
         .map(|(id, blobconfig)| async move {
             let blobstore = make_blobstore(
                 fb,
@@ -228,8 +228,8 @@ fn parse_args(fb: FacebookInit) -> Result<Config, Error> {
     };
     let manifold_args = blobstores
         .iter()
-        .filter(|(id, _)| src_blobstore_id == *id)
-        .map(|(_, args)| args)
+        .filter(|component| src_blobstore_id == component.blobstore_id)
+        .map(|component| &component.blobstore)
         .next()
         .ok_or(format_err!(
             "failed to find source blobstore id: {:?}",
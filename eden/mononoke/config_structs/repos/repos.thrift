@@ -118,6 +118,34 @@ struct RawRepoConfig {
     // Name of this repository in hgsql for globalrevs. Required for syncing
     // globalrevs through the sync job.
     37: optional string hgsql_globalrevs_name,
+
+    // Number of changesets derived together in one batch when backfilling
+    // derived data. Must be positive. Defaults to the backfiller's built-in
+    // value.
+    38: optional i64 derived_data_batch_size,
+    // Fraction of scuba samples to log, between 0.0 and 1.0. Defaults to
+    // logging every sample.
+    39: optional double scuba_sample_rate,
+    // Additional bookmarks to warm up the cache for, on top of
+    // `cache_warmup`.
+    40: optional list<RawCacheWarmupConfig> cache_warmups,
+    // How often to rebuild the skiplist at `skiplist_index_blobstore_key`,
+    // as a duration string such as "1h". Unset means it is rebuilt
+    // externally.
+    41: optional string skiplist_rebuild_interval,
+    // Which hashes `hash_validation_percentage` validates: "FilenodeSha1",
+    // "ContentBlake2" or "Both". Defaults to "Both".
+    42: optional string hash_validation_mode,
+    // Files that pushes may not add or modify.
+    43: optional RawPushPolicy push_policy,
+    // Prepended to all blobstore keys of the repo, so that repos can share
+    // a blobstore without their keys colliding.
+    44: optional string blobstore_key_prefix,
+    // Rewrites applied to commit author identities at push time.
+    45: optional list<RawIdentityRewriteRule> identity_rewrite,
+    // Makes the repo read-only during a maintenance window. Can't be
+    // combined with `readonly`.
+    46: optional RawReadOnlyWindow readonly_window,
 }
 
 struct RawDerivedDataConfig {
@@ -125,6 +153,8 @@ struct RawDerivedDataConfig {
   2: optional set<string> derived_data_types,
   // Defaults to v1
   3: optional RawUnodeVersion raw_unode_version,
+  // zstd level to compress fsnode blobs with. Unset means uncompressed.
+  4: optional i32 fsnode_compression_level,
 }
 
 union RawUnodeVersion {
@@ -161,6 +191,20 @@ struct RawBlobstoreManifoldWithTtl {
     2: string manifold_prefix,
     3: i64 ttl_secs,
 }
+// Not supported yet: configs naming one are rejected until there is a GCS
+// client to open it with.
+struct RawBlobstoreGcs {
+    1: string gcs_bucket,
+    // Name of the credentials to authenticate with, looked up by the server.
+    2: optional string gcs_credentials_key,
+}
+// Reads go to the primary first and fall through to the fallback for keys
+// the primary doesn't have. Writes only go to the primary. Used to migrate
+// a repo onto a new blobstore without copying everything up front.
+struct RawBlobstoreFallback {
+    1: RawBlobstoreConfig primary (rust.box),
+    2: RawBlobstoreConfig fallback (rust.box),
+}
 
 // Configuration for a single blobstore. These are intended to be defined in a
 // separate blobstore.toml config file, and then referenced by name from a
@@ -177,11 +221,16 @@ union RawBlobstoreConfig {
     6: RawBlobstoreMysql mysql,
     7: RawBlobstoreMultiplexed multiplexed,
     8: RawBlobstoreManifoldWithTtl manifold_with_ttl,
+    9: RawBlobstoreFallback fallback,
+    10: RawBlobstoreGcs gcs,
 }
 
 struct RawBlobstoreIdConfig {
     1: i64 blobstore_id,
     2: RawBlobstoreConfig blobstore,
+    // Blobstore is still read from, but no new writes go to it. Used when
+    // draining a blobstore out of a multiplex.
+    3: optional bool read_only,
 }
 
 struct RawDbLocal {
@@ -322,6 +371,28 @@ struct RawInfinitepushParams {
     4: optional bool populate_reverse_filler_queue,
 }
 
+struct RawReadOnlyWindow {
+    // RFC3339 timestamps, e.g. "2020-06-01T09:00:00+01:00". The repo is
+    // read-only from `start` until just before `end`.
+    1: string start,
+    2: string end,
+}
+
+struct RawIdentityRewriteRule {
+    // Regex matched against the whole author email
+    1: string match_email,
+    2: optional string replace_email,
+    3: optional string replace_name,
+}
+
+struct RawPushPolicy {
+    // Extensions without the leading dot, e.g. "exe"
+    1: optional list<string> denied_extensions,
+    // Globs matched against the whole path, e.g. "secrets/**"
+    2: optional list<string> denied_path_globs,
+    3: optional i64 max_file_size_bytes,
+}
+
 struct RawFilestoreParams {
     1: i64 chunk_size,
     2: i32 concurrency,
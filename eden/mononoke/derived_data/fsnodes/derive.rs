@@ -41,6 +41,7 @@ pub(crate) fn derive_fsnode(
 ) -> impl Future<Item = FsnodeId, Error = Error> {
     future::lazy(move || {
         let blobstore = repo.get_blobstore();
+        let compression_level = repo.get_derived_data_config().fsnode_compression_level;
         let content_ids = changes
             .iter()
             .filter_map(|(_mpath, content_id_and_file_type)| {
@@ -61,6 +62,7 @@ pub(crate) fn derive_fsnode(
                             create_fsnode(
                                 ctx.clone(),
                                 blobstore.clone(),
+                                compression_level,
                                 Some(sender),
                                 prefetched_content_metadata.clone(),
                                 tree_info,
@@ -78,9 +80,16 @@ pub(crate) fn derive_fsnode(
                             parents,
                             subentries: Default::default(),
                         };
-                        create_fsnode(ctx, blobstore, None, prefetched_content_metadata, tree_info)
-                            .map(|(_, tree_id)| tree_id)
-                            .right_future()
+                        create_fsnode(
+                            ctx,
+                            blobstore,
+                            compression_level,
+                            None,
+                            prefetched_content_metadata,
+                            tree_info,
+                        )
+                        .map(|(_, tree_id)| tree_id)
+                        .right_future()
                     }
                 })
             })
@@ -231,10 +240,12 @@ fn collect_fsnode_subentries(
     })
 }
 
-/// Create a new fsnode for the tree described by `tree_info`.
+/// Create a new fsnode for the tree described by `tree_info`. The fsnode blob
+/// is compressed if `compression_level` is set.
 fn create_fsnode(
     ctx: CoreContext,
     blobstore: RepoBlobstore,
+    compression_level: Option<i32>,
     sender: Option<mpsc::UnboundedSender<BoxFuture<(), Error>>>,
     prefetched_content_metadata: Arc<HashMap<ContentId, ContentMetadata>>,
     tree_info: TreeInfo<FsnodeId, (ContentId, FileType), Option<FsnodeSummary>>,
@@ -299,7 +310,13 @@ fn create_fsnode(
         let fsnode = Fsnode::new(entries, summary.clone());
         let fsnode_id = fsnode.get_fsnode_id();
         let key = fsnode_id.blobstore_key();
-        let blob = fsnode.into_blob();
+        let blob = match compression_level {
+            Some(level) => match fsnode.into_compressed_blob(level) {
+                Ok(blob) => blob,
+                Err(e) => return future::err(e).left_future(),
+            },
+            None => fsnode.into_blob(),
+        };
         let f = blobstore.put(ctx, key, blob.into()).boxify();
 
         let res = match sender {
@@ -310,7 +327,7 @@ fn create_fsnode(
                 .left_future(),
             None => f.right_future(),
         };
-        res.map(move |()| (Some(summary), fsnode_id))
+        res.map(move |()| (Some(summary), fsnode_id)).right_future()
     })
 }
 
@@ -14,9 +14,11 @@ use thiserror::Error;
 mod batch;
 mod derive;
 mod mapping;
+mod three_way;
 
 pub use derive::prefetch_content_metadata;
 pub use mapping::{RootFsnodeId, RootFsnodeMapping};
+pub use three_way::{fsnode_three_way, ThreeWayEntry};
 
 #[derive(Debug, Error)]
 pub enum ErrorKind {
@@ -10,7 +10,7 @@ use crate::derive::derive_fsnode;
 use anyhow::{Error, Result};
 use async_trait::async_trait;
 use blobrepo::BlobRepo;
-use blobstore::{Blobstore, BlobstoreGetData};
+use blobstore::{Blobstore, BlobstoreGetData, Loadable};
 use bytes::Bytes;
 use context::CoreContext;
 use derived_data::{BonsaiDerived, BonsaiDerivedMapping};
@@ -22,8 +22,11 @@ use futures_old::{
     stream::{self, FuturesUnordered},
     Future, Stream,
 };
+use manifest::{Entry, ManifestOps};
+use mononoke_types::fsnode::FsnodeEntry;
 use mononoke_types::{
     BlobstoreBytes, BonsaiChangeset, ChangesetId, ContentId, FileType, FsnodeId, MPath,
+    MPathElement,
 };
 use repo_blobstore::RepoBlobstore;
 use std::{
@@ -140,6 +143,99 @@ impl RootFsnodeMapping {
         format!("derived_root_fsnode.{}", cs_id)
     }
 
+    /// Find the type (regular, executable or symlink) of the file at `path`
+    /// in changeset `cs_id`, deriving fsnodes if needed.
+    ///
+    /// Returns `None` if `path` does not exist or is a directory.
+    pub async fn file_type_at(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        cs_id: ChangesetId,
+        path: MPath,
+    ) -> Result<Option<FileType>> {
+        let root_fsnode_id = RootFsnodeId::derive(ctx.clone(), repo.clone(), cs_id)
+            .compat()
+            .await?;
+        let entry = root_fsnode_id
+            .fsnode_id()
+            .find_entry(ctx.clone(), repo.get_blobstore(), Some(path))
+            .compat()
+            .await?;
+        Ok(match entry {
+            Some(Entry::Leaf((_content_id, file_type))) => Some(file_type),
+            _ => None,
+        })
+    }
+
+    /// List the immediate children of the directory at `dir` (the root if
+    /// `None`) in changeset `cs_id`, deriving fsnodes if needed.
+    ///
+    /// Returns `None` if `dir` does not exist or is a file.
+    pub async fn list_directory(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        cs_id: ChangesetId,
+        dir: Option<MPath>,
+    ) -> Result<Option<Vec<(MPathElement, FsnodeEntry)>>> {
+        let root_fsnode_id = RootFsnodeId::derive(ctx.clone(), repo.clone(), cs_id)
+            .compat()
+            .await?;
+        let entry = root_fsnode_id
+            .fsnode_id()
+            .find_entry(ctx.clone(), repo.get_blobstore(), dir)
+            .compat()
+            .await?;
+        let fsnode_id = match entry {
+            Some(Entry::Tree(fsnode_id)) => fsnode_id,
+            _ => return Ok(None),
+        };
+        let fsnode = fsnode_id
+            .load(ctx.clone(), repo.blobstore())
+            .compat()
+            .await?;
+        Ok(Some(
+            fsnode
+                .list()
+                .map(|(name, entry)| (name.clone(), entry.clone()))
+                .collect(),
+        ))
+    }
+
+    /// Check whether `path` exists, as either a file or a directory, in
+    /// changeset `cs_id`, deriving fsnodes if needed.
+    ///
+    /// Only the directories along `path` are loaded, stopping at the first
+    /// missing component.
+    pub async fn path_exists(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        cs_id: ChangesetId,
+        path: MPath,
+    ) -> Result<bool> {
+        let root_fsnode_id = RootFsnodeId::derive(ctx.clone(), repo.clone(), cs_id)
+            .compat()
+            .await?;
+        let entry = root_fsnode_id
+            .fsnode_id()
+            .find_entry(ctx.clone(), repo.get_blobstore(), Some(path))
+            .compat()
+            .await?;
+        Ok(entry.is_some())
+    }
+
+    /// Split `csids` into those that already have fsnodes derived and those
+    /// that don't, keeping their order. The mapping is queried in one batch.
+    pub async fn filter_derived(
+        &self,
+        ctx: &CoreContext,
+        csids: Vec<ChangesetId>,
+    ) -> Result<(Vec<ChangesetId>, Vec<ChangesetId>)> {
+        let derived = self.get(ctx.clone(), csids.clone()).compat().await?;
+        Ok(csids
+            .into_iter()
+            .partition(|cs_id| derived.contains_key(cs_id)))
+    }
+
     fn fetch_fsnode(
         &self,
         ctx: CoreContext,
@@ -202,10 +298,11 @@ mod test {
         merge_even, merge_uneven, unshared_merge_even, unshared_merge_uneven,
     };
     use futures::future::Future as NewFuture;
-    use manifest::Entry;
     use mercurial_types::{HgChangesetId, HgManifestId};
     use revset::AncestorsNodeStream;
+    use std::str::FromStr;
     use test_utils::iterate_all_entries;
+    use tests_utils::CreateCommitContext;
     use tokio_compat::runtime::Runtime;
 
     fn fetch_manifest_by_cs_id(
@@ -312,4 +409,143 @@ mod test {
         verify_repo(fb, unshared_merge_even::getrepo(fb), &mut runtime);
         verify_repo(fb, unshared_merge_uneven::getrepo(fb), &mut runtime);
     }
+
+    #[fbinit::compat_test]
+    async fn test_file_type_at(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = blobrepo_factory::new_memblob_empty(None)?;
+
+        let cs_id = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("dir/regular", "regular")
+            .add_file_with_type("dir/exec", "#!/bin/sh", FileType::Executable)
+            .add_file_with_type("link", "dir/regular", FileType::Symlink)
+            .commit()
+            .await?;
+
+        let file_type_at = |path: &str| {
+            RootFsnodeMapping::file_type_at(&ctx, &repo, cs_id, MPath::new(path).unwrap())
+        };
+        assert_eq!(file_type_at("dir/regular").await?, Some(FileType::Regular));
+        assert_eq!(file_type_at("dir/exec").await?, Some(FileType::Executable));
+        assert_eq!(file_type_at("link").await?, Some(FileType::Symlink));
+        assert_eq!(file_type_at("dir").await?, None);
+        assert_eq!(file_type_at("missing").await?, None);
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_list_directory(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = many_files_dirs::getrepo(fb).await;
+
+        let hg_cs_id = HgChangesetId::from_str("d261bc7900818dea7c86935b3fb17a33b2e3a6b4")?;
+        let cs_id = repo
+            .get_bonsai_from_hg(ctx.clone(), hg_cs_id)
+            .compat()
+            .await?
+            .expect("changeset exists");
+
+        // Directory listings as (name, is_directory) pairs.
+        let list = |dir: Option<&str>| {
+            let dir = dir.map(|dir| MPath::new(dir).unwrap());
+            let list = RootFsnodeMapping::list_directory(&ctx, &repo, cs_id, dir);
+            async move {
+                let entries = list.await?.map(|entries| {
+                    entries
+                        .into_iter()
+                        .map(|(name, entry)| {
+                            let is_dir = match entry {
+                                FsnodeEntry::Directory(_) => true,
+                                FsnodeEntry::File(_) => false,
+                            };
+                            (String::from_utf8_lossy(name.as_ref()).to_string(), is_dir)
+                        })
+                        .collect::<Vec<_>>()
+                });
+                Ok::<_, Error>(entries)
+            }
+        };
+
+        let entry = |name: &str, is_dir| (name.to_string(), is_dir);
+        assert_eq!(
+            list(None).await?,
+            Some(vec![
+                entry("1", false),
+                entry("2", false),
+                entry("dir1", true),
+                entry("dir2", true),
+            ])
+        );
+        assert_eq!(
+            list(Some("dir1/subdir1")).await?,
+            Some(vec![
+                entry("file_1", false),
+                entry("subsubdir1", true),
+                entry("subsubdir2", true),
+            ])
+        );
+        assert_eq!(list(Some("dir1/file_1_in_dir1")).await?, None);
+        assert_eq!(list(Some("missing")).await?, None);
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_path_exists(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = linear::getrepo(fb).await;
+
+        let hg_cs_id = HgChangesetId::from_str("79a13814c5ce7330173ec04d279bf95ab3f652fb")?;
+        let cs_id = repo
+            .get_bonsai_from_hg(ctx.clone(), hg_cs_id)
+            .compat()
+            .await?
+            .expect("changeset exists");
+
+        let exists = |path: &str| {
+            RootFsnodeMapping::path_exists(&ctx, &repo, cs_id, MPath::new(path).unwrap())
+        };
+        assert!(exists("files").await?);
+        assert!(exists("10").await?);
+        assert!(!exists("11").await?);
+        assert!(!exists("10/nested").await?);
+
+        Ok(())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_filter_derived(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = blobrepo_factory::new_memblob_empty(None)?;
+
+        let a = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("file", "a")
+            .commit()
+            .await?;
+        let b = CreateCommitContext::new(&ctx, &repo, vec![a])
+            .add_file("file", "b")
+            .commit()
+            .await?;
+        let c = CreateCommitContext::new(&ctx, &repo, vec![b])
+            .add_file("file", "c")
+            .commit()
+            .await?;
+        let d = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("file", "d")
+            .commit()
+            .await?;
+
+        // Deriving b also derives its ancestor a.
+        RootFsnodeId::derive(ctx.clone(), repo.clone(), b)
+            .compat()
+            .await?;
+
+        let mapping = RootFsnodeId::mapping(&ctx, &repo);
+        let (derived, not_derived) = mapping.filter_derived(&ctx, vec![c, a, d, b]).await?;
+        assert_eq!(derived, vec![a, b]);
+        assert_eq!(not_derived, vec![c, d]);
+
+        Ok(())
+    }
 }
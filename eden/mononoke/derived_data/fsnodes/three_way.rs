@@ -0,0 +1,244 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use anyhow::{format_err, Error};
+use blobrepo::BlobRepo;
+use blobstore::Loadable;
+use bounded_traversal::bounded_traversal_stream;
+use cloned::cloned;
+use context::CoreContext;
+use futures::{
+    compat::Future01CompatExt,
+    future::{self, try_join3},
+    Stream, TryStreamExt,
+};
+use manifest::{Entry, Manifest};
+use mononoke_types::fsnode::Fsnode;
+use mononoke_types::{ContentId, FileType, FsnodeId, MPath, MPathElement};
+
+type FsnodeManifestEntry = Entry<FsnodeId, (ContentId, FileType)>;
+
+/// How a path differs between the two sides of a three-way merge.
+#[derive(Clone, Debug, Eq, PartialEq, Ord, PartialOrd)]
+pub enum ThreeWayEntry {
+    /// Only the left side differs from the base.
+    ChangedOnLeft(MPath),
+    /// Only the right side differs from the base.
+    ChangedOnRight(MPath),
+    /// Both sides differ from the base, and from each other.
+    ChangedOnBoth(MPath),
+}
+
+/// A path being compared, with its entry in each of the three trees.
+struct ThreeWayStep {
+    path: Option<MPath>,
+    base: Option<FsnodeManifestEntry>,
+    left: Option<FsnodeManifestEntry>,
+    right: Option<FsnodeManifestEntry>,
+}
+
+/// Find the paths changed by `left` and `right` relative to their merge base
+/// `base`.
+///
+/// Only files are reported, except when a file is replaced by a directory (or
+/// the other way around), in which case the path itself is reported. Paths
+/// changed in the same way on both sides merge cleanly, so they are not
+/// reported. Subtrees are only traversed where the left and right fsnodes
+/// differ, and subtrees unchanged on one side are only loaded once.
+pub fn fsnode_three_way(
+    ctx: CoreContext,
+    repo: BlobRepo,
+    base: FsnodeId,
+    left: FsnodeId,
+    right: FsnodeId,
+) -> impl Stream<Item = Result<ThreeWayEntry, Error>> {
+    bounded_traversal_stream(
+        256,
+        Some(ThreeWayStep {
+            path: None,
+            base: Some(Entry::Tree(base)),
+            left: Some(Entry::Tree(left)),
+            right: Some(Entry::Tree(right)),
+        }),
+        move |step| {
+            cloned!(ctx, repo);
+            async move { three_way_unfold(&ctx, &repo, step).await }
+        },
+    )
+    .try_filter_map(future::ok)
+}
+
+async fn three_way_unfold(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    step: ThreeWayStep,
+) -> Result<(Option<ThreeWayEntry>, Vec<ThreeWayStep>), Error> {
+    let ThreeWayStep {
+        path,
+        base,
+        left,
+        right,
+    } = step;
+
+    if left == right {
+        return Ok((None, vec![]));
+    }
+
+    let is_file = |entry: &Option<FsnodeManifestEntry>| match entry {
+        Some(Entry::Leaf(_)) => true,
+        _ => false,
+    };
+    if is_file(&base) || is_file(&left) || is_file(&right) {
+        let path = path.ok_or_else(|| format_err!("unexpected file at the root"))?;
+        let entry = if left == base {
+            ThreeWayEntry::ChangedOnRight(path)
+        } else if right == base {
+            ThreeWayEntry::ChangedOnLeft(path)
+        } else {
+            ThreeWayEntry::ChangedOnBoth(path)
+        };
+        return Ok((Some(entry), vec![]));
+    }
+
+    // All entries are directories (or missing). Sides unchanged relative to
+    // the base share its listing.
+    let (base_dir, left_dir, right_dir) = try_join3(
+        load_dir(ctx, repo, &base),
+        load_dir(ctx, repo, if left == base { &None } else { &left }),
+        load_dir(ctx, repo, if right == base { &None } else { &right }),
+    )
+    .await?;
+    let base_children = list_dir(base_dir);
+    let left_children = if left == base {
+        base_children.clone()
+    } else {
+        list_dir(left_dir)
+    };
+    let right_children = if right == base {
+        base_children.clone()
+    } else {
+        list_dir(right_dir)
+    };
+
+    let names: BTreeSet<&MPathElement> = base_children
+        .keys()
+        .chain(left_children.keys())
+        .chain(right_children.keys())
+        .collect();
+    let steps = names
+        .into_iter()
+        .map(|name| ThreeWayStep {
+            path: Some(MPath::join_opt_element(path.as_ref(), name)),
+            base: base_children.get(name).cloned(),
+            left: left_children.get(name).cloned(),
+            right: right_children.get(name).cloned(),
+        })
+        .collect();
+    Ok((None, steps))
+}
+
+async fn load_dir(
+    ctx: &CoreContext,
+    repo: &BlobRepo,
+    entry: &Option<FsnodeManifestEntry>,
+) -> Result<Option<Fsnode>, Error> {
+    match entry {
+        Some(Entry::Tree(fsnode_id)) => Ok(Some(
+            fsnode_id
+                .load(ctx.clone(), repo.blobstore())
+                .compat()
+                .await?,
+        )),
+        _ => Ok(None),
+    }
+}
+
+fn list_dir(dir: Option<Fsnode>) -> BTreeMap<MPathElement, FsnodeManifestEntry> {
+    match dir {
+        Some(dir) => Manifest::list(&dir).collect(),
+        None => BTreeMap::new(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::RootFsnodeId;
+    use derived_data::BonsaiDerived;
+    use fbinit::FacebookInit;
+    use mononoke_types::ChangesetId;
+    use tests_utils::CreateCommitContext;
+
+    async fn root_fsnode(
+        ctx: &CoreContext,
+        repo: &BlobRepo,
+        cs_id: ChangesetId,
+    ) -> Result<FsnodeId, Error> {
+        let root = RootFsnodeId::derive(ctx.clone(), repo.clone(), cs_id)
+            .compat()
+            .await?;
+        Ok(root.into_fsnode_id())
+    }
+
+    #[fbinit::compat_test]
+    async fn test_fsnode_three_way(fb: FacebookInit) -> Result<(), Error> {
+        let ctx = CoreContext::test_mock(fb);
+        let repo = blobrepo_factory::new_memblob_empty(None)?;
+
+        let base = CreateCommitContext::new_root(&ctx, &repo)
+            .add_file("a", "1")
+            .add_file("b", "1")
+            .add_file("c", "1")
+            .add_file("same", "1")
+            .add_file("dir/d", "1")
+            .add_file("dir/e", "1")
+            .add_file("untouched/f", "1")
+            .commit()
+            .await?;
+        let left = CreateCommitContext::new(&ctx, &repo, vec![base])
+            .add_file("a", "2")
+            .delete_file("c")
+            .add_file("same", "2")
+            .add_file("dir/d", "2")
+            .commit()
+            .await?;
+        let right = CreateCommitContext::new(&ctx, &repo, vec![base])
+            .add_file("b", "2")
+            .add_file("same", "2")
+            .add_file("dir/d", "3")
+            .add_file("new/g", "1")
+            .commit()
+            .await?;
+
+        let mut entries: Vec<_> = fsnode_three_way(
+            ctx.clone(),
+            repo.clone(),
+            root_fsnode(&ctx, &repo, base).await?,
+            root_fsnode(&ctx, &repo, left).await?,
+            root_fsnode(&ctx, &repo, right).await?,
+        )
+        .try_collect()
+        .await?;
+        entries.sort();
+
+        let path = |p: &str| MPath::new(p).unwrap();
+        assert_eq!(
+            entries,
+            vec![
+                ThreeWayEntry::ChangedOnLeft(path("a")),
+                ThreeWayEntry::ChangedOnLeft(path("c")),
+                ThreeWayEntry::ChangedOnRight(path("b")),
+                ThreeWayEntry::ChangedOnRight(path("new/g")),
+                ThreeWayEntry::ChangedOnBoth(path("dir/d")),
+            ]
+        );
+
+        Ok(())
+    }
+}
@@ -23,14 +23,17 @@ pub enum ErrorKind {
     #[error("invalid config options: {0}")]
     InvalidConfig(String),
     /// Duplicated repo ids
-    #[error("repoid {0} used more than once")]
-    DuplicatedRepoId(RepositoryId),
+    #[error("repoid {0} used by both {1} and {2}")]
+    DuplicatedRepoId(RepositoryId, String, String),
     /// Missing path for hook
     #[error("missing path")]
     MissingPath(),
     /// Invalid pushvar
     #[error("invalid pushvar, should be KEY=VALUE: {0}")]
     InvalidPushvar(String),
+    /// A `${VAR}` in the config names an unset environment variable
+    #[error("environment variable {0} used in config is not set")]
+    MissingEnvVar(String),
     /// Too many bypass options for a hook
     #[error("Only one bypass option is allowed. Hook: {0}")]
     TooManyBypassOptions(String),
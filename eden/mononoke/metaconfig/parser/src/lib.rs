@@ -14,4 +14,4 @@ pub mod errors;
 pub mod repoconfig;
 
 pub use crate::errors::ErrorKind;
-pub use crate::repoconfig::RepoConfigs;
+pub use crate::repoconfig::{ReadOptions, RepoConfigs};
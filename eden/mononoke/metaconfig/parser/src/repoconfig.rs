@@ -9,12 +9,13 @@
 //! deserialized from TOML files from metaconfig repo
 
 use std::{
-    collections::{BTreeSet, HashMap, HashSet},
+    collections::{BTreeSet, HashMap},
     convert::{TryFrom, TryInto},
-    fs,
-    path::Path,
+    env, fs, io,
+    path::{Path, PathBuf},
     str,
     str::FromStr,
+    thread,
     time::Duration,
 };
 
@@ -29,18 +30,18 @@ use maplit::hashmap;
 use metaconfig_types::{
     BookmarkOrRegex, BookmarkParams, Bundle2ReplayParams, CacheWarmupParams, CommitSyncConfig,
     CommitSyncDirection, CommonConfig, DefaultSmallToLargeCommitSyncPathAction, DerivedDataConfig,
-    HgsqlGlobalrevsName, HgsqlName, HookBypass, HookConfig, HookManagerParams, HookParams,
-    InfinitepushNamespace, InfinitepushParams, LfsParams, PushParams, PushrebaseFlags,
-    PushrebaseParams, Redaction, RepoConfig, RepoReadOnly, SmallRepoCommitSyncConfig,
-    SourceControlServiceParams, StorageConfig, UnodeVersion, WhitelistEntry,
-    WireprotoLoggingConfig,
+    HashValidationMode, HgsqlGlobalrevsName, HgsqlName, HookBypass, HookConfig, HookManagerParams,
+    HookParams, IdentityRewriteRule, InfinitepushNamespace, InfinitepushParams, LfsParams,
+    PushParams, PushPolicy, PushrebaseFlags, PushrebaseParams, Redaction, RepoConfig, RepoReadOnly,
+    SmallRepoCommitSyncConfig, SourceControlServiceParams, StorageConfig, UnodeVersion,
+    WhitelistEntry, WireprotoLoggingConfig,
 };
-use mononoke_types::{MPath, RepositoryId};
+use mononoke_types::{DateTime, MPath, RepositoryId};
 use regex::Regex;
 use repos::{
     RawCommitSyncConfig, RawCommitSyncSmallRepoConfig, RawCommonConfig, RawHookConfig,
-    RawInfinitepushParams, RawRepoConfig, RawRepoConfigs, RawStorageConfig, RawUnodeVersion,
-    RawWireprotoLoggingConfig,
+    RawIdentityRewriteRule, RawInfinitepushParams, RawPushPolicy, RawReadOnlyWindow, RawRepoConfig,
+    RawRepoConfigs, RawStorageConfig, RawUnodeVersion, RawWireprotoLoggingConfig,
 };
 
 const CONFIGERATOR_CRYPTO_PROJECT: &'static str = "SCM";
@@ -48,6 +49,8 @@ const CONFIGERATOR_PREFIX: &'static str = "configerator://";
 const LIST_KEYS_PATTERNS_MAX_DEFAULT: u64 = 500_000;
 const HOOK_MAX_FILE_SIZE_DEFAULT: u64 = 8 * 1024 * 1024; // 8MiB
 const DEFAULT_ARG_SIZE_THRESHOLD: u64 = 500_000;
+const MAX_BLOBSTORE_KEY_PREFIX_LEN: usize = 64;
+const MAX_PUSHREBASE_RECURSION_LIMIT: usize = 100_000;
 
 /// Holds configuration all configuration that was read from metaconfig repository's manifest.
 #[derive(Debug, PartialEq)]
@@ -58,20 +61,70 @@ pub struct RepoConfigs {
     pub common: CommonConfig,
 }
 
+/// Controls how config files are read from disk.
+#[derive(Clone, Debug, Default)]
+pub struct ReadOptions {
+    /// How many times to retry a failed read of a config file or directory.
+    pub retries: usize,
+    /// How long to wait before the first retry. The wait doubles on each
+    /// following retry.
+    pub backoff: Duration,
+}
+
+impl ReadOptions {
+    /// Run `read`, retrying it if it fails.
+    fn retry<T>(&self, mut read: impl FnMut() -> io::Result<T>) -> io::Result<T> {
+        let mut backoff = self.backoff;
+        let mut attempt = 0;
+        loop {
+            match read() {
+                Ok(value) => return Ok(value),
+                Err(_) if attempt < self.retries => {
+                    attempt += 1;
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Look up the metadata of `path`, or `None` if it does not exist. Other
+    /// failures are retried, so a flaky filesystem isn't mistaken for a
+    /// missing or mistyped path.
+    fn metadata(&self, path: &Path) -> io::Result<Option<fs::Metadata>> {
+        self.retry(|| match fs::metadata(path) {
+            Ok(metadata) => Ok(Some(metadata)),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e),
+        })
+    }
+}
+
 impl RepoConfigs {
     /// Read repo configs
     pub fn read_configs(fb: FacebookInit, config_path: impl AsRef<Path>) -> Result<Self> {
+        Self::read_configs_with_options(fb, config_path, &ReadOptions::default())
+    }
+
+    /// Read repo configs, retrying reads from disk as configured by `options`.
+    /// This is useful when the configs live on a network filesystem.
+    pub fn read_configs_with_options(
+        fb: FacebookInit,
+        config_path: impl AsRef<Path>,
+        options: &ReadOptions,
+    ) -> Result<Self> {
         let config_path = config_path.as_ref();
 
         let RawRepoConfigs {
             commit_sync,
-            common: _,
+            common,
             repos,
             storage,
-        } = Self::read_raw_configs(fb, config_path)?;
+        } = Self::read_raw_configs(fb, config_path, options)?;
         let commit_sync = Self::parse_commit_sync_config(commit_sync)?;
         let mut repo_configs = HashMap::new();
-        let mut repoids = HashSet::new();
+        let mut repoids = HashMap::new();
 
         for (reponame, raw_repo_config) in &repos {
             let config = RepoConfigs::process_single_repo_config(
@@ -81,14 +134,16 @@ impl RepoConfigs {
                 &commit_sync,
             )?;
 
-            if !repoids.insert(config.repoid) {
-                return Err(ErrorKind::DuplicatedRepoId(config.repoid).into());
+            if let Some(other) = repoids.insert(config.repoid, reponame.clone()) {
+                return Err(
+                    ErrorKind::DuplicatedRepoId(config.repoid, other, reponame.clone()).into(),
+                );
             }
 
             repo_configs.insert(reponame.clone(), config);
         }
 
-        let common = Self::read_common_config(fb, &config_path)?;
+        let common = Self::parse_common_config(common)?;
         Ok(Self {
             repos: repo_configs,
             common,
@@ -101,7 +156,11 @@ impl RepoConfigs {
         config_path: impl AsRef<Path>,
     ) -> Result<CommonConfig> {
         let config_path = config_path.as_ref();
-        let raw_config = Self::read_raw_configs(fb, config_path)?.common;
+        let raw_config = Self::read_raw_configs(fb, config_path, &ReadOptions::default())?.common;
+        Self::parse_common_config(raw_config)
+    }
+
+    fn parse_common_config(raw_config: RawCommonConfig) -> Result<CommonConfig> {
         let mut tiers_num = 0;
         let whitelisted_entries: Result<Vec<_>> = raw_config
             .whitelist_entry
@@ -364,7 +423,7 @@ impl RepoConfigs {
     ) -> Result<HashMap<String, StorageConfig>> {
         let config_root_path = config_root_path.as_ref();
 
-        Self::read_raw_configs(fb, config_root_path)?
+        Self::read_raw_configs(fb, config_root_path, &ReadOptions::default())?
             .storage
             .into_iter()
             .map(|(k, v)| {
@@ -526,18 +585,22 @@ impl RepoConfigs {
             None => Default::default(),
         };
 
-        let cache_warmup = match this.cache_warmup {
-            Some(raw) => Some(CacheWarmupParams {
-                bookmark: BookmarkName::new(raw.bookmark)?,
-                commit_limit: raw
-                    .commit_limit
-                    .map(|v| v.try_into())
-                    .transpose()?
-                    .unwrap_or(200000),
-                microwave_preload: raw.microwave_preload.unwrap_or(false),
-            }),
-            None => None,
-        };
+        let cache_warmup = this
+            .cache_warmup
+            .into_iter()
+            .chain(this.cache_warmups.into_iter().flatten())
+            .map(|raw| -> Result<_> {
+                Ok(CacheWarmupParams {
+                    bookmark: BookmarkName::new(raw.bookmark)?,
+                    commit_limit: raw
+                        .commit_limit
+                        .map(|v| v.try_into())
+                        .transpose()?
+                        .unwrap_or(200000),
+                    microwave_preload: raw.microwave_preload.unwrap_or(false),
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
 
         let hook_manager_params = this.hook_manager_params.map(|params| HookManagerParams {
             disable_acl_checker: params.disable_acl_checker,
@@ -607,14 +670,22 @@ impl RepoConfigs {
             .pushrebase
             .map(|raw| -> Result<_, Error> {
                 let default = PushrebaseParams::default();
+                let recursion_limit: Option<usize> =
+                    raw.recursion_limit.map(|v| v.try_into()).transpose()?;
+                match recursion_limit {
+                    Some(limit) if limit > MAX_PUSHREBASE_RECURSION_LIMIT => {
+                        return Err(ErrorKind::InvalidConfig(format!(
+                            "pushrebase recursion_limit must be at most {}, got {}",
+                            MAX_PUSHREBASE_RECURSION_LIMIT, limit
+                        ))
+                        .into());
+                    }
+                    _ => {}
+                }
                 Ok(PushrebaseParams {
                     flags: PushrebaseFlags {
                         rewritedates: raw.rewritedates.unwrap_or(default.flags.rewritedates),
-                        recursion_limit: raw
-                            .recursion_limit
-                            .map(|v| v.try_into())
-                            .transpose()?
-                            .or(default.flags.recursion_limit),
+                        recursion_limit: recursion_limit.or(default.flags.recursion_limit),
                         forbid_p2_root_rebases: raw
                             .forbid_p2_root_rebases
                             .unwrap_or(default.flags.forbid_p2_root_rebases),
@@ -645,7 +716,16 @@ impl RepoConfigs {
         let lfs = match this.lfs {
             Some(lfs_params) => LfsParams {
                 threshold: lfs_params.threshold.map(|v| v.try_into()).transpose()?,
-                rollout_percentage: lfs_params.rollout_percentage.unwrap_or(0).try_into()?,
+                rollout_percentage: match lfs_params.rollout_percentage.unwrap_or(0) {
+                    percentage @ 0..=100 => percentage.try_into()?,
+                    percentage => {
+                        return Err(ErrorKind::InvalidConfig(format!(
+                            "lfs rollout_percentage must be between 0 and 100, got {}",
+                            percentage
+                        ))
+                        .into());
+                    }
+                },
                 generate_lfs_blob_in_hg_sync_job: lfs_params
                     .generate_lfs_blob_in_hg_sync_job
                     .unwrap_or(false),
@@ -660,10 +740,48 @@ impl RepoConfigs {
             .transpose()?
             .unwrap_or(0);
 
-        let readonly = if this.readonly.unwrap_or_default() {
-            RepoReadOnly::ReadOnly("Set by config option".to_string())
+        let hash_validation_mode = this
+            .hash_validation_mode
+            .map(|mode| {
+                mode.parse::<HashValidationMode>().map_err(|_| {
+                    ErrorKind::InvalidConfig(format!("invalid hash_validation_mode {:?}", mode))
+                })
+            })
+            .transpose()?;
+        let hash_validation_mode = if hash_validation_percentage > 0 {
+            Some(hash_validation_mode.unwrap_or(HashValidationMode::Both))
         } else {
-            RepoReadOnly::ReadWrite
+            hash_validation_mode
+        };
+
+        let readonly = match (this.readonly.unwrap_or_default(), this.readonly_window) {
+            (true, Some(_)) => {
+                return Err(ErrorKind::InvalidConfig(
+                    "readonly and readonly_window can't both be set".to_string(),
+                )
+                .into());
+            }
+            (true, None) => RepoReadOnly::ReadOnly("Set by config option".to_string()),
+            (false, Some(RawReadOnlyWindow { start, end })) => {
+                let parse = |time: &str| {
+                    DateTime::from_rfc3339(time).map_err(|_| {
+                        ErrorKind::InvalidConfig(format!(
+                            "readonly_window time {:?} is not an RFC3339 timestamp",
+                            time
+                        ))
+                    })
+                };
+                let (start, end) = (parse(&start)?, parse(&end)?);
+                if start >= end {
+                    return Err(ErrorKind::InvalidConfig(format!(
+                        "readonly_window must start before it ends, got {} to {}",
+                        start, end
+                    ))
+                    .into());
+                }
+                RepoReadOnly::ReadOnlyDuring { start, end }
+            }
+            (false, None) => RepoReadOnly::ReadWrite,
         };
 
         let redaction = if this.redaction.unwrap_or(true) {
@@ -683,10 +801,19 @@ impl RepoConfigs {
                  }| {
                     let namespace = match namespace_pattern {
                         Some(ns) => {
-                            let regex = Regex::new(&ns);
-                            match regex {
+                            if ns.is_empty() {
+                                return Err(ErrorKind::InvalidConfig(
+                                    "infinitepush namespace_pattern must not be empty".to_string(),
+                                ));
+                            }
+                            match Regex::new(&ns) {
                                 Ok(regex) => Some(InfinitepushNamespace::new(regex)),
-                                Err(_) => None,
+                                Err(e) => {
+                                    return Err(ErrorKind::InvalidConfig(format!(
+                                        "invalid infinitepush namespace_pattern {:?}: {}",
+                                        ns, e
+                                    )));
+                                }
                             }
                         }
                         None => None,
@@ -696,14 +823,15 @@ impl RepoConfigs {
                     // Same for queue population
                     let populate_reverse_filler_queue =
                         populate_reverse_filler_queue.unwrap_or(false);
-                    InfinitepushParams {
+                    Ok(InfinitepushParams {
                         allow_writes,
                         namespace,
                         hydrate_getbundle_response,
                         populate_reverse_filler_queue,
-                    }
+                    })
                 },
             )
+            .transpose()?
             .unwrap_or(InfinitepushParams::default());
 
         let generation_cache_size: usize = this
@@ -782,6 +910,7 @@ impl RepoConfigs {
                         .derived_data_types
                         .unwrap_or(BTreeSet::new()),
                     unode_version,
+                    fsnode_compression_level: raw_derived_data_config.fsnode_compression_level,
                 })
             })
             .transpose()?
@@ -794,6 +923,156 @@ impl RepoConfigs {
                 .unwrap_or_else(|| hgsql_name.0.clone()),
         );
 
+        let derived_data_batch_size = this
+            .derived_data_batch_size
+            .map(|size| -> Result<usize> {
+                if size <= 0 {
+                    return Err(ErrorKind::InvalidConfig(format!(
+                        "derived_data_batch_size must be positive, got {}",
+                        size
+                    ))
+                    .into());
+                }
+                Ok(size.try_into()?)
+            })
+            .transpose()?;
+
+        let scuba_sample_rate = this
+            .scuba_sample_rate
+            .map(|rate| -> Result<f64> {
+                if !(0.0..=1.0).contains(&rate) {
+                    return Err(ErrorKind::InvalidConfig(format!(
+                        "scuba_sample_rate must be between 0.0 and 1.0, got {}",
+                        rate
+                    ))
+                    .into());
+                }
+                Ok(rate)
+            })
+            .transpose()?;
+
+        let skiplist_rebuild_interval = this
+            .skiplist_rebuild_interval
+            .map(|interval| -> Result<Duration> {
+                let duration = humantime::parse_duration(&interval).map_err(|e| {
+                    ErrorKind::InvalidConfig(format!(
+                        "invalid skiplist_rebuild_interval {:?}: {}",
+                        interval, e
+                    ))
+                })?;
+                if duration == Duration::from_secs(0) {
+                    return Err(ErrorKind::InvalidConfig(format!(
+                        "skiplist_rebuild_interval must be positive, got {:?}",
+                        interval
+                    ))
+                    .into());
+                }
+                Ok(duration)
+            })
+            .transpose()?;
+
+        let push_policy = this
+            .push_policy
+            .map(
+                |RawPushPolicy {
+                     denied_extensions,
+                     denied_path_globs,
+                     max_file_size_bytes,
+                 }|
+                 -> Result<PushPolicy> {
+                    let denied_extensions = denied_extensions.unwrap_or_default();
+                    for extension in &denied_extensions {
+                        if extension.is_empty()
+                            || extension.starts_with('.')
+                            || extension.contains('/')
+                        {
+                            return Err(ErrorKind::InvalidConfig(format!(
+                                "invalid push_policy denied extension {:?}",
+                                extension
+                            ))
+                            .into());
+                        }
+                    }
+                    let denied_path_globs = denied_path_globs.unwrap_or_default();
+                    for glob in &denied_path_globs {
+                        globset::Glob::new(glob).map_err(|e| {
+                            ErrorKind::InvalidConfig(format!(
+                                "invalid push_policy denied path glob {:?}: {}",
+                                glob, e
+                            ))
+                        })?;
+                    }
+                    let max_file_size_bytes = max_file_size_bytes
+                        .map(|size| {
+                            size.try_into().map_err(|_| {
+                                ErrorKind::InvalidConfig(format!(
+                                    "push_policy max_file_size_bytes must not be negative, got {}",
+                                    size
+                                ))
+                            })
+                        })
+                        .transpose()?;
+                    Ok(PushPolicy {
+                        denied_extensions,
+                        denied_path_globs,
+                        max_file_size_bytes,
+                    })
+                },
+            )
+            .transpose()?
+            .unwrap_or_default();
+
+        let blobstore_key_prefix = this
+            .blobstore_key_prefix
+            .map(|prefix| {
+                if prefix.is_empty()
+                    || prefix.len() > MAX_BLOBSTORE_KEY_PREFIX_LEN
+                    || prefix.chars().any(char::is_whitespace)
+                {
+                    return Err(ErrorKind::InvalidConfig(format!(
+                        "blobstore_key_prefix must be 1 to {} characters without whitespace, got {:?}",
+                        MAX_BLOBSTORE_KEY_PREFIX_LEN, prefix
+                    )));
+                }
+                Ok(prefix)
+            })
+            .transpose()?;
+
+        let identity_rewrite = this
+            .identity_rewrite
+            .unwrap_or_default()
+            .into_iter()
+            .map(
+                |RawIdentityRewriteRule {
+                     match_email,
+                     replace_email,
+                     replace_name,
+                 }|
+                 -> Result<IdentityRewriteRule> {
+                    if replace_email.is_none() && replace_name.is_none() {
+                        return Err(ErrorKind::InvalidConfig(format!(
+                            "identity_rewrite for {:?} replaces neither email nor name",
+                            match_email
+                        ))
+                        .into());
+                    }
+                    // The regex must match the whole email
+                    let match_email =
+                        Regex::new(&format!("^(?:{})$", match_email)).map_err(|e| {
+                            ErrorKind::InvalidConfig(format!(
+                                "invalid identity_rewrite match_email {:?}: {}",
+                                match_email, e
+                            ))
+                        })?;
+                    Ok(IdentityRewriteRule {
+                        match_email,
+                        replace_email,
+                        replace_name,
+                    })
+                },
+            )
+            .collect::<Result<Vec<_>>>()?;
+
         Ok(RepoConfig {
             enabled,
             storage_config,
@@ -829,6 +1108,13 @@ impl RepoConfigs {
             derived_data_config,
             hgsql_name,
             hgsql_globalrevs_name,
+            derived_data_batch_size,
+            scuba_sample_rate,
+            skiplist_rebuild_interval,
+            hash_validation_mode,
+            push_policy,
+            blobstore_key_prefix,
+            identity_rewrite,
         })
     }
 
@@ -842,7 +1128,11 @@ impl RepoConfigs {
             .find(|(_, repo_config)| repo_config.repoid == repo_id)
     }
 
-    fn read_raw_configs(fb: FacebookInit, config_path: &Path) -> Result<RawRepoConfigs> {
+    fn read_raw_configs(
+        fb: FacebookInit,
+        config_path: &Path,
+        options: &ReadOptions,
+    ) -> Result<RawRepoConfigs> {
         if config_path.starts_with(CONFIGERATOR_PREFIX) {
             let cfg_path = config_path
                 .strip_prefix(CONFIGERATOR_PREFIX)?
@@ -860,48 +1150,69 @@ impl RepoConfigs {
             .get_config_handle::<RawRepoConfigs>(cfg_path)?
             .get();
             Ok((*arc_conf).clone())
-        } else if config_path.is_dir() {
-            Self::read_raw_configs_toml(config_path)
-        } else if config_path.is_file() {
-            let repo_configs = fs::read(config_path)?;
-            Ok(serde_json::from_slice(&repo_configs)?)
         } else {
-            Err(ErrorKind::InvalidFileStructure(format!(
-                "{} does not exist",
-                config_path.display()
-            ))
-            .into())
+            match options.metadata(config_path)? {
+                Some(metadata) if metadata.is_dir() => {
+                    Self::read_raw_configs_toml(config_path, options)
+                }
+                Some(metadata) if metadata.is_file() => {
+                    let repo_configs = options.retry(|| fs::read(config_path))?;
+                    Ok(serde_json::from_slice(&repo_configs)?)
+                }
+                _ => Err(ErrorKind::InvalidFileStructure(format!(
+                    "{} does not exist",
+                    config_path.display()
+                ))
+                .into()),
+            }
         }
     }
 
-    fn read_raw_configs_toml(config_path: &Path) -> Result<RawRepoConfigs> {
+    fn read_raw_configs_toml(config_path: &Path, options: &ReadOptions) -> Result<RawRepoConfigs> {
         let commit_sync = Self::read_toml_path::<HashMap<String, RawCommitSyncConfig>>(
             config_path
                 .join("common")
                 .join("commitsyncmap.toml")
                 .as_path(),
             false,
+            options,
         )?;
         let common = Self::read_toml_path::<RawCommonConfig>(
             config_path.join("common").join("common.toml").as_path(),
             true,
+            options,
         )?;
         let storage = Self::read_toml_path::<HashMap<String, RawStorageConfig>>(
             config_path.join("common").join("storage.toml").as_path(),
             true,
+            options,
         )?;
 
         let mut repos = HashMap::new();
         let repos_dir = config_path.join("repos");
-        if !repos_dir.is_dir() {
+        if !options
+            .metadata(&repos_dir)?
+            .map_or(false, |metadata| metadata.is_dir())
+        {
             return Err(ErrorKind::InvalidFileStructure(format!(
                 "expected 'repos' directory under {}",
                 config_path.display()
             ))
             .into());
         }
-        for entry in repos_dir.read_dir()? {
-            let repo_config_path = entry?.path();
+        // Optional defaults shared by all repos, overridden by each server.toml
+        let repo_defaults_path = repos_dir.join("common.toml");
+        let repo_defaults = if options.metadata(&repo_defaults_path)?.is_some() {
+            Some(Self::read_toml_value_path(&repo_defaults_path, options)?)
+        } else {
+            None
+        };
+        let entries = options.retry(|| repos_dir.read_dir()?.collect::<io::Result<Vec<_>>>())?;
+        for entry in entries {
+            let repo_config_path = entry.path();
+            if repo_config_path == repo_defaults_path {
+                continue;
+            }
             let reponame = repo_config_path
                 .file_name()
                 .and_then(|s| s.to_str())
@@ -913,11 +1224,18 @@ impl RepoConfigs {
                 })?
                 .to_string();
 
-            let repo_config = Self::read_toml_path::<RawRepoConfig>(
+            let mut repo_config = Self::read_toml_with_includes(
                 repo_config_path.join("server.toml").as_path(),
-                false,
+                &mut Vec::new(),
+                options,
             )?;
-            repos.insert(reponame, repo_config);
+            if let Some(repo_defaults) = &repo_defaults {
+                merge_toml_defaults(&mut repo_config, repo_defaults);
+            }
+            repos.insert(
+                reponame,
+                Self::from_toml_value::<RawRepoConfig>(repo_config)?,
+            );
         }
 
         Ok(RawRepoConfigs {
@@ -928,49 +1246,208 @@ impl RepoConfigs {
         })
     }
 
-    fn read_toml_path<T>(path: &Path, defaults: bool) -> Result<T, Error>
+    fn read_toml_path<T>(path: &Path, defaults: bool, options: &ReadOptions) -> Result<T, Error>
     where
         T: serde::de::DeserializeOwned + Default,
     {
-        if !path.is_file() {
-            if defaults && !path.exists() {
-                return Ok(Default::default());
+        if defaults && options.metadata(path)?.is_none() {
+            return Ok(Default::default());
+        }
+        Self::from_toml_value(Self::read_toml_value_path(path, options)?)
+    }
+
+    /// Read a repo config, merging in the `hooks` and `bookmarks` of the files
+    /// listed in its top-level `include`, ahead of its own. Relative include
+    /// paths are resolved against the directory of the including file, and
+    /// included files may include others. `include_stack` holds the files
+    /// currently being read, to reject cycles.
+    fn read_toml_with_includes(
+        path: &Path,
+        include_stack: &mut Vec<PathBuf>,
+        options: &ReadOptions,
+    ) -> Result<toml::Value, Error> {
+        let mut config = Self::read_toml_value_path(path, options)?;
+        let canonical_path = fs::canonicalize(path)?;
+        if include_stack.contains(&canonical_path) {
+            return Err(
+                ErrorKind::InvalidConfig(format!("cyclic include of {}", path.display())).into(),
+            );
+        }
+
+        let table = match config.as_table_mut() {
+            Some(table) => table,
+            None => return Ok(config),
+        };
+        let includes = match table.remove("include") {
+            Some(toml::Value::Array(includes)) => includes,
+            Some(_) => {
+                return Err(ErrorKind::InvalidConfig(format!(
+                    "include in {} must be a list of paths",
+                    path.display()
+                ))
+                .into());
+            }
+            None => return Ok(config),
+        };
+
+        include_stack.push(canonical_path);
+        let dir = path.parent().unwrap_or_else(|| Path::new(""));
+        let mut hooks = Vec::new();
+        let mut bookmarks = Vec::new();
+        for include in includes {
+            let include_path = match include {
+                toml::Value::String(include) => dir.join(include),
+                _ => {
+                    return Err(ErrorKind::InvalidConfig(format!(
+                        "include in {} must be a list of paths",
+                        path.display()
+                    ))
+                    .into());
+                }
+            };
+            let included = Self::read_toml_with_includes(&include_path, include_stack, options)?;
+            for (key, value) in included.as_table().into_iter().flatten() {
+                match (key.as_str(), value) {
+                    ("hooks", toml::Value::Array(values)) => hooks.extend(values.iter().cloned()),
+                    ("bookmarks", toml::Value::Array(values)) => {
+                        bookmarks.extend(values.iter().cloned())
+                    }
+                    _ => {
+                        return Err(ErrorKind::InvalidConfig(format!(
+                            "included file {} can only define hooks and bookmarks, not {}",
+                            include_path.display(),
+                            key
+                        ))
+                        .into());
+                    }
+                }
+            }
+        }
+        include_stack.pop();
+
+        for (key, mut included) in vec![("hooks", hooks), ("bookmarks", bookmarks)] {
+            match table.remove(key) {
+                Some(toml::Value::Array(own)) => included.extend(own),
+                Some(own) => {
+                    table.insert(key.to_string(), own);
+                    continue;
+                }
+                None => {}
             }
+            if !included.is_empty() {
+                table.insert(key.to_string(), toml::Value::Array(included));
+            }
+        }
+        Ok(config)
+    }
 
+    fn read_toml_value_path(path: &Path, options: &ReadOptions) -> Result<toml::Value, Error> {
+        if !options
+            .metadata(path)?
+            .map_or(false, |metadata| metadata.is_file())
+        {
             return Err(ErrorKind::InvalidFileStructure(format!(
                 "{} should be a file",
                 path.display()
             ))
             .into());
         }
-        let content = fs::read(path)?;
-        let res = Self::read_toml::<T>(&content);
-        res
+        let content = options.retry(|| fs::read(path))?;
+        Self::read_toml_value(&content)
+    }
+
+    /// Parse a toml file. `${VAR}` in string values is replaced by the value of
+    /// the environment variable `VAR`, and `$${VAR}` by a literal `${VAR}`.
+    fn read_toml_value(bytes: &[u8]) -> Result<toml::Value, Error> {
+        match str::from_utf8(bytes) {
+            Ok(s) => {
+                let mut value: toml::Value = toml::from_str(s)?;
+                expand_env_vars(&mut value)?;
+                Ok(value)
+            }
+            Err(e) => Err(anyhow!("error parsing toml: {}", e)),
+        }
     }
 
-    /// Helper to read toml files which throws an error upon encountering
-    /// unknown keys
-    fn read_toml<T>(bytes: &[u8]) -> Result<T, Error>
+    /// Helper to deserialize parsed toml which throws an error upon
+    /// encountering unknown keys
+    fn from_toml_value<T>(value: toml::Value) -> Result<T, Error>
     where
         T: serde::de::DeserializeOwned,
     {
-        match str::from_utf8(bytes) {
-            Ok(s) => {
-                let mut unused = BTreeSet::new();
-                let de = &mut toml::de::Deserializer::new(s);
-                let t: T = serde_ignored::deserialize(de, |path| {
-                    unused.insert(path.to_string());
-                })?;
+        let mut unused = BTreeSet::new();
+        let t: T = serde_ignored::deserialize(value, |path| {
+            unused.insert(path.to_string());
+        })?;
 
-                if unused.len() > 0 {
-                    Err(anyhow!("unknown keys in config parsing: `{:?}`", unused))?;
+        if unused.len() > 0 {
+            Err(anyhow!("unknown keys in config parsing: `{:?}`", unused))?;
+        }
+
+        Ok(t)
+    }
+}
+
+/// Fill in keys missing from `config` with their values in `defaults`. Tables
+/// present in both are merged the same way, otherwise the value in `config`
+/// wins.
+fn merge_toml_defaults(config: &mut toml::Value, defaults: &toml::Value) {
+    if let (toml::Value::Table(config), toml::Value::Table(defaults)) = (config, defaults) {
+        for (key, default) in defaults {
+            match config.get_mut(key) {
+                Some(value) => merge_toml_defaults(value, default),
+                None => {
+                    config.insert(key.clone(), default.clone());
                 }
+            }
+        }
+    }
+}
 
-                Ok(t)
+/// Replace `${VAR}` in all strings within `value` with the value of the
+/// environment variable `VAR`. `$${` escapes a literal `${`, for strings such
+/// as regexes that need one. Keys are left alone.
+fn expand_env_vars(value: &mut toml::Value) -> Result<()> {
+    match value {
+        toml::Value::String(s) => *s = expand_env_vars_in_str(s)?,
+        toml::Value::Array(values) => {
+            for value in values {
+                expand_env_vars(value)?;
             }
-            Err(e) => Err(anyhow!("error parsing toml: {}", e)),
         }
+        toml::Value::Table(table) => {
+            for (_, value) in table.iter_mut() {
+                expand_env_vars(value)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+fn expand_env_vars_in_str(s: &str) -> Result<String> {
+    let mut expanded = String::new();
+    let mut rest = s;
+    while let Some(start) = rest.find("${") {
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+        let end = match rest[start..].find('}') {
+            Some(end) => start + end,
+            // Not a variable reference, e.g. part of a regex
+            None => break,
+        };
+        let name = &rest[start + 2..end];
+        let var = env::var(name).map_err(|_| ErrorKind::MissingEnvVar(name.to_string()))?;
+        expanded.push_str(&rest[..start]);
+        expanded.push_str(&var);
+        rest = &rest[end + 1..];
     }
+    expanded.push_str(rest);
+    Ok(expanded)
 }
 
 #[cfg(test)]
@@ -979,11 +1456,13 @@ mod test {
     use maplit::{btreemap, btreeset, hashmap};
     use metaconfig_types::{
         BlobConfig, BlobstoreId, DatabaseConfig, FilestoreParams, LocalDatabaseConfig,
-        MetadataDatabaseConfig, MultiplexId, RemoteDatabaseConfig, RemoteMetadataDatabaseConfig,
-        ShardableRemoteDatabaseConfig, ShardedRemoteDatabaseConfig, SourceControlServiceMonitoring,
+        MetadataDatabaseConfig, MultiplexId, MultiplexedBlobstoreConfig, RemoteDatabaseConfig,
+        RemoteMetadataDatabaseConfig, ShardableRemoteDatabaseConfig, ShardedRemoteDatabaseConfig,
+        SourceControlServiceMonitoring,
     };
     use nonzero_ext::nonzero;
     use pretty_assertions::assert_eq;
+    use std::cell::Cell;
     use std::fs::{create_dir_all, write};
     use std::num::NonZeroUsize;
     use tempdir::TempDir;
@@ -1041,8 +1520,8 @@ mod test {
             "common/commitsyncmap.toml" => commit_sync_config
         };
         let tmp_dir = write_files(&paths);
-        let raw_config =
-            RepoConfigs::read_raw_configs(fb, tmp_dir.path()).expect("expect to read configs");
+        let raw_config = RepoConfigs::read_raw_configs(fb, tmp_dir.path(), &ReadOptions::default())
+            .expect("expect to read configs");
         let commit_sync = RepoConfigs::parse_commit_sync_config(raw_config.commit_sync)
             .expect("expected to get a commit sync config");
 
@@ -1351,6 +1830,8 @@ mod test {
         println!("res = {}", msg);
         assert!(res.is_err());
         assert!(msg.contains("DuplicatedRepoId"));
+        assert!(msg.contains("www1"));
+        assert!(msg.contains("www2"));
     }
 
     #[fbinit::test]
@@ -1362,11 +1843,14 @@ mod test {
             scuba_table="scuba_table"
             scuba_table_hooks="scm_hooks"
             skiplist_index_blobstore_key="skiplist_key"
+            skiplist_rebuild_interval="1h"
             bookmarks_cache_ttl=5000
             storage_config="main"
             list_keys_patterns_max=123
             hook_max_file_size=456
             hipster_acl="foo/test"
+            derived_data_batch_size=512
+            scuba_sample_rate=0.1
 
             [wireproto_logging]
             scribe_category="category"
@@ -1380,6 +1864,7 @@ mod test {
 
             [derived_data_config]
             derived_data_types=["fsnodes"]
+            fsnode_compression_level=3
 
             [derived_data_config.raw_unode_version]
             unode_version_v2 = {}
@@ -1495,19 +1980,21 @@ mod test {
             scuba_table: Some("blobstore_scuba_table".to_string()),
             scuba_sample_rate: nonzero!(100u64),
             blobstores: vec![
-                (
-                    BlobstoreId::new(0),
-                    BlobConfig::Manifold {
+                MultiplexedBlobstoreConfig {
+                    blobstore_id: BlobstoreId::new(0),
+                    read_only: false,
+                    blobstore: BlobConfig::Manifold {
                         bucket: "bucket".into(),
                         prefix: "".into(),
                     },
-                ),
-                (
-                    BlobstoreId::new(1),
-                    BlobConfig::Files {
+                },
+                MultiplexedBlobstoreConfig {
+                    blobstore_id: BlobstoreId::new(1),
+                    read_only: false,
+                    blobstore: BlobConfig::Files {
                         path: "/tmp/foo".into(),
                     },
-                ),
+                },
             ],
             queue_db: DatabaseConfig::Remote(RemoteDatabaseConfig {
                 db_address: "queue_db_address".into(),
@@ -1542,11 +2029,11 @@ mod test {
                 scuba_local_path: None,
                 scuba_table_hooks: Some("scm_hooks".to_string()),
                 scuba_local_path_hooks: None,
-                cache_warmup: Some(CacheWarmupParams {
+                cache_warmup: vec![CacheWarmupParams {
                     bookmark: BookmarkName::new("master").unwrap(),
                     commit_limit: 100,
                     microwave_preload: false,
-                }),
+                }],
                 hook_manager_params: Some(HookManagerParams {
                     disable_acl_checker: false,
                 }),
@@ -1652,9 +2139,17 @@ mod test {
                     derived_data_types: btreeset![String::from("fsnodes")],
                     scuba_table: None,
                     unode_version: UnodeVersion::V2,
+                    fsnode_compression_level: Some(3),
                 },
                 hgsql_name: HgsqlName("fbsource".to_string()),
                 hgsql_globalrevs_name: HgsqlGlobalrevsName("fbsource".to_string()),
+                derived_data_batch_size: Some(512),
+                scuba_sample_rate: Some(0.1),
+                skiplist_rebuild_interval: Some(Duration::from_secs(3600)),
+                hash_validation_mode: None,
+                push_policy: PushPolicy::default(),
+                blobstore_key_prefix: None,
+                identity_rewrite: vec![],
             },
         );
 
@@ -1677,7 +2172,7 @@ mod test {
                 scuba_local_path: None,
                 scuba_table_hooks: Some("scm_hooks".to_string()),
                 scuba_local_path_hooks: None,
-                cache_warmup: None,
+                cache_warmup: vec![],
                 hook_manager_params: None,
                 bookmarks: vec![],
                 bookmarks_cache_ttl: None,
@@ -1702,6 +2197,13 @@ mod test {
                 derived_data_config: DerivedDataConfig::default(),
                 hgsql_name: HgsqlName("www-foobar".to_string()),
                 hgsql_globalrevs_name: HgsqlGlobalrevsName("www-barfoo".to_string()),
+                derived_data_batch_size: None,
+                scuba_sample_rate: None,
+                skiplist_rebuild_interval: None,
+                hash_validation_mode: None,
+                push_policy: PushPolicy::default(),
+                blobstore_key_prefix: None,
+                identity_rewrite: vec![],
             },
         );
         assert_eq!(
@@ -1823,89 +2325,887 @@ mod test {
     }
 
     #[fbinit::test]
-    fn test_broken_common_config(fb: FacebookInit) {
-        fn check_fails(fb: FacebookInit, common: &str, expect: &str) {
-            let content = r#"
-                repoid = 0
-                storage_config = "storage"
-
-                [storage.storage.metadata.local]
-                local_db_path = "/tmp/fbsource"
-
-                [storage.storage.blobstore.blob_sqlite]
-                path = "/tmp/fbsource"
-            "#;
-
-            let paths = btreemap! {
-                "common/common.toml" => common,
-                "common/commitsyncmap.toml" => "",
-                "repos/fbsource/server.toml" => content,
-            };
+    fn test_repo_defaults(fb: FacebookInit) {
+        let defaults = r#"
+            generation_cache_size=1024
+            scuba_table="scuba_table"
+            storage_config="files"
 
-            let tmp_dir = write_files(&paths);
+            [storage.files.metadata.local]
+            local_db_path = "/tmp/db"
 
-            let res = RepoConfigs::read_configs(fb, tmp_dir.path());
-            println!("res = {:?}", res);
-            let msg = format!("{:?}", res);
-            assert!(res.is_err(), "unexpected success for {}", common);
-            assert!(
-                msg.contains(expect),
-                "wrong failure, wanted \"{}\" in {}",
-                expect,
-                common
-            );
-        }
+            [storage.files.blobstore.blob_files]
+            path = "/tmp/blobs"
 
-        let common = r#"
-        [[whitelist_entry]]
-        identity_type="user"
+            [pushrebase]
+            rewritedates = false
+            recursion_limit = 1024
         "#;
-        check_fails(fb, common, "identity type and data must be specified");
-
-        let common = r#"
-        [[whitelist_entry]]
-        identity_data="user"
+        let www = r#"
+            repoid=1
         "#;
-        check_fails(fb, common, "identity type and data must be specified");
+        let fbsource = r#"
+            repoid=2
+            generation_cache_size=2048
 
-        let common = r#"
-        [[whitelist_entry]]
-        tier="user"
-        identity_type="user"
-        identity_data="user"
+            [pushrebase]
+            recursion_limit = 10
         "#;
-        check_fails(fb, common, "tier and identity cannot be both specified");
 
-        // Only one tier is allowed
-        let common = r#"
-        [[whitelist_entry]]
-        tier="tier1"
-        [[whitelist_entry]]
-        tier="tier2"
-        "#;
-        check_fails(fb, common, "only one tier is allowed");
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/common.toml" => defaults,
+            "repos/www/server.toml" => www,
+            "repos/fbsource/server.toml" => fbsource,
+        };
+
+        let tmp_dir = write_files(&paths);
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path()).expect("read configs failed");
+        assert_eq!(res.repos.len(), 2);
+
+        let www = &res.repos["www"];
+        assert_eq!(www.repoid, RepositoryId::new(1));
+        assert_eq!(www.generation_cache_size, 1024);
+        assert_eq!(www.scuba_table, Some("scuba_table".to_string()));
+        assert_eq!(www.pushrebase.flags.recursion_limit, Some(1024));
+        assert!(!www.pushrebase.flags.rewritedates);
+
+        let fbsource = &res.repos["fbsource"];
+        assert_eq!(fbsource.generation_cache_size, 2048);
+        assert_eq!(fbsource.scuba_table, Some("scuba_table".to_string()));
+        assert_eq!(fbsource.pushrebase.flags.recursion_limit, Some(10));
+        assert!(!fbsource.pushrebase.flags.rewritedates);
     }
 
     #[fbinit::test]
-    fn test_common_storage(fb: FacebookInit) {
-        const STORAGE: &str = r#"
-        [multiplex_store.metadata.remote]
-        primary = { db_address = "some_db" }
-        filenodes = { sharded = { shard_map = "some-shards", shard_num = 123 } }
-        mutation = { db_address = "some_db" }
+    fn test_include_hooks(fb: FacebookInit) {
+        let repo = r#"
+            include=["../../shared/security_hooks.toml"]
+            repoid=0
+            storage_config="files"
 
-        [multiplex_store.blobstore.multiplexed]
-        multiplex_id = 1
-        components = [
-            { blobstore_id = 1, blobstore = { blob_files = { path = "/tmp/foo" } } },
-        ]
-        queue_db = { remote = { db_address = "queue_db_address" } }
+            [storage.files.metadata.local]
+            local_db_path = "/tmp/db"
 
-        "#;
+            [storage.files.blobstore.blob_files]
+            path = "/tmp/blobs"
 
-        const REPO: &str = r#"
-        repoid = 123
-        storage_config = "multiplex_store"
+            [[bookmarks]]
+            name="master"
+            [[bookmarks.hooks]]
+            hook_name="repo_hook"
+
+            [[hooks]]
+            name="repo_hook"
+            hook_type="PerChangeset"
+        "#;
+        let security_hooks = r#"
+            include=["more_hooks.toml"]
+
+            [[bookmarks]]
+            regex="release/.*"
+            [[bookmarks.hooks]]
+            hook_name="security_hook"
+
+            [[hooks]]
+            name="security_hook"
+            hook_type="PerChangeset"
+        "#;
+        let more_hooks = r#"
+            [[hooks]]
+            name="more_hook"
+            hook_type="PerChangeset"
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/test/server.toml" => repo,
+            "shared/security_hooks.toml" => security_hooks,
+            "shared/more_hooks.toml" => more_hooks,
+        };
+
+        let tmp_dir = write_files(&paths);
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path()).expect("read configs failed");
+        let test = &res.repos["test"];
+        let hook_names: Vec<_> = test.hooks.iter().map(|hook| hook.name.as_str()).collect();
+        assert_eq!(hook_names, vec!["more_hook", "security_hook", "repo_hook"]);
+        let bookmark_hooks: Vec<_> = test
+            .bookmarks
+            .iter()
+            .map(|bookmark| bookmark.hooks.clone())
+            .collect();
+        assert_eq!(
+            bookmark_hooks,
+            vec![
+                vec!["security_hook".to_string()],
+                vec!["repo_hook".to_string()]
+            ]
+        );
+
+        // Including a file that includes the including file back is an error
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/test/server.toml" => repo,
+            "shared/security_hooks.toml" => security_hooks,
+            "shared/more_hooks.toml" => r#"include=["security_hooks.toml"]"#,
+        };
+
+        let tmp_dir = write_files(&paths);
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path());
+        let msg = format!("{:#?}", res);
+        println!("res = {}", msg);
+        assert!(res.is_err());
+        assert!(msg.contains("cyclic include"));
+    }
+
+    #[fbinit::test]
+    fn test_invalid_derived_data_batch_size(fb: FacebookInit) {
+        let content = r#"
+            repoid=0
+            storage_config = "sqlite"
+            derived_data_batch_size=0
+
+            [storage.sqlite.metadata.local]
+            local_db_path = "/tmp/fbsource"
+
+            [storage.sqlite.blobstore.blob_files]
+            path = "/tmp/fbsource"
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/fbsource/server.toml" => content,
+        };
+
+        let tmp_dir = write_files(&paths);
+
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path());
+        let msg = format!("{:#?}", res);
+        println!("res = {}", msg);
+        assert!(res.is_err());
+        assert!(msg.contains("derived_data_batch_size must be positive"));
+    }
+
+    #[fbinit::test]
+    fn test_invalid_scuba_sample_rate(fb: FacebookInit) {
+        let content = r#"
+            repoid=0
+            storage_config = "sqlite"
+            scuba_sample_rate=1.5
+
+            [storage.sqlite.metadata.local]
+            local_db_path = "/tmp/fbsource"
+
+            [storage.sqlite.blobstore.blob_files]
+            path = "/tmp/fbsource"
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/fbsource/server.toml" => content,
+        };
+
+        let tmp_dir = write_files(&paths);
+
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path());
+        let msg = format!("{:#?}", res);
+        println!("res = {}", msg);
+        assert!(res.is_err());
+        assert!(msg.contains("scuba_sample_rate must be between 0.0 and 1.0"));
+    }
+
+    #[fbinit::test]
+    fn test_invalid_bookmark_config(fb: FacebookInit) {
+        let read_with_bookmark = |bookmark: &str| {
+            let content = format!(
+                r#"
+                repoid=0
+                storage_config = "sqlite"
+
+                [storage.sqlite.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.sqlite.blobstore.blob_files]
+                path = "/tmp/fbsource"
+
+                [[bookmarks]]
+                {}
+                "#,
+                bookmark
+            );
+            let paths = btreemap! {
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content.as_str(),
+            };
+            let tmp_dir = write_files(&paths);
+            RepoConfigs::read_configs(fb, tmp_dir.path())
+        };
+
+        let res = read_with_bookmark(r#"regex="release/.*""#);
+        let fbsource = &res.expect("regex bookmark should be valid").repos["fbsource"];
+        match &fbsource.bookmarks[0].bookmark {
+            BookmarkOrRegex::Regex(regex) => assert_eq!(regex.as_str(), "release/.*"),
+            other => panic!("unexpected bookmark {:?}", other),
+        }
+
+        for (bookmark, error) in &[
+            (
+                r#"name="master"
+                regex="release/.*""#,
+                "bookmark's params need to specify regex xor name",
+            ),
+            ("", "bookmark's params need to specify regex xor name"),
+            (r#"regex="release/(""#, "invalid bookmark regex"),
+        ] {
+            let msg = format!("{:#?}", read_with_bookmark(bookmark));
+            println!("res = {}", msg);
+            assert!(msg.contains(error));
+        }
+    }
+
+    #[fbinit::test]
+    fn test_invalid_lfs_rollout_percentage(fb: FacebookInit) {
+        let content = r#"
+            repoid=0
+            storage_config = "sqlite"
+
+            [storage.sqlite.metadata.local]
+            local_db_path = "/tmp/fbsource"
+
+            [storage.sqlite.blobstore.blob_files]
+            path = "/tmp/fbsource"
+
+            [lfs]
+            threshold = 1000
+            rollout_percentage = 101
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/fbsource/server.toml" => content,
+        };
+
+        let tmp_dir = write_files(&paths);
+
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path());
+        let msg = format!("{:#?}", res);
+        println!("res = {}", msg);
+        assert!(res.is_err());
+        assert!(msg.contains("lfs rollout_percentage must be between 0 and 100, got 101"));
+    }
+
+    #[fbinit::test]
+    fn test_pushrebase_recursion_limit(fb: FacebookInit) {
+        let read_recursion_limit = |pushrebase: &str| {
+            let content = format!(
+                r#"
+                repoid=0
+                storage_config = "files"
+
+                [storage.files.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.files.blobstore.blob_files]
+                path = "/tmp/fbsource"
+
+                [pushrebase]
+                {}
+                "#,
+                pushrebase
+            );
+            let paths = btreemap! {
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content.as_str(),
+            };
+            let tmp_dir = write_files(&paths);
+            RepoConfigs::read_configs(fb, tmp_dir.path())
+                .map(|configs| configs.repos["fbsource"].pushrebase.flags.recursion_limit)
+        };
+
+        // The default limit is allowed
+        assert_eq!(read_recursion_limit("").unwrap(), Some(16384));
+        assert_eq!(
+            read_recursion_limit("recursion_limit = 100000").unwrap(),
+            Some(100000)
+        );
+
+        let msg = format!("{:#?}", read_recursion_limit("recursion_limit = 100001"));
+        println!("res = {}", msg);
+        assert!(msg.contains("pushrebase recursion_limit must be at most 100000"));
+    }
+
+    #[fbinit::test]
+    fn test_invalid_skiplist_rebuild_interval(fb: FacebookInit) {
+        let content = r#"
+            repoid=0
+            storage_config = "sqlite"
+            skiplist_rebuild_interval="0s"
+
+            [storage.sqlite.metadata.local]
+            local_db_path = "/tmp/fbsource"
+
+            [storage.sqlite.blobstore.blob_files]
+            path = "/tmp/fbsource"
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/fbsource/server.toml" => content,
+        };
+
+        let tmp_dir = write_files(&paths);
+
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path());
+        let msg = format!("{:#?}", res);
+        println!("res = {}", msg);
+        assert!(res.is_err());
+        assert!(msg.contains("skiplist_rebuild_interval must be positive"));
+    }
+
+    #[fbinit::test]
+    fn test_infinitepush_namespace(fb: FacebookInit) {
+        let read_infinitepush = |infinitepush: &str| {
+            let content = format!(
+                r#"
+                repoid=0
+                storage_config = "sqlite"
+
+                [storage.sqlite.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.sqlite.blobstore.blob_files]
+                path = "/tmp/fbsource"
+
+                [infinitepush]
+                {}
+            "#,
+                infinitepush
+            );
+            let paths = btreemap! {
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content.as_str(),
+            };
+            let tmp_dir = write_files(&paths);
+            RepoConfigs::read_configs(fb, tmp_dir.path())
+                .map(|configs| configs.repos["fbsource"].infinitepush.clone())
+        };
+
+        let infinitepush = read_infinitepush(
+            r#"
+            allow_writes = true
+            namespace_pattern = "scratch/.+"
+            "#,
+        )
+        .unwrap();
+        assert!(infinitepush.allow_writes);
+        let namespace = infinitepush.namespace.unwrap();
+        assert_eq!(namespace.as_str(), "scratch/.+");
+        assert!(namespace.matches_bookmark(&BookmarkName::new("scratch/foo").unwrap()));
+        assert!(!namespace.matches_bookmark(&BookmarkName::new("master").unwrap()));
+
+        // Writes can be allowed without scratch bookmarks.
+        let infinitepush = read_infinitepush("allow_writes = true").unwrap();
+        assert_eq!(infinitepush.namespace, None);
+
+        let msg = format!(
+            "{:#?}",
+            read_infinitepush(
+                r#"
+                allow_writes = true
+                namespace_pattern = ""
+                "#,
+            )
+        );
+        assert!(msg.contains("namespace_pattern must not be empty"));
+
+        let msg = format!(
+            "{:#?}",
+            read_infinitepush(
+                r#"
+                allow_writes = true
+                namespace_pattern = "scratch/("
+                "#,
+            )
+        );
+        assert!(msg.contains("invalid infinitepush namespace_pattern"));
+    }
+
+    #[fbinit::test]
+    fn test_push_policy(fb: FacebookInit) {
+        let read_policy = |policy: &str| {
+            let content = format!(
+                r#"
+                repoid=0
+                storage_config = "sqlite"
+
+                [storage.sqlite.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.sqlite.blobstore.blob_files]
+                path = "/tmp/fbsource"
+
+                [push_policy]
+                {}
+            "#,
+                policy
+            );
+            let paths = btreemap! {
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content.as_str(),
+            };
+            let tmp_dir = write_files(&paths);
+            RepoConfigs::read_configs(fb, tmp_dir.path())
+                .map(|configs| configs.repos["fbsource"].push_policy.clone())
+        };
+
+        let policy = read_policy(
+            r#"
+            denied_extensions = ["exe"]
+            denied_path_globs = ["secrets/**"]
+            max_file_size_bytes = 1048576
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            policy,
+            PushPolicy {
+                denied_extensions: vec!["exe".to_string()],
+                denied_path_globs: vec!["secrets/**".to_string()],
+                max_file_size_bytes: Some(1048576),
+            }
+        );
+        assert_eq!(read_policy("").unwrap(), PushPolicy::default());
+
+        let msg = format!(
+            "{:#?}",
+            read_policy(r#"denied_path_globs = ["secrets/[a"]"#)
+        );
+        assert!(msg.contains("invalid push_policy denied path glob"));
+        let msg = format!("{:#?}", read_policy(r#"denied_extensions = [".exe"]"#));
+        assert!(msg.contains("invalid push_policy denied extension"));
+        let msg = format!("{:#?}", read_policy("max_file_size_bytes = -1"));
+        assert!(msg.contains("max_file_size_bytes must not be negative"));
+    }
+
+    #[fbinit::test]
+    fn test_blobstore_key_prefix(fb: FacebookInit) {
+        let read_prefix = |prefix: &str| {
+            let content = format!(
+                r#"
+                repoid=0
+                storage_config = "sqlite"
+                {}
+
+                [storage.sqlite.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.sqlite.blobstore.blob_files]
+                path = "/tmp/fbsource"
+            "#,
+                prefix
+            );
+            let paths = btreemap! {
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content.as_str(),
+            };
+            let tmp_dir = write_files(&paths);
+            RepoConfigs::read_configs(fb, tmp_dir.path())
+                .map(|configs| configs.repos["fbsource"].blobstore_key_prefix.clone())
+        };
+
+        assert_eq!(
+            read_prefix(r#"blobstore_key_prefix = "shared/fbsource.""#).unwrap(),
+            Some("shared/fbsource.".to_string())
+        );
+        assert_eq!(read_prefix("").unwrap(), None);
+
+        for invalid in &["", "with space", "tab\t"] {
+            let msg = format!(
+                "{:#?}",
+                read_prefix(&format!("blobstore_key_prefix = {:?}", invalid))
+            );
+            assert!(msg.contains("blobstore_key_prefix must be"));
+        }
+        let msg = format!(
+            "{:#?}",
+            read_prefix(&format!("blobstore_key_prefix = {:?}", "a".repeat(65)))
+        );
+        assert!(msg.contains("blobstore_key_prefix must be"));
+    }
+
+    #[fbinit::test]
+    fn test_identity_rewrite(fb: FacebookInit) {
+        let read_rules = |rules: &str| {
+            let content = format!(
+                r#"
+                repoid=0
+                storage_config = "files"
+
+                [storage.files.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.files.blobstore.blob_files]
+                path = "/tmp/fbsource"
+
+                {}
+                "#,
+                rules
+            );
+            let paths = btreemap! {
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content.as_str(),
+            };
+            let tmp_dir = write_files(&paths);
+            RepoConfigs::read_configs(fb, tmp_dir.path())
+                .map(|configs| configs.repos["fbsource"].identity_rewrite.clone())
+        };
+
+        let rules = read_rules(
+            r#"
+            [[identity_rewrite]]
+            match_email = "alice@old\\.example\\.com"
+            replace_email = "alice@example.com"
+
+            [[identity_rewrite]]
+            match_email = ".*@users\\.noreply\\.github\\.com"
+            replace_email = "unknown@example.com"
+            replace_name = "Unknown"
+            "#,
+        )
+        .expect("read configs failed");
+        assert_eq!(rules.len(), 2);
+        assert!(rules[0].match_email.is_match("alice@old.example.com"));
+        assert!(!rules[0].match_email.is_match("malice@old.example.com"));
+        assert_eq!(
+            rules[0].replace_email,
+            Some("alice@example.com".to_string())
+        );
+        assert_eq!(rules[0].replace_name, None);
+        assert!(rules[1]
+            .match_email
+            .is_match("bob@users.noreply.github.com"));
+        assert_eq!(rules[1].replace_name, Some("Unknown".to_string()));
+
+        let msg = format!(
+            "{:#?}",
+            read_rules(
+                r#"
+                [[identity_rewrite]]
+                match_email = "(unclosed"
+                replace_name = "Someone"
+                "#
+            )
+        );
+        assert!(msg.contains("invalid identity_rewrite match_email"));
+
+        let msg = format!(
+            "{:#?}",
+            read_rules(
+                r#"
+                [[identity_rewrite]]
+                match_email = "alice@example.com"
+                "#
+            )
+        );
+        assert!(msg.contains("replaces neither email nor name"));
+    }
+
+    #[fbinit::test]
+    fn test_readonly_window(fb: FacebookInit) {
+        let read_readonly = |readonly: &str| {
+            let content = format!(
+                r#"
+                repoid=0
+                storage_config = "files"
+                {}
+
+                [storage.files.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.files.blobstore.blob_files]
+                path = "/tmp/fbsource"
+                "#,
+                readonly
+            );
+            let paths = btreemap! {
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content.as_str(),
+            };
+            let tmp_dir = write_files(&paths);
+            RepoConfigs::read_configs(fb, tmp_dir.path())
+                .map(|mut configs| configs.repos.remove("fbsource").unwrap())
+        };
+        let time = |time: &str| DateTime::from_rfc3339(time).unwrap();
+
+        let config = read_readonly(
+            r#"readonly_window = { start = "2020-06-01T09:00:00+01:00", end = "2020-06-01T10:00:00Z" }"#,
+        )
+        .expect("read configs failed");
+        assert_eq!(
+            config.readonly,
+            RepoReadOnly::ReadOnlyDuring {
+                start: time("2020-06-01T08:00:00Z"),
+                end: time("2020-06-01T10:00:00Z"),
+            }
+        );
+        assert!(!config.is_readonly_at(time("2020-06-01T07:59:59Z")));
+        assert!(config.is_readonly_at(time("2020-06-01T08:00:00Z")));
+        assert!(config.is_readonly_at(time("2020-06-01T11:30:00+02:00")));
+        assert!(config.is_readonly_at(time("2020-06-01T09:59:59Z")));
+        assert!(!config.is_readonly_at(time("2020-06-01T10:00:00Z")));
+        assert!(!config.is_readonly_at(time("2020-06-02T08:30:00Z")));
+
+        let config = read_readonly("readonly = true").expect("read configs failed");
+        assert!(config.is_readonly_at(time("2020-06-01T08:00:00Z")));
+        let config = read_readonly("").expect("read configs failed");
+        assert!(!config.is_readonly_at(time("2020-06-01T08:00:00Z")));
+
+        for (readonly, error) in &[
+            (
+                r#"readonly = true
+                readonly_window = { start = "2020-06-01T08:00:00Z", end = "2020-06-01T10:00:00Z" }"#,
+                "readonly and readonly_window can't both be set",
+            ),
+            (
+                r#"readonly_window = { start = "2020-06-01 08:00", end = "2020-06-01T10:00:00Z" }"#,
+                "is not an RFC3339 timestamp",
+            ),
+            (
+                r#"readonly_window = { start = "2020-06-01T10:00:00Z", end = "2020-06-01T08:00:00Z" }"#,
+                "readonly_window must start before it ends",
+            ),
+        ] {
+            let msg = format!("{:#?}", read_readonly(readonly));
+            println!("res = {}", msg);
+            assert!(msg.contains(error));
+        }
+    }
+
+    #[fbinit::test]
+    fn test_hash_validation_mode(fb: FacebookInit) {
+        let read_mode = |config: &str| {
+            let content = format!(
+                r#"
+                repoid=0
+                storage_config = "sqlite"
+                {}
+
+                [storage.sqlite.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.sqlite.blobstore.blob_files]
+                path = "/tmp/fbsource"
+                "#,
+                config
+            );
+            let paths = btreemap! {
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content.as_str(),
+            };
+            let tmp_dir = write_files(&paths);
+            RepoConfigs::read_configs(fb, tmp_dir.path())
+                .map(|res| res.repos["fbsource"].hash_validation_mode)
+        };
+
+        for (name, mode) in &[
+            ("FilenodeSha1", HashValidationMode::FilenodeSha1),
+            ("ContentBlake2", HashValidationMode::ContentBlake2),
+            ("Both", HashValidationMode::Both),
+        ] {
+            let config = format!("hash_validation_mode=\"{}\"", name);
+            assert_eq!(read_mode(&config).unwrap(), Some(*mode));
+        }
+
+        // Validation defaults to checking both hashes when enabled.
+        assert_eq!(
+            read_mode("hash_validation_percentage=10").unwrap(),
+            Some(HashValidationMode::Both)
+        );
+        assert_eq!(read_mode("").unwrap(), None);
+
+        let msg = format!("{:#?}", read_mode("hash_validation_mode=\"Sha256\""));
+        assert!(msg.contains("invalid hash_validation_mode"));
+    }
+
+    #[fbinit::test]
+    fn test_multiple_cache_warmups(fb: FacebookInit) {
+        let content = r#"
+            repoid=0
+            storage_config = "sqlite"
+
+            [cache_warmup]
+            bookmark="master"
+            commit_limit=100
+
+            [[cache_warmups]]
+            bookmark="release"
+            commit_limit=10
+
+            [[cache_warmups]]
+            bookmark="stable"
+
+            [storage.sqlite.metadata.local]
+            local_db_path = "/tmp/fbsource"
+
+            [storage.sqlite.blobstore.blob_files]
+            path = "/tmp/fbsource"
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/fbsource/server.toml" => content,
+        };
+
+        let tmp_dir = write_files(&paths);
+
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path()).expect("read configs failed");
+        assert_eq!(
+            res.repos["fbsource"].cache_warmup,
+            vec![
+                CacheWarmupParams {
+                    bookmark: BookmarkName::new("master").unwrap(),
+                    commit_limit: 100,
+                    microwave_preload: false,
+                },
+                CacheWarmupParams {
+                    bookmark: BookmarkName::new("release").unwrap(),
+                    commit_limit: 10,
+                    microwave_preload: false,
+                },
+                CacheWarmupParams {
+                    bookmark: BookmarkName::new("stable").unwrap(),
+                    commit_limit: 200000,
+                    microwave_preload: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_read_options_retry() {
+        // A reader that fails on its first attempt only.
+        let attempts = Cell::new(0);
+        let flaky_read = || {
+            attempts.set(attempts.get() + 1);
+            if attempts.get() == 1 {
+                Err(io::Error::new(io::ErrorKind::Other, "transient error"))
+            } else {
+                Ok("content")
+            }
+        };
+
+        assert!(ReadOptions::default().retry(flaky_read).is_err());
+        assert_eq!(attempts.get(), 1);
+
+        attempts.set(0);
+        let options = ReadOptions {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+        };
+        assert_eq!(options.retry(flaky_read).unwrap(), "content");
+        assert_eq!(attempts.get(), 2);
+    }
+
+    #[test]
+    fn test_read_options_metadata() {
+        let tmp_dir = TempDir::new("mononoke_test_config").expect("tmp_dir failed");
+        let options = ReadOptions {
+            retries: 1,
+            backoff: Duration::from_millis(1),
+        };
+        let metadata = options.metadata(tmp_dir.path()).unwrap();
+        assert!(metadata.map_or(false, |metadata| metadata.is_dir()));
+        // A missing path is an answer, not a failure to retry
+        assert!(options
+            .metadata(&tmp_dir.path().join("missing"))
+            .unwrap()
+            .is_none());
+    }
+
+    #[fbinit::test]
+    fn test_broken_common_config(fb: FacebookInit) {
+        fn check_fails(fb: FacebookInit, common: &str, expect: &str) {
+            let content = r#"
+                repoid = 0
+                storage_config = "storage"
+
+                [storage.storage.metadata.local]
+                local_db_path = "/tmp/fbsource"
+
+                [storage.storage.blobstore.blob_sqlite]
+                path = "/tmp/fbsource"
+            "#;
+
+            let paths = btreemap! {
+                "common/common.toml" => common,
+                "common/commitsyncmap.toml" => "",
+                "repos/fbsource/server.toml" => content,
+            };
+
+            let tmp_dir = write_files(&paths);
+
+            let res = RepoConfigs::read_configs(fb, tmp_dir.path());
+            println!("res = {:?}", res);
+            let msg = format!("{:?}", res);
+            assert!(res.is_err(), "unexpected success for {}", common);
+            assert!(
+                msg.contains(expect),
+                "wrong failure, wanted \"{}\" in {}",
+                expect,
+                common
+            );
+        }
+
+        let common = r#"
+        [[whitelist_entry]]
+        identity_type="user"
+        "#;
+        check_fails(fb, common, "identity type and data must be specified");
+
+        let common = r#"
+        [[whitelist_entry]]
+        identity_data="user"
+        "#;
+        check_fails(fb, common, "identity type and data must be specified");
+
+        let common = r#"
+        [[whitelist_entry]]
+        tier="user"
+        identity_type="user"
+        identity_data="user"
+        "#;
+        check_fails(fb, common, "tier and identity cannot be both specified");
+
+        // Only one tier is allowed
+        let common = r#"
+        [[whitelist_entry]]
+        tier="tier1"
+        [[whitelist_entry]]
+        tier="tier2"
+        "#;
+        check_fails(fb, common, "only one tier is allowed");
+    }
+
+    #[fbinit::test]
+    fn test_common_storage(fb: FacebookInit) {
+        const STORAGE: &str = r#"
+        [multiplex_store.metadata.remote]
+        primary = { db_address = "some_db" }
+        filenodes = { sharded = { shard_map = "some-shards", shard_num = 123 } }
+        mutation = { db_address = "some_db" }
+
+        [multiplex_store.blobstore.multiplexed]
+        multiplex_id = 1
+        components = [
+            { blobstore_id = 1, blobstore = { blob_files = { path = "/tmp/foo" } } },
+        ]
+        queue_db = { remote = { db_address = "queue_db_address" } }
+
+        "#;
+
+        const REPO: &str = r#"
+        repoid = 123
+        storage_config = "multiplex_store"
 
         # Not overriding common store
         [storage.some_other_store.metadata.remote]
@@ -1935,9 +3235,13 @@ mod test {
                         scuba_table: None,
                         scuba_sample_rate: nonzero!(100u64),
                         blobstores: vec![
-                            (BlobstoreId::new(1), BlobConfig::Files {
-                                path: "/tmp/foo".into()
-                            })
+                            MultiplexedBlobstoreConfig {
+                                blobstore_id: BlobstoreId::new(1),
+                                read_only: false,
+                                blobstore: BlobConfig::Files {
+                                    path: "/tmp/foo".into()
+                                },
+                            }
                         ],
                         queue_db: DatabaseConfig::Remote(
                             RemoteDatabaseConfig {
@@ -1963,6 +3267,13 @@ mod test {
                 hook_max_file_size: HOOK_MAX_FILE_SIZE_DEFAULT,
                 hgsql_name: HgsqlName("test".to_string()),
                 hgsql_globalrevs_name: HgsqlGlobalrevsName("test".to_string()),
+                derived_data_batch_size: None,
+                scuba_sample_rate: None,
+                skiplist_rebuild_interval: None,
+                hash_validation_mode: None,
+                push_policy: PushPolicy::default(),
+                blobstore_key_prefix: None,
+                identity_rewrite: vec![],
                 ..Default::default()
             }
         };
@@ -1974,6 +3285,227 @@ mod test {
         )
     }
 
+    #[fbinit::test]
+    fn test_multiplexed_read_only_blobstore(fb: FacebookInit) {
+        const STORAGE: &str = r#"
+        [multiplex_store.metadata.local]
+        local_db_path = "/tmp/db"
+
+        [multiplex_store.blobstore.multiplexed]
+        multiplex_id = 1
+        components = [
+            { blobstore_id = 1, blobstore = { blob_files = { path = "/tmp/foo" } } },
+            { blobstore_id = 2, blobstore = { blob_files = { path = "/tmp/bar" } }, read_only = true },
+        ]
+        queue_db = { local = { local_db_path = "/tmp/queue_db" } }
+        "#;
+
+        let paths = btreemap! {
+            "common/storage.toml" => STORAGE,
+            "common/commitsyncmap.toml" => "",
+        };
+
+        let tmp_dir = write_files(&paths);
+
+        let res = RepoConfigs::read_storage_configs(fb, tmp_dir.path())
+            .expect("read storage configs failed");
+
+        let expected = vec![
+            MultiplexedBlobstoreConfig {
+                blobstore_id: BlobstoreId::new(1),
+                read_only: false,
+                blobstore: BlobConfig::Files {
+                    path: "/tmp/foo".into(),
+                },
+            },
+            MultiplexedBlobstoreConfig {
+                blobstore_id: BlobstoreId::new(2),
+                read_only: true,
+                blobstore: BlobConfig::Files {
+                    path: "/tmp/bar".into(),
+                },
+            },
+        ];
+        match &res["multiplex_store"].blobstore {
+            BlobConfig::Multiplexed { blobstores, .. } => assert_eq!(blobstores, &expected),
+            other => panic!("unexpected blobstore config {:?}", other),
+        }
+    }
+
+    #[fbinit::test]
+    fn test_multiplexed_mixed_blobstores(fb: FacebookInit) {
+        const STORAGE: &str = r#"
+        [multiplex_store.metadata.local]
+        local_db_path = "/tmp/db"
+
+        [multiplex_store.blobstore.multiplexed]
+        multiplex_id = 1
+        components = [
+            { blobstore_id = 1, blobstore = { manifold = { manifold_bucket = "bucket", manifold_prefix = "" } } },
+            { blobstore_id = 2, blobstore = { blob_files = { path = "/tmp/foo" } }, read_only = true },
+        ]
+        queue_db = { local = { local_db_path = "/tmp/queue_db" } }
+        "#;
+
+        let paths = btreemap! {
+            "common/storage.toml" => STORAGE,
+            "common/commitsyncmap.toml" => "",
+        };
+
+        let tmp_dir = write_files(&paths);
+
+        let res = RepoConfigs::read_storage_configs(fb, tmp_dir.path())
+            .expect("read storage configs failed");
+
+        let expected = vec![
+            MultiplexedBlobstoreConfig {
+                blobstore_id: BlobstoreId::new(1),
+                read_only: false,
+                blobstore: BlobConfig::Manifold {
+                    bucket: "bucket".into(),
+                    prefix: "".into(),
+                },
+            },
+            MultiplexedBlobstoreConfig {
+                blobstore_id: BlobstoreId::new(2),
+                read_only: true,
+                blobstore: BlobConfig::Files {
+                    path: "/tmp/foo".into(),
+                },
+            },
+        ];
+        match &res["multiplex_store"].blobstore {
+            BlobConfig::Multiplexed { blobstores, .. } => assert_eq!(blobstores, &expected),
+            other => panic!("unexpected blobstore config {:?}", other),
+        }
+        assert!(!res["multiplex_store"].blobstore.is_local());
+
+        // GCS components parse, but are rejected as there is no client for them
+        let gcs_component = r#"{ blobstore_id = 3, blobstore = { gcs = { gcs_bucket = "gcs_bucket" } } },
+        ]
+        queue_db"#;
+        let paths = btreemap! {
+            "common/storage.toml" => STORAGE.replace("]\n        queue_db", gcs_component),
+            "common/commitsyncmap.toml" => "".to_string(),
+        };
+        let tmp_dir = write_files(&paths);
+        let msg = format!(
+            "{:#}",
+            RepoConfigs::read_storage_configs(fb, tmp_dir.path())
+                .expect_err("gcs blobstore should be rejected")
+        );
+        assert!(
+            msg.contains("gcs blobstore (bucket gcs_bucket) is not supported yet"),
+            "{}",
+            msg
+        );
+    }
+
+    #[fbinit::test]
+    fn test_env_var_substitution(fb: FacebookInit) {
+        const STORAGE: &str = r#"
+        [remote_store.metadata.remote]
+        primary = { db_address = "${MONONOKE_TEST_DB}" }
+        filenodes = { unsharded = { db_address = "${MONONOKE_TEST_DB}_filenodes" } }
+        mutation = { db_address = "mutation-${MONONOKE_TEST_DB}" }
+
+        [remote_store.blobstore.disabled]
+        "#;
+
+        let paths = btreemap! {
+            "common/storage.toml" => STORAGE,
+            "common/commitsyncmap.toml" => "",
+        };
+        let tmp_dir = write_files(&paths);
+
+        env::set_var("MONONOKE_TEST_DB", "xdb.test");
+        let res = RepoConfigs::read_storage_configs(fb, tmp_dir.path())
+            .expect("read storage configs failed");
+        assert_eq!(
+            res["remote_store"].metadata,
+            MetadataDatabaseConfig::Remote(RemoteMetadataDatabaseConfig {
+                primary: RemoteDatabaseConfig {
+                    db_address: "xdb.test".into(),
+                },
+                filenodes: ShardableRemoteDatabaseConfig::Unsharded(RemoteDatabaseConfig {
+                    db_address: "xdb.test_filenodes".into(),
+                }),
+                mutation: RemoteDatabaseConfig {
+                    db_address: "mutation-xdb.test".into(),
+                },
+            })
+        );
+
+        let paths = btreemap! {
+            "common/storage.toml" => STORAGE.replace("MONONOKE_TEST_DB", "MONONOKE_TEST_UNSET"),
+            "common/commitsyncmap.toml" => "".to_string(),
+        };
+        let tmp_dir = write_files(&paths);
+        let msg = format!(
+            "{:#?}",
+            RepoConfigs::read_storage_configs(fb, tmp_dir.path())
+        );
+        println!("res = {}", msg);
+        assert!(msg.contains("MissingEnvVar"));
+        assert!(msg.contains("MONONOKE_TEST_UNSET"));
+    }
+
+    #[test]
+    fn test_expand_env_vars_in_str() {
+        env::set_var("MONONOKE_TEST_EXPAND", "value");
+        assert_eq!(
+            expand_env_vars_in_str("a-${MONONOKE_TEST_EXPAND}-b").unwrap(),
+            "a-value-b"
+        );
+        assert_eq!(
+            expand_env_vars_in_str("^$${MONONOKE_TEST_UNSET}$").unwrap(),
+            "^${MONONOKE_TEST_UNSET}$"
+        );
+        assert_eq!(
+            expand_env_vars_in_str("$${MONONOKE_TEST_EXPAND} ${MONONOKE_TEST_EXPAND}").unwrap(),
+            "${MONONOKE_TEST_EXPAND} value"
+        );
+        assert_eq!(expand_env_vars_in_str("^a{2}${").unwrap(), "^a{2}${");
+        assert!(expand_env_vars_in_str("${MONONOKE_TEST_UNSET}").is_err());
+    }
+
+    #[fbinit::test]
+    fn test_fallback_blobstore(fb: FacebookInit) {
+        const STORAGE: &str = r#"
+        [migrating_store.metadata.local]
+        local_db_path = "/tmp/db"
+
+        [migrating_store.blobstore.fallback]
+        primary = { manifold = { manifold_bucket = "new_bucket", manifold_prefix = "" } }
+        fallback = { manifold = { manifold_bucket = "old_bucket", manifold_prefix = "repo-" } }
+        "#;
+
+        let paths = btreemap! {
+            "common/storage.toml" => STORAGE,
+            "common/commitsyncmap.toml" => "",
+        };
+
+        let tmp_dir = write_files(&paths);
+
+        let res = RepoConfigs::read_storage_configs(fb, tmp_dir.path())
+            .expect("read storage configs failed");
+
+        assert_eq!(
+            res["migrating_store"].blobstore,
+            BlobConfig::Fallback {
+                primary: Box::new(BlobConfig::Manifold {
+                    bucket: "new_bucket".into(),
+                    prefix: "".into(),
+                }),
+                fallback: Box::new(BlobConfig::Manifold {
+                    bucket: "old_bucket".into(),
+                    prefix: "repo-".into(),
+                }),
+            }
+        );
+        assert!(!res["migrating_store"].blobstore.is_local());
+    }
+
     #[fbinit::test]
     fn test_common_blobstores_local_override(fb: FacebookInit) {
         const STORAGE: &str = r#"
@@ -2037,6 +3569,13 @@ mod test {
                 hook_max_file_size: HOOK_MAX_FILE_SIZE_DEFAULT,
                 hgsql_name: HgsqlName("test".to_string()),
                 hgsql_globalrevs_name: HgsqlGlobalrevsName("test".to_string()),
+                derived_data_batch_size: None,
+                scuba_sample_rate: None,
+                skiplist_rebuild_interval: None,
+                hash_validation_mode: None,
+                push_policy: PushPolicy::default(),
+                blobstore_key_prefix: None,
+                identity_rewrite: vec![],
                 ..Default::default()
             }
         };
@@ -2076,4 +3615,31 @@ mod test {
         assert!(res.is_err());
         assert!(msg.contains("unknown keys in config parsing"));
     }
+
+    #[fbinit::test]
+    fn test_misspelled_key(fb: FacebookInit) {
+        const REPO: &str = r#"
+        reponame = 123
+        storage_config = "files"
+
+        [storage.files.metadata.local]
+        local_db_path = "/tmp/db"
+
+        [storage.files.blobstore.blob_files]
+        path = "/tmp/foo"
+        "#;
+
+        let paths = btreemap! {
+            "common/commitsyncmap.toml" => "",
+            "repos/test/server.toml" => REPO,
+        };
+
+        let tmp_dir = write_files(&paths);
+        let res = RepoConfigs::read_configs(fb, tmp_dir.path());
+        let msg = format!("{:#?}", res);
+        println!("res = {}", msg);
+        assert!(res.is_err());
+        assert!(msg.contains("unknown keys in config parsing"));
+        assert!(msg.contains("reponame"));
+    }
 }
@@ -27,7 +27,7 @@ use std::{
 
 use ascii::AsciiString;
 use bookmarks_types::BookmarkName;
-use mononoke_types::{MPath, RepositoryId};
+use mononoke_types::{DateTime, MPath, RepositoryId};
 use nonzero_ext::nonzero;
 use regex::Regex;
 use repos::{
@@ -69,7 +69,7 @@ pub struct CommonConfig {
 }
 
 /// Configuration of a single repository
-#[derive(Debug, Default, Clone, Eq, PartialEq)]
+#[derive(Debug, Default, Clone, PartialEq)]
 pub struct RepoConfig {
     /// If false, this repo config is completely ignored.
     pub enabled: bool,
@@ -89,8 +89,8 @@ pub struct RepoConfig {
     pub scuba_table_hooks: Option<String>,
     /// Local file to log hooks Scuba output to (useful in tests).
     pub scuba_local_path_hooks: Option<String>,
-    /// Parameters of how to warm up the cache
-    pub cache_warmup: Option<CacheWarmupParams>,
+    /// Parameters of how to warm up the cache, one entry per bookmark
+    pub cache_warmup: Vec<CacheWarmupParams>,
     /// Configuration for bookmarks
     pub bookmarks: Vec<BookmarkParams>,
     /// Infinitepush configuration
@@ -141,6 +141,25 @@ pub struct RepoConfig {
     /// Name of this repository in hgsql ... for globalrevs. This could, in some cases, not be the
     /// same as HgsqlName.
     pub hgsql_globalrevs_name: HgsqlGlobalrevsName,
+    /// Number of changesets derived together in one batch during derived
+    /// data backfills. None means the backfiller's built-in default.
+    pub derived_data_batch_size: Option<usize>,
+    /// Fraction of scuba samples to log, between 0.0 and 1.0. None means
+    /// every sample is logged.
+    pub scuba_sample_rate: Option<f64>,
+    /// How often the skiplist should be rebuilt. None means it is rebuilt
+    /// manually or by an external job.
+    pub skiplist_rebuild_interval: Option<Duration>,
+    /// Which hashes are validated when hash validation is enabled. Always
+    /// set if `hash_validation_percentage` is non-zero.
+    pub hash_validation_mode: Option<HashValidationMode>,
+    /// Files that pushes are not allowed to add or modify
+    pub push_policy: PushPolicy,
+    /// Prefix for all blobstore keys of this repo, for repos sharing a
+    /// blobstore
+    pub blobstore_key_prefix: Option<String>,
+    /// Rewrites of commit author identities applied at push time
+    pub identity_rewrite: Vec<IdentityRewriteRule>,
 }
 
 /// Config for derived data
@@ -152,6 +171,9 @@ pub struct DerivedDataConfig {
     pub derived_data_types: BTreeSet<String>,
     /// What unode version should be used (defaults to V1)
     pub unode_version: UnodeVersion,
+    /// zstd level to compress new fsnode blobs with, or None to store them
+    /// uncompressed
+    pub fsnode_compression_level: Option<i32>,
 }
 
 /// What type of unode derived data to generate
@@ -174,6 +196,15 @@ impl RepoConfig {
     pub fn primary_metadata_db_address(&self) -> Option<String> {
         self.storage_config.metadata.primary_address()
     }
+
+    /// Whether the config makes the repo read-only at `now`. The lock state in
+    /// the database is not taken into account.
+    pub fn is_readonly_at(&self, now: DateTime) -> bool {
+        match self.readonly.at(now) {
+            RepoReadOnly::ReadOnly(_) => true,
+            _ => false,
+        }
+    }
 }
 
 #[derive(Eq, Copy, Clone, Debug, PartialEq, Deserialize)]
@@ -198,6 +229,39 @@ pub enum RepoReadOnly {
     ReadOnly(String),
     /// This repo should accept writes.
     ReadWrite,
+    /// This repo is read-only from `start` until `end` for scheduled
+    /// maintenance, and should accept writes otherwise.
+    ReadOnlyDuring {
+        /// When the repo becomes read-only
+        start: DateTime,
+        /// When the repo accepts writes again
+        end: DateTime,
+    },
+}
+
+impl RepoReadOnly {
+    /// The state at `now`: `ReadOnlyDuring` is `ReadOnly` within its window
+    /// and `ReadWrite` outside it.
+    pub fn at(&self, now: DateTime) -> RepoReadOnly {
+        match self {
+            RepoReadOnly::ReadOnlyDuring { start, end } => {
+                if *start <= now && now < *end {
+                    RepoReadOnly::ReadOnly(format!(
+                        "Read-only for scheduled maintenance until {}",
+                        end
+                    ))
+                } else {
+                    RepoReadOnly::ReadWrite
+                }
+            }
+            readonly => readonly.clone(),
+        }
+    }
+
+    /// The state at the current time, see `at`.
+    pub fn now(&self) -> RepoReadOnly {
+        self.at(DateTime::now())
+    }
 }
 
 impl Default for RepoReadOnly {
@@ -615,6 +679,68 @@ impl TryFrom<RawStorageConfig> for StorageConfig {
     }
 }
 
+/// Files that pushes are not allowed to add or modify. The default policy
+/// allows everything.
+#[derive(Debug, Default, Clone, Eq, PartialEq)]
+pub struct PushPolicy {
+    /// File extensions, without the leading dot, that are denied.
+    pub denied_extensions: Vec<String>,
+    /// Globs of denied paths, matched against the whole path from the repo
+    /// root. These are validated when the config is loaded.
+    pub denied_path_globs: Vec<String>,
+    /// Files larger than this are denied.
+    pub max_file_size_bytes: Option<u64>,
+}
+
+/// Rewrite of the author identity of commits whose author email matches
+#[derive(Debug, Clone)]
+pub struct IdentityRewriteRule {
+    /// Matched against the whole author email
+    pub match_email: Regex,
+    /// Email to use instead, if it is replaced
+    pub replace_email: Option<String>,
+    /// Name to use instead, if it is replaced
+    pub replace_name: Option<String>,
+}
+
+impl PartialEq for IdentityRewriteRule {
+    fn eq(&self, other: &Self) -> bool {
+        self.match_email.as_str() == other.match_email.as_str()
+            && self.replace_email == other.replace_email
+            && self.replace_name == other.replace_name
+    }
+}
+
+impl Eq for IdentityRewriteRule {}
+
+/// Which hashes are checked by hash validation
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum HashValidationMode {
+    /// Check Mercurial filenode hashes
+    FilenodeSha1,
+    /// Check content ids
+    ContentBlake2,
+    /// Check both kinds of hashes
+    Both,
+}
+
+impl FromStr for HashValidationMode {
+    type Err = Error;
+
+    fn from_str(string: &str) -> Result<Self, Self::Err> {
+        match string {
+            "FilenodeSha1" => Ok(HashValidationMode::FilenodeSha1),
+            "ContentBlake2" => Ok(HashValidationMode::ContentBlake2),
+            "Both" => Ok(HashValidationMode::Both),
+            _ => Err(anyhow!(
+                "Unable to parse {} as {}",
+                string,
+                "HashValidationMode"
+            )),
+        }
+    }
+}
+
 /// What to do when the ScrubBlobstore finds a problem
 #[derive(Debug, Clone, Copy, Eq, PartialEq, Deserialize)]
 pub enum ScrubAction {
@@ -636,6 +762,17 @@ impl FromStr for ScrubAction {
     }
 }
 
+/// Configuration for one of the blobstores of a multiplex
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct MultiplexedBlobstoreConfig {
+    /// Id of the blobstore within the multiplex
+    pub blobstore_id: BlobstoreId,
+    /// Whether the blobstore is read-only (still read from, but not written to)
+    pub read_only: bool,
+    /// Configuration of the blobstore itself
+    pub blobstore: BlobConfig,
+}
+
 /// Configuration for a blobstore
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub enum BlobConfig {
@@ -673,7 +810,7 @@ pub enum BlobConfig {
         /// A scuba table I guess
         scuba_table: Option<String>,
         /// Set of blobstores being multiplexed over
-        blobstores: Vec<(BlobstoreId, BlobConfig)>,
+        blobstores: Vec<MultiplexedBlobstoreConfig>,
         /// 1 in scuba_sample_rate samples will be logged.
         scuba_sample_rate: NonZeroU64,
         /// DB config to use for the sync queue
@@ -686,7 +823,7 @@ pub enum BlobConfig {
         /// A scuba table I guess
         scuba_table: Option<String>,
         /// Set of blobstores being multiplexed over
-        blobstores: Vec<(BlobstoreId, BlobConfig)>,
+        blobstores: Vec<MultiplexedBlobstoreConfig>,
         /// Whether to attempt repair
         scrub_action: ScrubAction,
         /// 1 in scuba_sample_rate samples will be logged.
@@ -703,6 +840,14 @@ pub enum BlobConfig {
         /// TTL for each object we put in Manifold
         ttl: Duration,
     },
+    /// Read from the primary blobstore, falling back to the other blobstore for missing keys.
+    /// Writes only go to the primary.
+    Fallback {
+        /// Blobstore tried first for reads, and the only one written to
+        primary: Box<BlobConfig>,
+        /// Blobstore read from when the primary doesn't have a key
+        fallback: Box<BlobConfig>,
+    },
 }
 
 impl BlobConfig {
@@ -716,8 +861,9 @@ impl BlobConfig {
             Manifold { .. } | Mysql { .. } | ManifoldWithTtl { .. } => false,
             Multiplexed { blobstores, .. } | Scrub { blobstores, .. } => blobstores
                 .iter()
-                .map(|(_, config)| config)
+                .map(|component| &component.blobstore)
                 .all(BlobConfig::is_local),
+            Fallback { primary, fallback } => primary.is_local() && fallback.is_local(),
         }
     }
 
@@ -725,9 +871,12 @@ impl BlobConfig {
     /// This maximises error rates, and asks blobstores to silently fix errors when they are able
     /// to do so - ideal for repository checkers.
     pub fn set_scrubbed(&mut self, scrub_action: ScrubAction) {
-        use BlobConfig::{Multiplexed, Scrub};
+        use BlobConfig::{Fallback, Multiplexed, Scrub};
 
-        if let Multiplexed {
+        if let Fallback { primary, fallback } = self {
+            primary.set_scrubbed(scrub_action);
+            fallback.set_scrubbed(scrub_action);
+        } else if let Multiplexed {
             multiplex_id,
             scuba_table,
             scuba_sample_rate,
@@ -737,8 +886,8 @@ impl BlobConfig {
         {
             let scuba_table = mem::replace(scuba_table, None);
             let mut blobstores = mem::replace(blobstores, Vec::new());
-            for (_, store) in blobstores.iter_mut() {
-                store.set_scrubbed(scrub_action);
+            for component in blobstores.iter_mut() {
+                component.blobstore.set_scrubbed(scrub_action);
             }
             *self = Scrub {
                 multiplex_id: *multiplex_id,
@@ -810,10 +959,11 @@ impl TryFrom<RawBlobstoreConfig> for BlobConfig {
                     .components
                     .into_iter()
                     .map(|comp| {
-                        Ok((
-                            BlobstoreId(comp.blobstore_id.try_into()?),
-                            BlobConfig::try_from(comp.blobstore)?,
-                        ))
+                        Ok(MultiplexedBlobstoreConfig {
+                            blobstore_id: BlobstoreId(comp.blobstore_id.try_into()?),
+                            read_only: comp.read_only.unwrap_or(false),
+                            blobstore: BlobConfig::try_from(comp.blobstore)?,
+                        })
                     })
                     .collect::<Result<Vec<_>>>()?,
                 queue_db: def
@@ -829,6 +979,18 @@ impl TryFrom<RawBlobstoreConfig> for BlobConfig {
                     ttl,
                 }
             }
+            RawBlobstoreConfig::fallback(def) => BlobConfig::Fallback {
+                primary: Box::new(BlobConfig::try_from(*def.primary)?),
+                fallback: Box::new(BlobConfig::try_from(*def.fallback)?),
+            },
+            // Accepted by the schema so configs can be written ahead of time, but
+            // there is no GCS client to open one with yet.
+            RawBlobstoreConfig::gcs(def) => {
+                return Err(anyhow!(
+                    "gcs blobstore (bucket {}) is not supported yet",
+                    def.gcs_bucket
+                ));
+            }
             RawBlobstoreConfig::UnknownField(_) => {
                 return Err(anyhow!("unsupported blobstore configuration"));
             }
@@ -129,24 +129,22 @@ async fn do_main<'a>(
 
                     // Rewind bookmarks to the point where we have derived data. Cache
                     // warmup requires filenodes and hg changesets to be present.
-                    let req = match config.cache_warmup {
-                        Some(params) => {
-                            let CacheWarmupParams {
-                                bookmark,
-                                commit_limit,
-                                microwave_preload,
-                            } = params;
-
-                            let target = cache_warmup_target(&warmup_ctx, &repo, &bookmark).await?;
-
-                            Some(CacheWarmupRequest {
-                                target,
-                                commit_limit,
-                                microwave_preload,
-                            })
-                        }
-                        None => None,
-                    };
+                    let mut reqs = Vec::new();
+                    for params in config.cache_warmup {
+                        let CacheWarmupParams {
+                            bookmark,
+                            commit_limit,
+                            microwave_preload,
+                        } = params;
+
+                        let target = cache_warmup_target(&warmup_ctx, &repo, &bookmark).await?;
+
+                        reqs.push(CacheWarmupRequest {
+                            target,
+                            commit_limit,
+                            microwave_preload,
+                        });
+                    }
 
                     let repoid = config.repoid;
                     let warmup_repo = repo
@@ -157,7 +155,7 @@ async fn do_main<'a>(
                             Arc::new(MicrowaveChangesets::new(repoid, changesets_sender, inner))
                         });
 
-                    cache_warmup::cache_warmup(&warmup_ctx, &warmup_repo, req).await?;
+                    cache_warmup::cache_warmup(&warmup_ctx, &warmup_repo, reqs).await?;
 
                     Result::<_, Error>::Ok(repo)
                 };
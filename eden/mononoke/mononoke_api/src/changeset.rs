@@ -10,9 +10,11 @@ use std::convert::TryInto;
 use std::fmt;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::Arc;
 
 use anyhow::anyhow;
 use blobstore::Loadable;
+use bookmarks::BookmarkName;
 use changeset_info::ChangesetInfo;
 use chrono::{DateTime, FixedOffset};
 use cloned::cloned;
@@ -37,6 +39,48 @@ use crate::path::MononokePath;
 use crate::repo::RepoContext;
 use crate::specifiers::{ChangesetId, GitSha1, HgChangesetId};
 
+/// Options that control the traversal in `ChangesetContext::history`.
+#[derive(Clone, Debug, Default)]
+pub struct ChangesetHistoryOptions {
+    /// Stop at commits with an author date older than this timestamp.
+    pub until_timestamp: Option<i64>,
+    /// Stop at the commit this bookmark points to. That commit and all of
+    /// its ancestors are excluded from the history.
+    pub stop_at_bookmark: Option<BookmarkName>,
+    /// Only return commits with this committer. Commits without a separate
+    /// committer are matched against their author instead.
+    pub committer: Option<String>,
+    /// The order to return commits in. Only used by `ChangesetContext::history`
+    /// and `ChangesetContext::history_touching`.
+    pub order: HistoryOrder,
+}
+
+/// The order of the commits returned by `ChangesetContext::history`.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum HistoryOrder {
+    /// Breadth-first from the starting commit, so commits are returned as they
+    /// are reached. Where merges give paths of different lengths to a commit,
+    /// it can come before some of its descendants.
+    BreadthFirst,
+    /// Highest generation number first, so commits always come before their
+    /// ancestors.
+    Topological,
+    /// Lowest generation number first, so commits always come after their
+    /// ancestors. Useful for replaying history oldest-first.
+    TopologicalReverse,
+    /// Newest author date first. Commits with the same date stay in
+    /// breadth-first order.
+    AuthorDateDesc,
+    /// Oldest author date first.
+    AuthorDateAsc,
+}
+
+impl Default for HistoryOrder {
+    fn default() -> Self {
+        HistoryOrder::BreadthFirst
+    }
+}
+
 #[derive(Clone)]
 pub struct ChangesetContext {
     repo: RepoContext,
@@ -575,10 +619,142 @@ impl ChangesetContext {
     }
 
     /// Returns a stream of `ChangesetContext` for the history of the repository from this commit.
+    ///
+    /// Any order other than `HistoryOrder::BreadthFirst` needs the whole history, so it is
+    /// traversed and buffered in memory before the first commit is returned.
     pub async fn history(
         &self,
-        until_timestamp: Option<i64>,
+        opts: ChangesetHistoryOptions,
     ) -> impl Stream<Item = Result<ChangesetContext, MononokeError>> + '_ {
+        let order = opts.order;
+        let history = self
+            .history_impl(opts, false)
+            .map_ok(|(changeset, _distance)| changeset);
+        match order {
+            HistoryOrder::BreadthFirst => history.boxed(),
+            HistoryOrder::Topological | HistoryOrder::TopologicalReverse => {
+                let sorted = async move {
+                    let mut changesets: Vec<_> = history
+                        .and_then(|changeset| async move {
+                            Ok((changeset.generation().await?, changeset))
+                        })
+                        .try_collect()
+                        .await?;
+                    // A commit's generation number is higher than any of its
+                    // ancestors', so ordering by it is topological.
+                    if order == HistoryOrder::Topological {
+                        changesets.sort_by(|(a, _), (b, _)| b.cmp(a));
+                    } else {
+                        changesets.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    }
+                    Ok::<_, MononokeError>(stream::iter(
+                        changesets
+                            .into_iter()
+                            .map(|(_generation, changeset)| Ok(changeset)),
+                    ))
+                };
+                stream::once(sorted).try_flatten().boxed()
+            }
+            HistoryOrder::AuthorDateDesc | HistoryOrder::AuthorDateAsc => {
+                let sorted = async move {
+                    let mut changesets: Vec<_> = history
+                        .and_then(|changeset| async move {
+                            Ok((changeset.author_date().await?, changeset))
+                        })
+                        .try_collect()
+                        .await?;
+                    // The sort is stable, so commits with equal dates keep their
+                    // breadth-first order.
+                    if order == HistoryOrder::AuthorDateDesc {
+                        changesets.sort_by(|(a, _), (b, _)| b.cmp(a));
+                    } else {
+                        changesets.sort_by(|(a, _), (b, _)| a.cmp(b));
+                    }
+                    Ok::<_, MononokeError>(stream::iter(
+                        changesets
+                            .into_iter()
+                            .map(|(_date, changeset)| Ok(changeset)),
+                    ))
+                };
+                stream::once(sorted).try_flatten().boxed()
+            }
+        }
+    }
+
+    /// Returns a stream of `ChangesetContext` for the history of the repository from this commit,
+    /// along with the distance of each commit from this one.
+    ///
+    /// Commits on the first-parent chain of this commit are at the number of first-parent steps
+    /// needed to reach them, even if a merge provides a shorter path. Any other commit is one step
+    /// further than the commit through which the history first reached it, so the second parent
+    /// of a merge at distance 3 is at distance 4, and its first parent at distance 5.
+    pub async fn history_with_distance(
+        &self,
+        opts: ChangesetHistoryOptions,
+    ) -> impl Stream<Item = Result<(ChangesetContext, u64), MononokeError>> + '_ {
+        self.history_impl(opts, true)
+    }
+
+    /// Returns a stream of `ChangesetContext` for the history of the repository from this commit,
+    /// keeping only the commits that change any of `paths`, or anything inside them if they are
+    /// directories.
+    pub async fn history_touching(
+        &self,
+        paths: Vec<MPath>,
+        opts: ChangesetHistoryOptions,
+    ) -> impl Stream<Item = Result<ChangesetContext, MononokeError>> + '_ {
+        let paths = Arc::new(paths);
+        self.history(opts)
+            .await
+            .try_filter_map(move |changeset| {
+                cloned!(paths);
+                async move {
+                    let file_changes = changeset.file_changes().await?;
+                    let touches = file_changes
+                        .keys()
+                        .any(|changed| paths.iter().any(|path| path.is_prefix_of(changed)));
+                    Ok(if touches { Some(changeset) } else { None })
+                }
+            })
+            .boxed()
+    }
+
+    /// Returns the history of the repository from this commit, as in `history`, with maximal
+    /// runs of consecutive commits by the same author grouped together. Each group is the
+    /// author and their commits, in history order.
+    pub async fn history_grouped_by_author(
+        &self,
+        opts: ChangesetHistoryOptions,
+    ) -> Result<Vec<(String, Vec<ChangesetId>)>, MononokeError> {
+        self.history(opts)
+            .await
+            .try_fold(
+                Vec::new(),
+                |mut groups: Vec<(String, Vec<ChangesetId>)>, changeset| async move {
+                    let author = changeset.author().await?;
+                    match groups.last_mut() {
+                        Some((group_author, ids)) if *group_author == author => {
+                            ids.push(changeset.id())
+                        }
+                        _ => groups.push((author, vec![changeset.id()])),
+                    }
+                    Ok(groups)
+                },
+            )
+            .await
+    }
+
+    fn history_impl(
+        &self,
+        opts: ChangesetHistoryOptions,
+        track_first_parent: bool,
+    ) -> impl Stream<Item = Result<(ChangesetContext, u64), MononokeError>> + '_ {
+        let ChangesetHistoryOptions {
+            until_timestamp,
+            stop_at_bookmark,
+            committer,
+            order: _,
+        } = opts;
         let cs_info_enabled = self.repo.derive_changeset_info_enabled();
 
         let terminate = until_timestamp.map(move |until_timestamp| {
@@ -603,35 +779,186 @@ impl ChangesetContext {
             }
         });
 
-        stream::try_unfold(
-            // starting state
-            (hashset! { self.id() }, VecDeque::from(vec![self.id()])),
-            // unfold
-            move |(mut visited, mut queue)| async move {
-                if let Some(changeset_id) = queue.pop_front() {
-                    if let Some(terminate) = terminate {
-                        if terminate(changeset_id).await? {
-                            return Ok(Some((None, (visited, queue))));
+        let start = async move {
+            let stop_at = match stop_at_bookmark {
+                Some(bookmark) => match self.repo().resolve_bookmark(bookmark.as_str()).await? {
+                    Some(changeset) => Some((changeset.id(), changeset.generation().await?)),
+                    None => {
+                        return Err(MononokeError::InvalidRequest(format!(
+                            "bookmark not found: {}",
+                            bookmark
+                        )));
+                    }
+                },
+                None => None,
+            };
+            let first_parent = if track_first_parent {
+                Some(FirstParentChain::new(self.id(), self.generation().await?))
+            } else {
+                None
+            };
+            Ok((stop_at, first_parent))
+        };
+
+        stream::once(start)
+            .map_ok(move |(stop_at, first_parent)| {
+                stream::try_unfold(
+                    // starting state
+                    (
+                        hashset! { self.id() },
+                        VecDeque::from(vec![(self.id(), 0)]),
+                        first_parent,
+                    ),
+                    // unfold
+                    move |(mut visited, mut queue, mut first_parent)| async move {
+                        if let Some((changeset_id, distance)) = queue.pop_front() {
+                            if let Some(terminate) = terminate {
+                                if terminate(changeset_id).await? {
+                                    return Ok(Some((None, (visited, queue, first_parent))));
+                                }
+                            }
+                            let changeset =
+                                ChangesetContext::new(self.repo().clone(), changeset_id);
+                            let generation = if stop_at.is_some() || first_parent.is_some() {
+                                Some(changeset.generation().await?)
+                            } else {
+                                None
+                            };
+                            if let (Some((stop_at, stop_at_generation)), Some(generation)) =
+                                (stop_at, generation)
+                            {
+                                // Prune the bookmarked commit and its ancestors. Only commits
+                                // with a lower generation can be ancestors of it, so the others
+                                // don't need a reachability query.
+                                if changeset_id == stop_at
+                                    || (generation < stop_at_generation
+                                        && changeset.is_ancestor_of(stop_at).await?)
+                                {
+                                    // Its parents are ancestors too, so mark them visited
+                                    // rather than query them again if reached another way.
+                                    let parents = self
+                                        .repo()
+                                        .blob_repo()
+                                        .get_changeset_parents_by_bonsai(
+                                            self.ctx().clone(),
+                                            changeset_id,
+                                        )
+                                        .compat()
+                                        .await?;
+                                    visited.extend(parents);
+                                    return Ok(Some((None, (visited, queue, first_parent))));
+                                }
+                            }
+                            let distance = match (&mut first_parent, generation) {
+                                (Some(chain), Some(generation)) => {
+                                    self.extend_first_parent_chain(chain, generation).await?;
+                                    chain
+                                        .distances
+                                        .get(&changeset_id)
+                                        .copied()
+                                        .unwrap_or(distance)
+                                }
+                                _ => distance,
+                            };
+                            let parents = self
+                                .repo()
+                                .blob_repo()
+                                .get_changeset_parents_by_bonsai(self.ctx().clone(), changeset_id)
+                                .compat()
+                                .await?;
+                            queue.extend(
+                                parents
+                                    .into_iter()
+                                    .filter(|parent| visited.insert(*parent))
+                                    .map(|parent| (parent, distance + 1)),
+                            );
+                            Ok(Some((
+                                Some((changeset_id, distance)),
+                                (visited, queue, first_parent),
+                            )))
+                        } else {
+                            Ok::<_, MononokeError>(None)
+                        }
+                    },
+                )
+            })
+            .try_flatten()
+            .try_filter_map(move |item| {
+                let item = item.map(|(changeset_id, distance)| {
+                    (
+                        ChangesetContext::new(self.repo().clone(), changeset_id),
+                        distance,
+                    )
+                });
+                cloned!(committer);
+                async move {
+                    match (item, committer) {
+                        (Some((changeset, distance)), Some(committer)) => {
+                            let changeset_committer = match changeset.committer().await? {
+                                Some(changeset_committer) => changeset_committer,
+                                None => changeset.author().await?,
+                            };
+                            if changeset_committer == committer {
+                                Ok(Some((changeset, distance)))
+                            } else {
+                                Ok(None)
+                            }
                         }
+                        (item, _) => Ok::<_, MononokeError>(item),
                     }
-                    let parents = self
-                        .repo()
-                        .blob_repo()
-                        .get_changeset_parents_by_bonsai(self.ctx().clone(), changeset_id)
-                        .compat()
-                        .await?;
-                    queue.extend(parents.into_iter().filter(|parent| visited.insert(*parent)));
-                    Ok(Some((Some(changeset_id), (visited, queue))))
-                } else {
-                    Ok::<_, MononokeError>(None)
                 }
-            },
-        )
-        .try_filter_map(move |changeset_id| {
-            let changeset = changeset_id
-                .map(|changeset_id| ChangesetContext::new(self.repo().clone(), changeset_id));
-            async move { Ok::<_, MononokeError>(changeset) }
-        })
-        .boxed()
+            })
+            .boxed()
+    }
+
+    /// Follows the first-parent chain down to `generation`, so the distance of every chain
+    /// commit at or above it is known.
+    async fn extend_first_parent_chain(
+        &self,
+        chain: &mut FirstParentChain,
+        generation: Generation,
+    ) -> Result<(), MononokeError> {
+        while let Some((changeset_id, distance, tip_generation)) = chain.tip {
+            // Generations strictly decrease along the chain, so no commit further
+            // down it can be at `generation` or above.
+            if tip_generation < generation {
+                break;
+            }
+            chain.distances.insert(changeset_id, distance);
+            let parents = self
+                .repo()
+                .blob_repo()
+                .get_changeset_parents_by_bonsai(self.ctx().clone(), changeset_id)
+                .compat()
+                .await?;
+            chain.tip = match parents.first() {
+                Some(first_parent) => Some((
+                    *first_parent,
+                    distance + 1,
+                    ChangesetContext::new(self.repo().clone(), *first_parent)
+                        .generation()
+                        .await?,
+                )),
+                None => None,
+            };
+        }
+        Ok(())
+    }
+}
+
+/// The part of the first-parent chain of a commit walked so far, with the first-parent
+/// distance of each commit on it.
+struct FirstParentChain {
+    distances: HashMap<ChangesetId, u64>,
+    /// The next commit on the chain to visit, with its distance and generation.
+    tip: Option<(ChangesetId, u64, Generation)>,
+}
+
+impl FirstParentChain {
+    fn new(changeset_id: ChangesetId, generation: Generation) -> Self {
+        FirstParentChain {
+            distances: HashMap::new(),
+            tip: Some((changeset_id, 0, generation)),
+        }
     }
 }
@@ -22,6 +22,7 @@ use fastlog::{list_file_history, FastlogError};
 use filestore::FetchKey;
 use futures::compat::Future01CompatExt;
 use futures::future::{FutureExt, Shared};
+use futures::pin_mut;
 use futures::stream::Stream;
 use futures_old::Future as FutureLegacy;
 use futures_util::{try_join, TryStreamExt};
@@ -29,6 +30,7 @@ use manifest::{Entry, ManifestOps};
 use mononoke_types::{
     Blame, ChangesetId, ContentId, FileType, FileUnodeId, FsnodeId, ManifestUnodeId,
 };
+use unodes::RootUnodeManifestId;
 use xdiff;
 
 pub use xdiff::CopyInfo;
@@ -177,7 +179,6 @@ impl ChangesetPathContext {
         self.fsnode_id.clone().await
     }
 
-    #[allow(dead_code)]
     async fn unode_id(&self) -> Result<Option<Entry<ManifestUnodeId, FileUnodeId>>, MononokeError> {
         self.unode_id.clone().await
     }
@@ -272,6 +273,44 @@ impl ChangesetPathContext {
             .await
     }
 
+    /// Returns the changeset that most recently modified the file or
+    /// directory at this path, or `None` if the path doesn't exist.
+    ///
+    /// This is the linknode of the path's unode. If unodes haven't been
+    /// derived for this changeset, the first entry of the path's history is
+    /// used instead.
+    pub async fn last_modified(&self) -> Result<Option<ChangesetId>, MononokeError> {
+        let ctx = self.changeset.ctx().clone();
+        let repo = self.repo().blob_repo().clone();
+        let csid = self.changeset.id();
+
+        if !RootUnodeManifestId::is_derived(&ctx, &repo, &csid).await? {
+            let history = self.history(None).await?;
+            pin_mut!(history);
+            return Ok(history.try_next().await?.map(|cs| cs.id()));
+        }
+
+        let blobstore = repo.get_blobstore();
+        let linknode = match self.unode_id().await? {
+            Some(Entry::Tree(manifest_unode_id)) => Some(
+                *manifest_unode_id
+                    .load(ctx, &blobstore)
+                    .compat()
+                    .await?
+                    .linknode(),
+            ),
+            Some(Entry::Leaf(file_unode_id)) => Some(
+                *file_unode_id
+                    .load(ctx, &blobstore)
+                    .compat()
+                    .await?
+                    .linknode(),
+            ),
+            None => None,
+        };
+        Ok(linknode)
+    }
+
     /// Returns a list of `ChangesetContext` for the file at this path that represents
     /// a history of the path.
     pub async fn history(
@@ -47,7 +47,7 @@ mod test;
 
 pub use crate::legacy::get_content_by_path;
 
-pub use crate::changeset::{ChangesetContext, Generation};
+pub use crate::changeset::{ChangesetContext, ChangesetHistoryOptions, Generation, HistoryOrder};
 pub use crate::changeset_path::{
     unified_diff, ChangesetPathContext, CopyInfo, PathEntry, UnifiedDiff, UnifiedDiffMode,
 };
@@ -55,7 +55,7 @@ pub use crate::changeset_path_diff::ChangesetPathDiffContext;
 pub use crate::errors::MononokeError;
 pub use crate::file::{FileContext, FileId, FileMetadata, FileType};
 pub use crate::path::MononokePath;
-pub use crate::repo::RepoContext;
+pub use crate::repo::{RepoContext, RepoSize};
 pub use crate::repo_write::{CreateChange, CreateCopyInfo, RepoWriteContext};
 pub use crate::specifiers::{
     ChangesetId, ChangesetIdPrefix, ChangesetPrefixSpecifier, ChangesetSpecifier,
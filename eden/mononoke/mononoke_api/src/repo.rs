@@ -534,6 +534,13 @@ pub struct Stack {
     pub public: HashSet<ChangesetId>,
 }
 
+/// The number and total size of the files in a repo at some changeset.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct RepoSize {
+    pub file_count: u64,
+    pub total_bytes: u64,
+}
+
 /// A context object representing a query to a particular repo.
 impl RepoContext {
     pub(crate) async fn new(ctx: CoreContext, repo: Arc<Repo>) -> Result<Self, MononokeError> {
@@ -883,6 +890,23 @@ impl RepoContext {
         TreeContext::new_check_exists(self.clone(), tree_id).await
     }
 
+    /// Get the number and total size of the files in the repo at a changeset.
+    ///
+    /// This is read from the summary of the changeset's root fsnode, which is
+    /// derived if needed.
+    pub async fn repo_size_at(&self, cs_id: ChangesetId) -> Result<RepoSize, MononokeError> {
+        let root_fsnode_id = ChangesetContext::new(self.clone(), cs_id)
+            .root_fsnode_id()
+            .await?;
+        let summary = TreeContext::new(self.clone(), *root_fsnode_id.fsnode_id())
+            .summary()
+            .await?;
+        Ok(RepoSize {
+            file_count: summary.descendant_files_count,
+            total_bytes: summary.descendant_files_total_size,
+        })
+    }
+
     /// Get a File by id.  Returns `None` if the file doesn't exist.
     pub async fn file(&self, file_id: FileId) -> Result<Option<FileContext>, MononokeError> {
         FileContext::new_check_exists(self.clone(), FetchKey::Canonical(file_id)).await
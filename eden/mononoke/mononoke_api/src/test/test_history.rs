@@ -12,10 +12,12 @@ use anyhow::Result;
 use context::CoreContext;
 use fbinit::FacebookInit;
 use futures::stream::TryStreamExt;
-use mononoke_types::DateTime;
-use tests_utils::CreateCommitContext;
+use mononoke_types::{DateTime, MPath};
+use tests_utils::{bookmark, CreateCommitContext};
 
-use crate::{ChangesetId, ChangesetSpecifier, Repo, RepoContext};
+use crate::{
+    ChangesetHistoryOptions, ChangesetId, ChangesetSpecifier, HistoryOrder, Repo, RepoContext,
+};
 
 // Generates this commit graph:
 //
@@ -286,6 +288,34 @@ async fn commit_path_history(fb: FacebookInit) -> Result<()> {
     Ok(())
 }
 
+#[fbinit::compat_test]
+async fn commit_path_last_modified(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let (repo, changesets) = init_repo(&ctx).await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(changesets["c2"]))
+        .await?
+        .expect("changeset exists");
+
+    // Nothing is derived yet, so this goes through history. The merge m2
+    // doesn't count as modifying "a", as it keeps a4's version.
+    assert_eq!(cs.path("a")?.last_modified().await?, Some(changesets["a4"]));
+
+    // Unodes are derived now, so these use their linknodes.
+    assert_eq!(
+        cs.path("dir2")?.last_modified().await?,
+        Some(changesets["b3"])
+    );
+    assert_eq!(
+        cs.path("dir3")?.last_modified().await?,
+        Some(changesets["c2"])
+    );
+    assert_eq!(cs.path("missing")?.last_modified().await?, None);
+
+    Ok(())
+}
+
 #[fbinit::compat_test]
 async fn commit_history(fb: FacebookInit) -> Result<()> {
     let ctx = CoreContext::test_mock(fb);
@@ -298,7 +328,7 @@ async fn commit_history(fb: FacebookInit) -> Result<()> {
 
     // The commit history includes all commits, including empty ones.
     let history: Vec<_> = cs
-        .history(None)
+        .history(Default::default())
         .await
         .and_then(|cs| async move { Ok(cs.id()) })
         .try_collect()
@@ -329,7 +359,7 @@ async fn commit_history(fb: FacebookInit) -> Result<()> {
         .await?
         .expect("changeset exists");
     let history: Vec<_> = cs
-        .history(None)
+        .history(Default::default())
         .await
         .and_then(|cs| async move { Ok(cs.id()) })
         .try_collect()
@@ -349,7 +379,10 @@ async fn commit_history(fb: FacebookInit) -> Result<()> {
 
     // Setting until_timestamp omits some commits.
     let history: Vec<_> = cs
-        .history(Some(2500))
+        .history(ChangesetHistoryOptions {
+            until_timestamp: Some(2500),
+            ..Default::default()
+        })
         .await
         .and_then(|cs| async move { Ok(cs.id()) })
         .try_collect()
@@ -366,3 +399,484 @@ async fn commit_history(fb: FacebookInit) -> Result<()> {
 
     Ok(())
 }
+
+#[fbinit::compat_test]
+async fn commit_history_author_date_order(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let (repo, changesets) = init_repo(&ctx).await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(changesets["c2"]))
+        .await?
+        .expect("changeset exists");
+
+    // Author dates are out of order with the topology on both branches of
+    // the merges, e.g. "e2" is older than "e3", and "b1" is older than "a2".
+    let by_date = vec![
+        changesets["c2"],
+        changesets["m2"],
+        changesets["e3"],
+        changesets["e2"],
+        changesets["b3"],
+        changesets["a4"],
+        changesets["c1"],
+        changesets["e1"],
+        changesets["m1"],
+        changesets["a3"],
+        changesets["b2"],
+        changesets["a2"],
+        changesets["b1"],
+        changesets["a1"],
+    ];
+
+    let history = |order| {
+        cs.history(ChangesetHistoryOptions {
+            order,
+            ..Default::default()
+        })
+    };
+    let breadth_first: Vec<_> = history(HistoryOrder::BreadthFirst)
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_ne!(breadth_first, by_date);
+
+    let desc: Vec<_> = history(HistoryOrder::AuthorDateDesc)
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(desc, by_date);
+
+    let asc: Vec<_> = history(HistoryOrder::AuthorDateAsc)
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(asc, by_date.into_iter().rev().collect::<Vec<_>>());
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn commit_history_topological_reverse(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let (repo, changesets) = init_repo(&ctx).await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(changesets["c2"]))
+        .await?
+        .expect("changeset exists");
+
+    let history = |order| {
+        cs.history(ChangesetHistoryOptions {
+            order,
+            ..Default::default()
+        })
+    };
+    let reverse: Vec<_> = history(HistoryOrder::TopologicalReverse)
+        .await
+        .and_then(|cs| async move { Ok((cs.id(), cs.parents().await?)) })
+        .try_collect()
+        .await?;
+
+    assert_eq!(reverse.len(), changesets.len());
+    assert_eq!(reverse.first().map(|(id, _)| id), Some(&changesets["a1"]));
+    assert_eq!(reverse.last().map(|(id, _)| id), Some(&changesets["c2"]));
+    // Every commit comes after all of its parents.
+    for (index, (id, parents)) in reverse.iter().enumerate() {
+        for parent in parents {
+            let parent_index = reverse.iter().position(|(id, _)| id == parent);
+            assert!(
+                parent_index.map_or(false, |parent_index| parent_index < index),
+                "{} comes before its parent {}",
+                id,
+                parent
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Generates this commit graph:
+//
+// o   "s"
+// |\
+// o | "a"
+// | |
+// o | "b"
+// |/
+// o "x"
+//
+// Breadth-first from "s" reaches "x" before "b", although "b" is its descendant.
+#[fbinit::compat_test]
+async fn commit_history_topological_merge(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = blobrepo_factory::new_memblob_empty(None)?;
+    let x = CreateCommitContext::new_root(&ctx, &blob_repo)
+        .add_file("x", "x")
+        .commit()
+        .await?;
+    let b = CreateCommitContext::new(&ctx, &blob_repo, vec![x])
+        .add_file("b", "b")
+        .commit()
+        .await?;
+    let a = CreateCommitContext::new(&ctx, &blob_repo, vec![b])
+        .add_file("a", "a")
+        .commit()
+        .await?;
+    let s = CreateCommitContext::new(&ctx, &blob_repo, vec![a, x])
+        .add_file("s", "s")
+        .commit()
+        .await?;
+    let repo = Repo::new_test(ctx.clone(), blob_repo).await?;
+    let repo = RepoContext::new(ctx, Arc::new(repo)).await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(s))
+        .await?
+        .expect("changeset exists");
+
+    let history = |order| {
+        cs.history(ChangesetHistoryOptions {
+            order,
+            ..Default::default()
+        })
+    };
+    let breadth_first: Vec<_> = history(HistoryOrder::BreadthFirst)
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(breadth_first, vec![s, a, x, b]);
+
+    let topological: Vec<_> = history(HistoryOrder::Topological)
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(topological, vec![s, a, b, x]);
+
+    let reverse: Vec<_> = history(HistoryOrder::TopologicalReverse)
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(reverse, vec![x, b, a, s]);
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn commit_history_touching(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let (repo, changesets) = init_repo(&ctx).await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(changesets["c2"]))
+        .await?
+        .expect("changeset exists");
+
+    // Commits touching either "a" or "b", but not "dir1/a" alone.
+    let history: Vec<_> = cs
+        .history_touching(vec![MPath::new("a")?, MPath::new("b")?], Default::default())
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(
+        history,
+        vec![
+            changesets["m2"],
+            changesets["a4"],
+            changesets["b3"],
+            changesets["m1"],
+            changesets["b2"],
+            changesets["a3"],
+            changesets["b1"],
+            changesets["a2"],
+            changesets["a1"],
+        ]
+    );
+
+    // Directories match any change inside them.
+    let history: Vec<_> = cs
+        .history_touching(vec![MPath::new("dir2")?], Default::default())
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(
+        history,
+        vec![
+            changesets["m2"],
+            changesets["b3"],
+            changesets["m1"],
+            changesets["b2"],
+            changesets["b1"],
+        ]
+    );
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn commit_history_with_distance(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let (repo, changesets) = init_repo(&ctx).await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(changesets["c2"]))
+        .await?
+        .expect("changeset exists");
+
+    let history: Vec<_> = cs
+        .history_with_distance(Default::default())
+        .await
+        .and_then(|(cs, distance)| async move { Ok((cs.id(), distance)) })
+        .try_collect()
+        .await?;
+
+    // Commits on the first-parent chain are at their first-parent distance.
+    let first_parent_chain = ["c2", "m2", "e2", "a4", "c1", "e1", "m1", "b2", "b1"];
+    for (expected, name) in first_parent_chain.iter().enumerate() {
+        let distance = history
+            .iter()
+            .find(|(id, _)| *id == changesets[name])
+            .map(|(_, distance)| *distance);
+        assert_eq!(distance, Some(expected as u64), "distance of {}", name);
+    }
+
+    // Other commits are one step further than the commit they were reached from.
+    assert_eq!(
+        history,
+        vec![
+            (changesets["c2"], 0),
+            (changesets["m2"], 1),
+            (changesets["e2"], 2),
+            (changesets["e3"], 2),
+            (changesets["a4"], 3),
+            (changesets["b3"], 3),
+            (changesets["c1"], 4),
+            (changesets["e1"], 5),
+            (changesets["m1"], 6),
+            (changesets["b2"], 7),
+            (changesets["a3"], 7),
+            (changesets["b1"], 8),
+            (changesets["a2"], 8),
+            (changesets["a1"], 9),
+        ]
+    );
+
+    Ok(())
+}
+
+// Generates this commit graph:
+//
+// o   "s"
+// |\
+// o | "a"
+// | |
+// o | "a2"
+// | |
+// | o   "y"
+// |/|
+// o | "b"
+//   |
+//   o "r"
+//
+// The first parent of "y" is "r". Breadth-first reaches "b" through "y" before
+// the first-parent chain gets to it.
+#[fbinit::compat_test]
+async fn commit_history_with_distance_merge(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = blobrepo_factory::new_memblob_empty(None)?;
+    let b = CreateCommitContext::new_root(&ctx, &blob_repo)
+        .add_file("b", "b")
+        .commit()
+        .await?;
+    let r = CreateCommitContext::new_root(&ctx, &blob_repo)
+        .add_file("r", "r")
+        .commit()
+        .await?;
+    let a2 = CreateCommitContext::new(&ctx, &blob_repo, vec![b])
+        .add_file("a", "2")
+        .commit()
+        .await?;
+    let y = CreateCommitContext::new(&ctx, &blob_repo, vec![r, b])
+        .add_file("y", "y")
+        .commit()
+        .await?;
+    let a = CreateCommitContext::new(&ctx, &blob_repo, vec![a2])
+        .add_file("a", "1")
+        .commit()
+        .await?;
+    let s = CreateCommitContext::new(&ctx, &blob_repo, vec![a, y])
+        .add_file("s", "s")
+        .commit()
+        .await?;
+    let repo = Repo::new_test(ctx.clone(), blob_repo).await?;
+    let repo = RepoContext::new(ctx, Arc::new(repo)).await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(s))
+        .await?
+        .expect("changeset exists");
+
+    let history: Vec<_> = cs
+        .history_with_distance(Default::default())
+        .await
+        .and_then(|(cs, distance)| async move { Ok((cs.id(), distance)) })
+        .try_collect()
+        .await?;
+    assert_eq!(
+        history,
+        vec![(s, 0), (a, 1), (y, 1), (a2, 2), (r, 2), (b, 3)]
+    );
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn commit_history_stop_at_bookmark(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let (repo, changesets) = init_repo(&ctx).await?;
+
+    let stop_at_bookmark = bookmark(&ctx, repo.blob_repo(), "merged")
+        .set_to(changesets["c1"])
+        .await?;
+
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(changesets["c2"]))
+        .await?
+        .expect("changeset exists");
+
+    // The bookmarked commit and its ancestors are pruned.
+    let history: Vec<_> = cs
+        .history(ChangesetHistoryOptions {
+            stop_at_bookmark: Some(stop_at_bookmark),
+            ..Default::default()
+        })
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(
+        history,
+        vec![
+            changesets["c2"],
+            changesets["m2"],
+            changesets["e2"],
+            changesets["e3"],
+            changesets["a4"],
+            changesets["b3"],
+        ]
+    );
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn commit_history_committer(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = blobrepo_factory::new_memblob_empty(None)?;
+
+    // Rebased commits keep their author but get a new committer.
+    let first = CreateCommitContext::new_root(&ctx, &blob_repo)
+        .add_file("a", "1")
+        .set_author("alice")
+        .commit()
+        .await?;
+    let second = CreateCommitContext::new(&ctx, &blob_repo, vec![first])
+        .add_file("a", "2")
+        .set_author("alice")
+        .set_committer("bob")
+        .commit()
+        .await?;
+    let third = CreateCommitContext::new(&ctx, &blob_repo, vec![second])
+        .add_file("a", "3")
+        .set_author("bob")
+        .commit()
+        .await?;
+    let fourth = CreateCommitContext::new(&ctx, &blob_repo, vec![third])
+        .add_file("a", "4")
+        .set_author("bob")
+        .set_committer("carol")
+        .commit()
+        .await?;
+
+    let repo = Repo::new_test(ctx.clone(), blob_repo).await?;
+    let repo = RepoContext::new(ctx, Arc::new(repo)).await?;
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(fourth))
+        .await?
+        .expect("changeset exists");
+
+    let history = |committer: &str| {
+        cs.history(ChangesetHistoryOptions {
+            committer: Some(committer.to_string()),
+            ..Default::default()
+        })
+    };
+
+    // "third" has no separate committer, so it matches on its author.
+    let bob: Vec<_> = history("bob")
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(bob, vec![third, second]);
+
+    let alice: Vec<_> = history("alice")
+        .await
+        .and_then(|cs| async move { Ok(cs.id()) })
+        .try_collect()
+        .await?;
+    assert_eq!(alice, vec![first]);
+
+    Ok(())
+}
+
+#[fbinit::compat_test]
+async fn commit_history_grouped_by_author(fb: FacebookInit) -> Result<()> {
+    let ctx = CoreContext::test_mock(fb);
+    let blob_repo = blobrepo_factory::new_memblob_empty(None)?;
+
+    let mut parents = vec![];
+    let mut ids = vec![];
+    for (i, author) in ["alice", "bob", "bob", "alice", "carol", "carol"]
+        .iter()
+        .enumerate()
+    {
+        let id = CreateCommitContext::new(&ctx, &blob_repo, parents)
+            .add_file("a", i.to_string())
+            .set_author(*author)
+            .commit()
+            .await?;
+        parents = vec![id];
+        ids.push(id);
+    }
+
+    let repo = Repo::new_test(ctx.clone(), blob_repo).await?;
+    let repo = RepoContext::new(ctx, Arc::new(repo)).await?;
+    let cs = repo
+        .changeset(ChangesetSpecifier::Bonsai(ids[5]))
+        .await?
+        .expect("changeset exists");
+
+    let groups = cs.history_grouped_by_author(Default::default()).await?;
+    assert_eq!(
+        groups,
+        vec![
+            ("carol".to_string(), vec![ids[5], ids[4]]),
+            ("alice".to_string(), vec![ids[3]]),
+            ("bob".to_string(), vec![ids[2], ids[1]]),
+            // Only consecutive commits are grouped.
+            ("alice".to_string(), vec![ids[0]]),
+        ]
+    );
+
+    Ok(())
+}
@@ -25,7 +25,7 @@ use crate::{
     changeset_path_diff::ChangesetPathDiffContext, ChangesetId, ChangesetIdPrefix,
     ChangesetPrefixSpecifier, ChangesetSpecifier, ChangesetSpecifierPrefixResolution, CoreContext,
     FileId, FileMetadata, FileType, HgChangesetId, HgChangesetIdPrefix, Mononoke, MononokePath,
-    TreeEntry, TreeId,
+    RepoSize, TreeEntry, TreeId,
 };
 use cross_repo_sync_test_utils::init_small_large_repo;
 use mononoke_types::{
@@ -338,6 +338,30 @@ async fn commit_path_exists_and_type(fb: FacebookInit) -> Result<(), Error> {
     Ok(())
 }
 
+#[fbinit::compat_test]
+async fn repo_size_at(fb: FacebookInit) -> Result<(), Error> {
+    let ctx = CoreContext::test_mock(fb);
+    let mononoke = Mononoke::new_test(
+        ctx.clone(),
+        vec![("test".to_string(), many_files_dirs::getrepo(fb).await)],
+    )
+    .await?;
+    let repo = mononoke.repo(ctx, "test").await?.expect("repo exists");
+    let hash = "b0d1bf77898839595ee0f0cba673dd6e3be9dadaaa78bc6dd2dea97ca6bee77e";
+    let cs_id = ChangesetId::from_str(hash)?;
+
+    // Two 2-byte files at the root, and seven 9-byte files below it.
+    assert_eq!(
+        repo.repo_size_at(cs_id).await?,
+        RepoSize {
+            file_count: 9,
+            total_bytes: 67,
+        }
+    );
+
+    Ok(())
+}
+
 #[fbinit::compat_test]
 async fn tree_list(fb: FacebookInit) -> Result<(), Error> {
     let ctx = CoreContext::test_mock(fb);
@@ -15,6 +15,7 @@ use crate::path::MPathElement;
 use crate::thrift;
 use crate::typed_hash::{ContentId, FsnodeId, FsnodeIdContext};
 
+use bytes::Bytes;
 use fbthrift::compact_protocol;
 use std::collections::BTreeMap;
 
@@ -50,6 +51,15 @@ use std::collections::BTreeMap;
 // Unlike unodes, fsnodes are not repository-wide unique. If the same set of
 // files and directories appear at different places in the commit graph,
 // they will share fsnodes.
+//
+// Fsnode blobs may be stored compressed with zstd, in which case the blob
+// data is COMPRESSED_FSNODE_VERSION followed by the compressed thrift data.
+// The fsnode id is always the hash of the uncompressed data, so compressed
+// and uncompressed blobs of the same fsnode have the same key.
+
+/// First byte of compressed fsnode blobs. No compact protocol struct starts
+/// with this byte, so uncompressed blobs are still read as they are.
+const COMPRESSED_FSNODE_VERSION: u8 = 0xff;
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
 pub struct Fsnode {
@@ -117,10 +127,26 @@ impl Fsnode {
     }
 
     pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
-        let thrift_tc = compact_protocol::deserialize(bytes)
-            .with_context(|| ErrorKind::BlobDeserializeError("Fsnode".into()))?;
+        let thrift_tc = match bytes.split_first() {
+            Some((&COMPRESSED_FSNODE_VERSION, compressed)) => {
+                let bytes = zstd::stream::decode_all(compressed)
+                    .with_context(|| ErrorKind::BlobDeserializeError("Fsnode".into()))?;
+                compact_protocol::deserialize(bytes.as_slice())
+            }
+            _ => compact_protocol::deserialize(bytes),
+        }
+        .with_context(|| ErrorKind::BlobDeserializeError("Fsnode".into()))?;
         Self::from_thrift(thrift_tc)
     }
+
+    /// Like `into_blob`, but with the blob data compressed with zstd at
+    /// `level`. The blob id is the same as for the uncompressed blob.
+    pub fn into_compressed_blob(self, level: i32) -> Result<FsnodeBlob> {
+        let blob = self.into_blob();
+        let mut data = vec![COMPRESSED_FSNODE_VERSION];
+        zstd::stream::copy_encode(blob.data().as_ref(), &mut data, level)?;
+        Ok(Blob::new(*blob.id(), Bytes::from(data)))
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq, Hash)]
@@ -322,3 +348,48 @@ impl BlobstoreValue for Fsnode {
         Self::from_bytes(blob.data().as_ref())
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn sample_fsnode() -> Fsnode {
+        let file = FsnodeFile::new(
+            ContentId::from_byte_array([1; 32]),
+            FileType::Regular,
+            10,
+            Sha1::from_byte_array([2; 20]),
+            Sha256::from_byte_array([3; 32]),
+        );
+        let subentries = (0..20)
+            .map(|i| {
+                let name = MPathElement::new(format!("file{}", i).into_bytes()).unwrap();
+                (name, FsnodeEntry::File(file.clone()))
+            })
+            .collect();
+        let summary = FsnodeSummary {
+            simple_format_sha1: Sha1::from_byte_array([4; 20]),
+            simple_format_sha256: Sha256::from_byte_array([5; 32]),
+            child_files_count: 20,
+            child_files_total_size: 200,
+            child_dirs_count: 0,
+            descendant_files_count: 20,
+            descendant_files_total_size: 200,
+        };
+        Fsnode::new(subentries, summary)
+    }
+
+    #[test]
+    fn compressed_fsnode_roundtrip() {
+        let fsnode = sample_fsnode();
+        let blob = fsnode.clone().into_blob();
+        let compressed = fsnode.clone().into_compressed_blob(3).unwrap();
+
+        assert_eq!(compressed.id(), blob.id());
+        assert_eq!(compressed.data()[0], COMPRESSED_FSNODE_VERSION);
+        assert!(compressed.data().len() < blob.data().len());
+        assert_eq!(Fsnode::from_blob(compressed).unwrap(), fsnode);
+        // Uncompressed blobs can still be read.
+        assert_eq!(Fsnode::from_blob(blob).unwrap(), fsnode);
+    }
+}
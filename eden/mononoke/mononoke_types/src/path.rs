@@ -647,6 +647,11 @@ impl MPathHash {
         thrift::MPathHash(thrift::IdType::Blake2(self.0.into_thrift()))
     }
 
+    /// Construct from the bytes of the underlying Blake2 hash, see `AsRef<[u8]>`.
+    pub fn from_bytes(bytes: impl AsRef<[u8]>) -> Result<MPathHash> {
+        Blake2::from_bytes(bytes).map(MPathHash)
+    }
+
     pub fn to_hex(&self) -> AsciiString {
         self.0.to_hex()
     }
@@ -656,6 +661,12 @@ impl MPathHash {
     }
 }
 
+impl AsRef<[u8]> for MPathHash {
+    fn as_ref(&self) -> &[u8] {
+        self.0.as_ref()
+    }
+}
+
 /// Context for incrementally computing a hash.
 #[derive(Clone)]
 pub struct MPathHashContext(Context);
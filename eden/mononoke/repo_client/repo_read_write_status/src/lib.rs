@@ -163,15 +163,14 @@ impl RepoReadWriteFetcher {
     }
 
     pub fn readonly(&self) -> BoxFuture<RepoReadOnly, Error> {
+        let readonly_config = self.readonly_config.now();
         if self.sql_repo_read_write_status.is_some() {
-            match self.readonly_config {
-                RepoReadOnly::ReadOnly(ref reason) => {
-                    ok(RepoReadOnly::ReadOnly(reason.clone())).boxify()
-                }
-                RepoReadOnly::ReadWrite => self.query_read_write_state().boxify(),
+            match readonly_config {
+                RepoReadOnly::ReadOnly(reason) => ok(RepoReadOnly::ReadOnly(reason)).boxify(),
+                _ => self.query_read_write_state().boxify(),
             }
         } else {
-            ok(self.readonly_config.clone()).boxify()
+            ok(readonly_config).boxify()
         }
     }
 
@@ -9,7 +9,10 @@ use std::convert::{TryFrom, TryInto};
 
 use context::CoreContext;
 use futures_util::{future, stream, try_join, StreamExt, TryStreamExt};
-use mononoke_api::{unified_diff, ChangesetSpecifier, CopyInfo, MononokePath, UnifiedDiffMode};
+use mononoke_api::{
+    unified_diff, ChangesetHistoryOptions, ChangesetSpecifier, CopyInfo, MononokePath,
+    UnifiedDiffMode,
+};
 use source_control as thrift;
 
 use crate::commit_id::{map_commit_identity, CommitIdExt};
@@ -312,7 +315,12 @@ impl SourceControlServiceImpl {
             .into());
         }
 
-        let history_stream = changeset.history(after_timestamp).await;
+        let history_stream = changeset
+            .history(ChangesetHistoryOptions {
+                until_timestamp: after_timestamp,
+                ..Default::default()
+            })
+            .await;
         let history = collect_history(
             history_stream,
             skip,
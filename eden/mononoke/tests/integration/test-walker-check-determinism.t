@@ -0,0 +1,31 @@
+# Copyright (c) Facebook, Inc. and its affiliates.
+#
+# This software may be used and distributed according to the terms of the
+# GNU General Public License found in the LICENSE file in the root
+# directory of this source tree.
+
+  $ . "${TEST_FIXTURES}/library.sh"
+
+setup configuration
+  $ default_setup_pre_blobimport "blob_files"
+  hg repo
+  o  C [draft;rev=2;26805aba1e60]
+  |
+  o  B [draft;rev=1;112478962961]
+  |
+  o  A [draft;rev=0;426bada5c675]
+  $
+  $ blobimport repo-hg/.hg repo --derived-data-type=fsnodes
+
+check-determinism, bonsai core data. Both walks visit the 7 nodes that scrub counts.
+  $ mononoke_walker --storage-id=blobstore --readonly-storage check-determinism -q --bookmark master_bookmark -I bonsai 2>&1 | strip_glog
+  Walking roots * (glob)
+  Walking edge types [BonsaiChangesetToBonsaiParent, BonsaiChangesetToFileContent, BookmarkToBonsaiChangeset]
+  Walking node types [BonsaiChangeset, Bookmark, FileContent]
+  Walked 7 nodes in first run, 7 nodes in second run
+  Walks are deterministic. Type:Visited BonsaiChangeset:3 Bookmark:1 FileContent:3
+
+check-determinism, deep walk across bonsai and hg data
+  $ mononoke_walker --storage-id=blobstore --readonly-storage check-determinism -q --bookmark master_bookmark -I deep 2>&1 | strip_glog | grep -v "^Walking"
+  Walked * nodes in first run, * nodes in second run (glob)
+  Walks are deterministic. Type:Visited * (glob)
@@ -91,3 +91,19 @@ Check error-as-data-edge-type, should get no errors as FileContentMetadataToGitS
   1 Final count: (40, 39)
   1 Bytes/s,* (glob)
   1 Walked* (glob)
+
+Check the walk fails once max-errors is reached, even with errors as data
+  $ mononoke_walker --storage-id=blobstore --readonly-storage scrub --error-as-data-node-type AliasContentMapping --max-errors 1 -I deep -q --bookmark master_bookmark 2>&1 | strip_glog | sed -re 's/^(Could not step to).*/\1/' | uniq -c | sed 's/^ *//'
+  1 Walking roots * (glob)
+  1 Walking edge types * (glob)
+  1 Walking node types * (glob)
+  1 Error as data enabled, walk results may not be complete. Errors as data enabled for node types [AliasContentMapping] edge types []
+  1 Could not step to
+  1 Reached 1 errors, no longer expanding the walk
+  1 Final count: * (glob)
+  1 Bytes/s,* (glob)
+  1 Walked* (glob)
+  1 Execution error: Walk stopped after reaching the limit of 1 errors
+  1 Error: Execution failed
+  $ mononoke_walker --storage-id=blobstore --readonly-storage scrub --error-as-data-node-type AliasContentMapping --max-errors 1 -I deep -q --bookmark master_bookmark > /dev/null 2>&1
+  [1]
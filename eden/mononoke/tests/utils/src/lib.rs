@@ -30,7 +30,9 @@ pub struct CreateCommitContext<'a> {
     repo: &'a BlobRepo,
     parents: Vec<CommitIdentifier>,
     files: BTreeMap<String, CreateFileContext>,
+    author: Option<String>,
     author_date: Option<DateTime>,
+    committer: Option<String>,
     extra: BTreeMap<String, Vec<u8>>,
 }
 
@@ -46,7 +48,9 @@ impl<'a> CreateCommitContext<'a> {
             repo,
             parents,
             files: BTreeMap::new(),
+            author: None,
             author_date: None,
+            committer: None,
             extra: btreemap! {},
         }
     }
@@ -59,7 +63,9 @@ impl<'a> CreateCommitContext<'a> {
             repo,
             parents: vec![],
             files: BTreeMap::new(),
+            author: None,
             author_date: None,
+            committer: None,
             extra: btreemap! {},
         }
     }
@@ -134,11 +140,21 @@ impl<'a> CreateCommitContext<'a> {
         self
     }
 
+    pub fn set_author(mut self, author: impl Into<String>) -> Self {
+        self.author = Some(author.into());
+        self
+    }
+
     pub fn set_author_date(mut self, author_date: DateTime) -> Self {
         self.author_date = Some(author_date);
         self
     }
 
+    pub fn set_committer(mut self, committer: impl Into<String>) -> Self {
+        self.committer = Some(committer.into());
+        self
+    }
+
     pub async fn commit(self) -> Result<ChangesetId, Error> {
         let parents = future::try_join_all(self.parents.into_iter().map({
             let ctx = &self.ctx;
@@ -168,10 +184,10 @@ impl<'a> CreateCommitContext<'a> {
 
         let mut bcs = BonsaiChangesetMut {
             parents,
-            author: "author".to_string(),
+            author: self.author.unwrap_or_else(|| "author".to_string()),
             author_date,
-            committer: None,
-            committer_date: None,
+            committer_date: self.committer.as_ref().map(|_| author_date),
+            committer: self.committer,
             message: "message".to_string(),
             extra: self.extra,
             file_changes: btreemap! {},
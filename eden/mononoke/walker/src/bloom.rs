@@ -0,0 +1,117 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::{
+    collections::hash_map::DefaultHasher,
+    f64::consts::LN_2,
+    fmt,
+    hash::{Hash, Hasher},
+    sync::atomic::{AtomicU64, Ordering},
+};
+
+/// Bloom filter that can be shared between concurrent walk steps.
+///
+/// Items are never removed, so one bit per slot is enough. Lookups can report
+/// an item as present when it is not, but never the other way around.
+pub struct BloomFilter {
+    bits: Vec<AtomicU64>,
+    num_bits: u64,
+    num_hashes: u64,
+    bits_set: AtomicU64,
+}
+
+impl BloomFilter {
+    /// Size the filter so that once `expected_items` are inserted, lookups of
+    /// new items are wrongly reported present at `false_positive_rate`.
+    pub fn new(expected_items: usize, false_positive_rate: f64) -> Self {
+        let n = expected_items.max(1) as f64;
+        let p = false_positive_rate.max(f64::MIN_POSITIVE).min(0.5);
+        let num_words = ((-n * p.ln() / (LN_2 * LN_2)) / 64.0).ceil().max(1.0) as u64;
+        let num_bits = num_words * 64;
+        let num_hashes = ((num_bits as f64 / n) * LN_2).round().max(1.0) as u64;
+        Self {
+            bits: (0..num_words).map(|_| AtomicU64::new(0)).collect(),
+            num_bits,
+            num_hashes,
+            bits_set: AtomicU64::new(0),
+        }
+    }
+
+    // Double hashing, so only two hashes are computed however many bits are used
+    fn bit_indexes<T: Hash>(&self, item: &T) -> impl Iterator<Item = u64> {
+        let mut hasher = DefaultHasher::new();
+        item.hash(&mut hasher);
+        let h1 = hasher.finish();
+        let mut hasher = DefaultHasher::new();
+        h1.hash(&mut hasher);
+        let h2 = hasher.finish() | 1;
+        let num_bits = self.num_bits;
+        (0..self.num_hashes).map(move |i| h1.wrapping_add(i.wrapping_mul(h2)) % num_bits)
+    }
+
+    /// If the filter did not have this value present, true is returned.
+    pub fn insert<T: Hash>(&self, item: &T) -> bool {
+        let mut inserted = false;
+        for i in self.bit_indexes(item) {
+            let mask = 1 << (i % 64);
+            let old = self.bits[(i / 64) as usize].fetch_or(mask, Ordering::Relaxed);
+            if old & mask == 0 {
+                inserted = true;
+                self.bits_set.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+        inserted
+    }
+
+    pub fn contains<T: Hash>(&self, item: &T) -> bool {
+        self.bit_indexes(item).all(|i| {
+            let mask = 1 << (i % 64);
+            self.bits[(i / 64) as usize].load(Ordering::Relaxed) & mask != 0
+        })
+    }
+
+    /// Estimated chance of a new item being reported present, given how many
+    /// bits are set now.
+    pub fn false_positive_rate(&self) -> f64 {
+        let fill = self.bits_set.load(Ordering::Relaxed) as f64 / self.num_bits as f64;
+        fill.powi(self.num_hashes as i32)
+    }
+}
+
+impl fmt::Debug for BloomFilter {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("BloomFilter")
+            .field("num_bits", &self.num_bits)
+            .field("num_hashes", &self.num_hashes)
+            .field("bits_set", &self.bits_set.load(Ordering::Relaxed))
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_bloom_filter() {
+        let filter = BloomFilter::new(10000, 0.01);
+        assert_eq!(filter.false_positive_rate(), 0.0);
+        assert!(filter.insert(&0));
+        assert!(!filter.insert(&0));
+
+        // No false negatives
+        let inserted = (1..10000).filter(|i| filter.insert(i)).count();
+        assert!((0..10000).all(|i| filter.contains(&i)));
+        assert!(inserted > 9900, "{}", inserted);
+
+        // False positives near the configured rate once full
+        let false_positives = (10000..20000).filter(|i| filter.contains(i)).count();
+        assert!(false_positives < 200, "{}", false_positives);
+        let rate = filter.false_positive_rate();
+        assert!(rate > 0.005 && rate < 0.02, "{}", rate);
+    }
+}
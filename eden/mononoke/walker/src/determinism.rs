@@ -0,0 +1,198 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Walk the same roots twice and check that both walks visit the same nodes.
+//!
+//! Each walk gets a fresh `WalkStateCHashMap`, so any difference comes from
+//! the walk itself rather than from state carried over between runs. Only the
+//! multiset of visited nodes is compared, as the order they are visited in
+//! depends on scheduling. For the same reason `StepStats::visited_of_type` is
+//! not compared: it snapshots a counter shared by concurrent steps and is
+//! combined with `max`, so it can legitimately differ between runs.
+
+use crate::graph::{Node, NodeData, NodeType};
+use crate::progress::sort_by_string;
+use crate::setup::{setup_common, RepoWalkParams, CHECK_DETERMINISM};
+use crate::state::{StepStats, WalkStateCHashMap};
+use crate::tail::report_content_checksum;
+use crate::walk::walk_exact;
+
+use anyhow::{format_err, Error};
+use blobrepo::BlobRepo;
+use clap::ArgMatches;
+use context::CoreContext;
+use fbinit::FacebookInit;
+use futures::{future, stream::TryStreamExt};
+use scuba_ext::ScubaSampleBuilder;
+use slog::{error, info, Logger};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::Arc,
+};
+
+// Don't flood the log if the walks are very different
+const MAX_REPORTED_DIFFERENCES: usize = 100;
+
+type VisitCounts = HashMap<Node, usize>;
+
+async fn walk_once(
+    fb: FacebookInit,
+    logger: &Logger,
+    repo: &BlobRepo,
+    walk_params: &RepoWalkParams,
+    scuba_builder: ScubaSampleBuilder,
+) -> Result<(VisitCounts, [u8; 32]), Error> {
+    // Bloom filter false positives depend on visit order, so would show up as differences
+    if walk_params.visited_bloom.is_some() {
+        return Err(format_err!(
+            "Checking determinism needs visited nodes tracked exactly, not in a bloom filter"
+        ));
+    }
+    let ctx = CoreContext::new_with_logger(fb, logger.clone());
+    let walk_state = Arc::new(WalkStateCHashMap::new(walk_params.walk_state_options()));
+
+    let visited = walk_exact::<_, (Node, Option<NodeData>, Option<StepStats>), ()>(
+        ctx,
+        repo.clone(),
+        walk_params.enable_derive,
+        walk_params.walk_roots.clone(),
+        walk_state.clone(),
+        walk_params.scheduled_max,
+        walk_params.error_as_data_node_types.clone(),
+        walk_params.error_as_data_edge_types.clone(),
+        scuba_builder,
+        false,
+    )
+    .try_fold(VisitCounts::new(), |mut visited, (node, _data, _stats)| {
+        *visited.entry(node).or_insert(0) += 1;
+        future::ok(visited)
+    })
+    .await?;
+    if walk_state.error_limit_reached() {
+        return Err(format_err!(
+            "Walk stopped after reaching the limit of {} errors",
+            walk_params.max_errors.unwrap_or_default()
+        ));
+    }
+    Ok((visited, walk_state.content_checksum()))
+}
+
+/// Nodes visited a different number of times by the two walks, as
+/// `(node, first count, second count)`, sorted so output is stable.
+fn compare_visits(first: &VisitCounts, second: &VisitCounts) -> Vec<(Node, usize, usize)> {
+    let nodes: HashSet<&Node> = first.keys().chain(second.keys()).collect();
+    let mut differences: Vec<_> = nodes
+        .into_iter()
+        .filter_map(|node| {
+            let first_count = first.get(node).cloned().unwrap_or(0);
+            let second_count = second.get(node).cloned().unwrap_or(0);
+            if first_count != second_count {
+                Some((node.clone(), first_count, second_count))
+            } else {
+                None
+            }
+        })
+        .collect();
+    differences.sort_by_key(|(node, _, _)| format!("{:?}", node));
+    differences
+}
+
+fn format_counts_by_type(visited: &VisitCounts) -> String {
+    let mut by_type: HashMap<NodeType, usize> = HashMap::new();
+    for (node, count) in visited {
+        *by_type.entry(node.get_type()).or_insert(0) += count;
+    }
+    sort_by_string(by_type.keys())
+        .into_iter()
+        .map(|t| format!("{}:{}", t, by_type[t]))
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+// Subcommand entry point for checking walks are reproducible
+pub async fn check_determinism<'a>(
+    fb: FacebookInit,
+    logger: Logger,
+    matches: &'a ArgMatches<'a>,
+    sub_m: &'a ArgMatches<'a>,
+) -> Result<(), Error> {
+    let (datasources, walk_params) =
+        setup_common(CHECK_DETERMINISM, fb, &logger, None, matches, sub_m)?;
+    let repo = datasources.blobrepo.await?;
+    let scuba_builder = datasources.scuba_builder;
+
+    let (first, first_checksum) =
+        walk_once(fb, &logger, &repo, &walk_params, scuba_builder.clone()).await?;
+    let (second, second_checksum) =
+        walk_once(fb, &logger, &repo, &walk_params, scuba_builder).await?;
+    report_content_checksum(&logger, walk_params.content_checksum, first_checksum);
+    info!(
+        logger,
+        "Walked {} nodes in first run, {} nodes in second run",
+        first.values().sum::<usize>(),
+        second.values().sum::<usize>(),
+    );
+
+    let differences = compare_visits(&first, &second);
+    if differences.is_empty() && first_checksum != second_checksum {
+        return Err(format_err!(
+            "Walks visited the same nodes but their content checksums differ"
+        ));
+    }
+    if differences.is_empty() {
+        info!(
+            logger,
+            "Walks are deterministic. Type:Visited {}",
+            format_counts_by_type(&first)
+        );
+        return Ok(());
+    }
+
+    for (node, first_count, second_count) in differences.iter().take(MAX_REPORTED_DIFFERENCES) {
+        error!(
+            logger,
+            "Node {:?} visited {} times in first run, {} times in second run",
+            node,
+            first_count,
+            second_count,
+        );
+    }
+    Err(format_err!(
+        "Walks are not deterministic, {} nodes were visited differently",
+        differences.len()
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mononoke_types::{ChangesetId, ContentId};
+    use std::str::FromStr;
+
+    const SAMPLE_BLAKE2: &str = "b847b8838bfe3ae13ea6f8ce2e341c51193587b8392494f6dbab7224b3b116bf";
+
+    #[test]
+    fn test_compare_visits() {
+        let bcs = Node::BonsaiChangeset(ChangesetId::from_str(SAMPLE_BLAKE2).unwrap());
+        let file = Node::FileContent(ContentId::from_str(SAMPLE_BLAKE2).unwrap());
+
+        let first: VisitCounts = vec![(bcs.clone(), 1), (file.clone(), 2)]
+            .into_iter()
+            .collect();
+        assert!(compare_visits(&first, &first.clone()).is_empty());
+        assert_eq!(
+            format_counts_by_type(&first),
+            "BonsaiChangeset:1 FileContent:2"
+        );
+
+        let second: VisitCounts = vec![(file.clone(), 1)].into_iter().collect();
+        assert_eq!(
+            compare_visits(&first, &second),
+            vec![(bcs, 1, 0), (file, 2, 1)]
+        );
+    }
+}
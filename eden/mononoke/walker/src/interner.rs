@@ -0,0 +1,91 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use chashmap::CHashMap;
+use std::{
+    hash::Hash,
+    sync::atomic::{AtomicU32, Ordering},
+};
+
+/// Maps ids to compact u32s, so sets that hold the same ids can store the u32
+/// instead of the full id each time. Safe to share between concurrent walk steps.
+#[derive(Debug)]
+pub struct Interner<K> {
+    ids: CHashMap<K, u32>,
+    next_id: AtomicU32,
+}
+
+impl<K: Eq + Hash + Copy> Interner<K> {
+    pub fn new() -> Self {
+        Self {
+            ids: CHashMap::new(),
+            next_id: AtomicU32::new(0),
+        }
+    }
+
+    /// Return the u32 for this key, assigning the next free one on first sight.
+    pub fn intern(&self, key: K) -> u32 {
+        if let Some(id) = self.ids.get(&key) {
+            return *id;
+        }
+        // Only called if the key is still absent once its bucket is locked, so
+        // concurrent callers racing on the same key get the same id.
+        self.ids.upsert(
+            key,
+            || {
+                let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+                assert!(id < u32::MAX, "walker interner ran out of u32 ids");
+                id
+            },
+            |_| (),
+        );
+        *self.ids.get(&key).expect("interned ids are never removed")
+    }
+
+    /// Every key interned so far, mapped to its u32.
+    pub fn ids(&self) -> &CHashMap<K, u32> {
+        &self.ids
+    }
+
+    /// Number of keys interned so far.
+    pub fn len(&self) -> usize {
+        self.ids.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{sync::Arc, thread};
+
+    #[test]
+    fn test_intern() {
+        let interner = Interner::new();
+        assert_eq!(interner.len(), 0);
+        assert_eq!(interner.intern("a"), 0);
+        assert_eq!(interner.intern("b"), 1);
+        assert_eq!(interner.intern("a"), 0);
+        assert_eq!(interner.len(), 2);
+    }
+
+    #[test]
+    fn test_intern_concurrent() {
+        let interner = Arc::new(Interner::new());
+        let threads: Vec<_> = (0..4)
+            .map(|_| {
+                let interner = interner.clone();
+                thread::spawn(move || (0..1000).map(|i| interner.intern(i)).collect::<Vec<_>>())
+            })
+            .collect();
+        let results: Vec<_> = threads.into_iter().map(|t| t.join().unwrap()).collect();
+
+        // Every thread saw the same id for each key, and no ids were wasted
+        assert!(results.iter().all(|ids| ids == &results[0]));
+        assert_eq!(interner.len(), 1000);
+        assert!(results[0].iter().all(|id| *id < 1000));
+    }
+}
@@ -15,14 +15,19 @@ use futures::future::{self, FutureExt};
 use cmdlib::{args, helpers::block_execute};
 
 mod blobstore;
+mod bloom;
+mod determinism;
 #[macro_use]
 mod graph;
+mod interner;
 mod parse_node;
 mod progress;
+mod rate_limit;
 mod sampling;
 mod scrub;
 mod setup;
 mod sizing;
+mod snapshot;
 mod state;
 mod tail;
 mod validate;
@@ -47,6 +52,9 @@ fn main(fb: FacebookInit) -> Result<(), Error> {
         (setup::VALIDATE, Some(sub_m)) => {
             validate::validate(fb, logger.clone(), &matches, sub_m).boxed()
         }
+        (setup::CHECK_DETERMINISM, Some(sub_m)) => {
+            determinism::check_determinism(fb, logger.clone(), &matches, sub_m).boxed()
+        }
         _ => {
             future::err::<_, Error>(Error::msg("Invalid Arguments, pass --help for usage.")).boxed()
         }
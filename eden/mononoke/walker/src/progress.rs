@@ -180,6 +180,27 @@ where
     }
 }
 
+// Only list the types with errors, so a clean walk logs as before
+fn format_error_detail(
+    types_sorted_by_name: &[NodeType],
+    summary_by_type: &HashMap<NodeType, ProgressSummary>,
+) -> String {
+    let error_detail = types_sorted_by_name
+        .iter()
+        .filter_map(|t| {
+            summary_by_type
+                .get(t)
+                .filter(|s| s.errors > 0)
+                .map(|s| format!("{}:{}", t, s.errors))
+        })
+        .collect::<Vec<_>>();
+    if error_detail.is_empty() {
+        String::new()
+    } else {
+        format!("; Type:Errors {}", error_detail.join(" "))
+    }
+}
+
 impl ProgressStateCountByType<StepStats, ProgressSummary> {
     fn report_stats(&self, node_type: &NodeType, summary: &ProgressSummary) {
         STATS::walk_progress_walked_by_type.add_value(
@@ -240,6 +261,21 @@ impl ProgressStateCountByType<StepStats, ProgressSummary> {
             .collect::<Vec<_>>()
             .join(" ");
 
+        let error_detail = format_error_detail(&self.params.types_sorted_by_name, &summary_by_type);
+
+        // Only non-zero once visited nodes are tracked in a bloom filter
+        let false_positive_rate = self
+            .work_stats
+            .stats_by_type
+            .values()
+            .map(|(_, ss)| ss.visited_false_positive_rate)
+            .fold(0.0, f64::max);
+        let false_positive_detail = if false_positive_rate > 0.0 {
+            format!("; Visited false positive rate {:.6}", false_positive_rate)
+        } else {
+            String::new()
+        };
+
         let (delta_s, delta_summary_per_s) = delta_time
             .map(|delta_time| {
                 (
@@ -262,7 +298,7 @@ impl ProgressStateCountByType<StepStats, ProgressSummary> {
 
         info!(
             self.params.logger,
-            "Walked/s,Children/s,Walked,Errors,Children,Time; Delta {:06}/s,{:06}/s,{},{},{},{}s; Run {:06}/s,{:06}/s,{},{},{},{}s; Type:Walked,Checks,Children {}",
+            "Walked/s,Children/s,Walked,Errors,Children,Time; Delta {:06}/s,{:06}/s,{},{},{},{}s; Run {:06}/s,{:06}/s,{},{},{},{}s; Type:Walked,Checks,Children {}{}{}",
             delta_summary_per_s.walked,
             delta_summary_per_s.queued,
             delta_summary.walked,
@@ -276,6 +312,8 @@ impl ProgressStateCountByType<StepStats, ProgressSummary> {
             new_summary.queued,
             total_time.as_secs(),
             detail,
+            error_detail,
+            false_positive_detail,
         );
 
         STATS::walk_progress_walked.add_value(
@@ -445,3 +483,34 @@ where
     })
     .await
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format_error_detail() {
+        let types = sort_by_string(vec![
+            NodeType::BonsaiChangeset,
+            NodeType::FileContent,
+            NodeType::HgChangeset,
+        ]);
+        let summary = |walked, errors| ProgressSummary {
+            walked,
+            checked: walked,
+            queued: 0,
+            errors,
+        };
+        let mut summary_by_type = HashMap::new();
+        summary_by_type.insert(NodeType::BonsaiChangeset, summary(10, 0));
+        assert_eq!(format_error_detail(&types, &summary_by_type), "");
+
+        // Types with errors are listed by name, leaving out the clean ones
+        summary_by_type.insert(NodeType::HgChangeset, summary(5, 2));
+        summary_by_type.insert(NodeType::FileContent, summary(3, 1));
+        assert_eq!(
+            format_error_detail(&types, &summary_by_type),
+            "; Type:Errors FileContent:1 HgChangeset:2"
+        );
+    }
+}
@@ -0,0 +1,193 @@
+use futures::{
+    future::FutureExt,
+    stream::{self, FuturesUnordered, StreamExt},
+    Stream,
+};
+use std::{
+    cmp,
+    collections::VecDeque,
+    future::Future,
+    iter::FromIterator,
+    sync::Mutex,
+    task::Poll,
+    time::{Duration, Instant},
+};
+
+/// Outcome of asking a TokenBucket for a token.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Reservation {
+    /// A token was taken, and can be used after waiting this long.
+    Taken(Duration),
+    /// A refill's worth of tokens is already reserved, so none was taken. Try
+    /// again after waiting this long.
+    Full(Duration),
+}
+
+/// Token bucket that refills at a fixed rate per second, holding at most one
+/// second's worth of tokens. Safe to share between concurrent walk steps.
+#[derive(Debug)]
+pub struct TokenBucket {
+    per_second: f64,
+    // Tokens available, and when they were last refilled. Goes negative once
+    // callers have reserved tokens that are not refilled yet, but never below
+    // one refill's worth.
+    state: Mutex<(f64, Instant)>,
+}
+
+impl TokenBucket {
+    /// A bucket that starts full.
+    pub fn new(per_second: u32) -> Self {
+        let per_second = f64::from(cmp::max(per_second, 1));
+        Self {
+            per_second,
+            state: Mutex::new((per_second, Instant::now())),
+        }
+    }
+
+    /// Take a token. Up to a refill's worth can be taken ahead of being
+    /// refilled, so throttled callers are delayed in turn, waiting at most a
+    /// second. Past that callers are told to retry rather than queued.
+    pub fn reserve(&self) -> Reservation {
+        self.reserve_at(Instant::now())
+    }
+
+    fn reserve_at(&self, now: Instant) -> Reservation {
+        let mut state = self.state.lock().expect("lock poisoned");
+        let (tokens, last_refill) = &mut *state;
+        let elapsed = now.saturating_duration_since(*last_refill).as_secs_f64();
+        *tokens = (*tokens + elapsed * self.per_second).min(self.per_second);
+        *last_refill = cmp::max(*last_refill, now);
+        if *tokens - 1.0 < -self.per_second {
+            let missing = 1.0 - self.per_second - *tokens;
+            return Reservation::Full(Duration::from_secs_f64(missing / self.per_second));
+        }
+        *tokens -= 1.0;
+        if *tokens >= 0.0 {
+            Reservation::Taken(Duration::from_secs(0))
+        } else {
+            Reservation::Taken(Duration::from_secs_f64(-*tokens / self.per_second))
+        }
+    }
+}
+
+/// As `bounded_traversal_stream`, but each input is first passed to `reserve`,
+/// and throttled inputs wait before `unfold` is called on them. Waiting inputs
+/// do not count against `scheduled_max`, so throttling one kind of input does
+/// not hold up the others. At most `scheduled_max` inputs wait at once.
+pub fn throttled_traversal_stream<In, InsInit, Ins, Out, Reserve, Unfold, UFut, UErr>(
+    scheduled_max: usize,
+    init: InsInit,
+    mut reserve: Reserve,
+    mut unfold: Unfold,
+) -> impl Stream<Item = Result<Out, UErr>>
+where
+    Reserve: FnMut(&In) -> Option<Reservation>,
+    Unfold: FnMut(In) -> UFut,
+    UFut: Future<Output = Result<(Out, Ins), UErr>>,
+    InsInit: IntoIterator<Item = In>,
+    Ins: IntoIterator<Item = In>,
+{
+    // Inputs, and whether they already hold a token
+    let mut unscheduled = VecDeque::from_iter(init.into_iter().map(|item| (item, false)));
+    let mut waiting = FuturesUnordered::new();
+    let mut scheduled = FuturesUnordered::new();
+    stream::poll_fn(move |cx| loop {
+        while scheduled.len() < scheduled_max && waiting.len() < scheduled_max {
+            let (item, taken) = match unscheduled.pop_front() {
+                Some(next) => next,
+                None => break,
+            };
+            let wait = if taken { None } else { reserve(&item) };
+            match wait {
+                None => scheduled.push(unfold(item)),
+                Some(Reservation::Taken(delay)) if delay == Duration::from_secs(0) => {
+                    scheduled.push(unfold(item))
+                }
+                Some(Reservation::Taken(delay)) => {
+                    waiting.push(tokio::time::delay_for(delay).map(move |()| (item, true)))
+                }
+                Some(Reservation::Full(delay)) => {
+                    waiting.push(tokio::time::delay_for(delay).map(move |()| (item, false)))
+                }
+            }
+        }
+
+        if scheduled.is_empty() && waiting.is_empty() && unscheduled.is_empty() {
+            return Poll::Ready(None);
+        }
+
+        if let Poll::Ready(Some(result)) = scheduled.poll_next_unpin(cx) {
+            let (out, children) = result?;
+            for child in children {
+                unscheduled.push_front((child, false));
+            }
+            return Poll::Ready(Some(Ok(out)));
+        }
+
+        // Done waiting, so go ahead of the other inputs
+        match waiting.poll_next_unpin(cx) {
+            Poll::Ready(Some(item)) => unscheduled.push_front(item),
+            Poll::Ready(None) if scheduled.is_empty() => {}
+            _ => return Poll::Pending,
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_token_bucket() {
+        let bucket = TokenBucket::new(10);
+        let start = bucket.state.lock().unwrap().1;
+
+        // A full bucket lets a second's worth through at once
+        for _ in 0..10 {
+            assert_eq!(
+                bucket.reserve_at(start),
+                Reservation::Taken(Duration::from_secs(0))
+            );
+        }
+        // Then callers queue up a tenth of a second apart
+        let waits: Vec<_> = (0..3).map(|_| bucket.reserve_at(start)).collect();
+        assert_eq!(
+            waits,
+            vec![
+                Reservation::Taken(Duration::from_millis(100)),
+                Reservation::Taken(Duration::from_millis(200)),
+                Reservation::Taken(Duration::from_millis(300))
+            ]
+        );
+        // Up to a refill's worth, after which callers are told to retry
+        for _ in 0..6 {
+            bucket.reserve_at(start);
+        }
+        assert_eq!(
+            bucket.reserve_at(start),
+            Reservation::Taken(Duration::from_secs(1))
+        );
+        assert_eq!(
+            bucket.reserve_at(start),
+            Reservation::Full(Duration::from_millis(100))
+        );
+        let retry = start + Duration::from_millis(100);
+        assert_eq!(
+            bucket.reserve_at(retry),
+            Reservation::Taken(Duration::from_secs(1))
+        );
+
+        // Once refilled the debt is paid off, and refilling caps at a second's worth
+        let later = start + Duration::from_secs(10);
+        for _ in 0..10 {
+            assert_eq!(
+                bucket.reserve_at(later),
+                Reservation::Taken(Duration::from_secs(0))
+            );
+        }
+        assert_eq!(
+            bucket.reserve_at(later),
+            Reservation::Taken(Duration::from_millis(100))
+        );
+    }
+}
@@ -6,7 +6,8 @@
  */
 
 use crate::graph::{EdgeType, Node, NodeData, NodeType, WrappedPath};
-use crate::state::{StepStats, WalkStateCHashMap};
+use crate::rate_limit::Reservation;
+use crate::state::{SnapshotWalkState, StepStats, Visited, WalkStateAny, WalkStateOptions};
 use crate::walk::{OutgoingEdge, WalkVisitor};
 
 use context::{CoreContext, SamplingKey};
@@ -15,7 +16,7 @@ use std::{collections::HashSet, sync::Arc};
 
 #[derive(Debug)]
 pub struct SamplingWalkVisitor<T> {
-    inner: WalkStateCHashMap,
+    inner: WalkStateAny,
     sample_node_types: HashSet<NodeType>,
     sampler: Arc<NodeSamplingHandler<T>>,
     sample_rate: u64,
@@ -23,19 +24,30 @@ pub struct SamplingWalkVisitor<T> {
 
 impl<T> SamplingWalkVisitor<T> {
     pub fn new(
-        include_node_types: HashSet<NodeType>,
-        include_edge_types: HashSet<EdgeType>,
+        options: WalkStateOptions,
+        visited: Visited,
         sample_node_types: HashSet<NodeType>,
         sampler: Arc<NodeSamplingHandler<T>>,
         sample_rate: u64,
     ) -> Self {
         Self {
-            inner: WalkStateCHashMap::new(include_node_types, include_edge_types),
+            inner: WalkStateAny::new(options, visited),
             sample_node_types,
             sampler,
             sample_rate,
         }
     }
+
+    /// See `WalkState::interned_ids`.
+    pub fn interned_ids(&self) -> usize {
+        self.inner.interned_ids()
+    }
+}
+
+impl<T> SnapshotWalkState for SamplingWalkVisitor<T> {
+    fn snapshot_state(&self) -> &WalkStateAny {
+        &self.inner
+    }
 }
 
 #[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
@@ -117,6 +129,10 @@ where
         self.inner.start_step(ctx, route.map(|_| &()), step)
     }
 
+    fn reserve_step(&self, step: &OutgoingEdge) -> Option<Reservation> {
+        self.inner.reserve_step(step)
+    }
+
     fn visit(
         &self,
         ctx: &CoreContext,
@@ -169,6 +185,10 @@ where
         self.inner.start_step(ctx, route.map(|_| &()), step)
     }
 
+    fn reserve_step(&self, step: &OutgoingEdge) -> Option<Reservation> {
+        self.inner.reserve_step(step)
+    }
+
     fn visit(
         &self,
         ctx: &CoreContext,
@@ -34,7 +34,7 @@ use futures::{
 };
 use mononoke_types::BlobstoreBytes;
 use samplingblob::SamplingHandler;
-use slog::{info, Logger};
+use slog::{debug, info, Logger};
 use stats::prelude::*;
 use std::{collections::HashMap, fmt, sync::Arc, time::Duration};
 
@@ -308,17 +308,13 @@ pub async fn scrub_objects<'a>(
     let quiet = walk_params.quiet;
     let progress_state = walk_params.progress_state.clone();
 
-    cloned!(
-        walk_params.include_node_types,
-        walk_params.include_edge_types
-    );
     let mut sampling_node_types = parse_node_types(
         sub_m,
         INCLUDE_SAMPLE_NODE_TYPE_ARG,
         EXCLUDE_SAMPLE_NODE_TYPE_ARG,
         DEFAULT_INCLUDE_NODE_TYPES,
     )?;
-    sampling_node_types.retain(|i| include_node_types.contains(i));
+    sampling_node_types.retain(|i| walk_params.include_node_types.contains(i));
 
     let sizing_progress_state =
         ProgressStateMutex::new(ProgressStateCountByType::<ScrubStats, ScrubStats>::new(
@@ -359,20 +355,22 @@ pub async fn scrub_objects<'a>(
     };
 
     let walk_state = Arc::new(SamplingWalkVisitor::new(
-        include_node_types,
-        include_edge_types,
+        walk_params.walk_state_options(),
+        walk_params.visited(),
         sampling_node_types,
         scrub_sampler,
         sample_rate,
     ));
     walk_exact_tail::<_, _, _, _, _, ()>(
         fb,
-        logger,
+        logger.clone(),
         datasources,
         walk_params,
-        walk_state,
+        walk_state.clone(),
         make_sink,
         false,
     )
-    .await
+    .await?;
+    debug!(logger, "Interned {} ids", walk_state.interned_ids());
+    Ok(())
 }
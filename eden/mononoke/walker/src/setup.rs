@@ -11,7 +11,7 @@ use crate::parse_node::parse_node;
 use crate::progress::{
     sort_by_string, ProgressStateCountByType, ProgressStateMutex, ProgressSummary,
 };
-use crate::state::StepStats;
+use crate::state::{StepStats, Visited, VisitedBloom, VisitedCHashMap, WalkStateOptions};
 use crate::validate::{CheckType, REPO, WALK_TYPE};
 use crate::walk::OutgoingEdge;
 
@@ -30,10 +30,18 @@ use futures::{
 use futures_ext::FutureExt as _;
 use lazy_static::lazy_static;
 use metaconfig_types::{Redaction, ScrubAction};
+use mononoke_types::ChangesetId;
 use samplingblob::SamplingHandler;
 use scuba_ext::{ScubaSampleBuilder, ScubaSampleBuilderExt};
 use slog::{info, warn, Logger};
-use std::{collections::HashSet, iter::FromIterator, str::FromStr, sync::Arc, time::Duration};
+use std::{
+    collections::{HashMap, HashSet},
+    iter::FromIterator,
+    path::PathBuf,
+    str::FromStr,
+    sync::Arc,
+    time::Duration,
+};
 
 pub struct RepoWalkDatasources {
     pub blobrepo: BoxFuture<'static, Result<BlobRepo, Error>>,
@@ -47,11 +55,47 @@ pub struct RepoWalkParams {
     pub walk_roots: Vec<OutgoingEdge>,
     pub include_node_types: HashSet<NodeType>,
     pub include_edge_types: HashSet<EdgeType>,
+    pub node_type_priority: Vec<NodeType>,
     pub tail_secs: Option<u64>,
     pub quiet: bool,
     pub progress_state: ProgressStateMutex<ProgressStateCountByType<StepStats, ProgressSummary>>,
     pub error_as_data_node_types: HashSet<NodeType>,
     pub error_as_data_edge_types: HashSet<EdgeType>,
+    pub max_errors: Option<usize>,
+    pub boundary_changesets: HashSet<ChangesetId>,
+    pub content_checksum: bool,
+    pub file_content_sample_rate: f64,
+    pub node_rate_limits: HashMap<NodeType, u32>,
+    pub load_snapshot: Option<PathBuf>,
+    pub save_snapshot: Option<PathBuf>,
+    // Expected nodes and false positive rate to size a bloom filter of visited nodes
+    pub visited_bloom: Option<(usize, f64)>,
+}
+
+impl RepoWalkParams {
+    /// The walk state options these params ask for.
+    pub fn walk_state_options(&self) -> WalkStateOptions {
+        WalkStateOptions {
+            include_node_types: self.include_node_types.clone(),
+            include_edge_types: self.include_edge_types.clone(),
+            node_type_priority: self.node_type_priority.clone(),
+            max_errors: self.max_errors,
+            boundary_changesets: self.boundary_changesets.clone(),
+            content_checksum: self.content_checksum,
+            file_content_sample_rate: self.file_content_sample_rate,
+            node_rate_limits: self.node_rate_limits.clone(),
+        }
+    }
+
+    /// A fresh visited set of the kind these params ask for.
+    pub fn visited(&self) -> Visited {
+        match self.visited_bloom {
+            Some((expected_nodes, false_positive_rate)) => {
+                Visited::Bloom(VisitedBloom::new(expected_nodes, false_positive_rate))
+            }
+            None => Visited::Exact(VisitedCHashMap::new()),
+        }
+    }
 }
 
 pub const PROGRESS_SAMPLE_RATE: u64 = 1000;
@@ -61,6 +105,7 @@ pub const PROGRESS_SAMPLE_DURATION_S: u64 = 5;
 pub const SCRUB: &str = "scrub";
 pub const COMPRESSION_BENEFIT: &str = "compression-benefit";
 pub const VALIDATE: &str = "validate";
+pub const CHECK_DETERMINISM: &str = "check-determinism";
 
 // Subcommand args
 const QUIET_ARG: &str = "quiet";
@@ -69,12 +114,22 @@ const SCHEDULED_MAX_ARG: &str = "scheduled-max";
 const TAIL_INTERVAL_ARG: &str = "tail-interval";
 const ERROR_AS_DATA_NODE_TYPE_ARG: &str = "error-as-data-node-type";
 const ERROR_AS_DATA_EDGE_TYPE_ARG: &str = "error-as-data-edge-type";
+const MAX_ERRORS_ARG: &str = "max-errors";
 const EXCLUDE_NODE_TYPE_ARG: &str = "exclude-node-type";
 const INCLUDE_NODE_TYPE_ARG: &str = "include-node-type";
 const EXCLUDE_EDGE_TYPE_ARG: &str = "exclude-edge-type";
 const INCLUDE_EDGE_TYPE_ARG: &str = "include-edge-type";
+const PRIORITY_NODE_TYPE_ARG: &str = "priority-node-type";
 const BOOKMARK_ARG: &str = "bookmark";
 const WALK_ROOT_ARG: &str = "walk-root";
+const BOUNDARY_CHANGESET_ARG: &str = "boundary-changeset";
+const CONTENT_CHECKSUM_ARG: &str = "content-checksum";
+const FILE_CONTENT_SAMPLE_RATE_ARG: &str = "file-content-sample-rate";
+const NODE_RATE_LIMIT_ARG: &str = "node-rate-limit";
+const LOAD_SNAPSHOT_ARG: &str = "load-snapshot";
+const SAVE_SNAPSHOT_ARG: &str = "save-snapshot";
+const VISITED_BLOOM_NODES_ARG: &str = "visited-bloom-nodes";
+const VISITED_BLOOM_FALSE_POSITIVE_RATE_ARG: &str = "visited-bloom-false-positive-rate";
 const INNER_BLOBSTORE_ID_ARG: &str = "inner-blobstore-id";
 const SCRUB_BLOBSTORE_ACTION_ARG: &str = "scrub-blobstore-action";
 const ENABLE_DERIVE_ARG: &str = "enable-derive";
@@ -348,6 +403,11 @@ pub fn setup_toplevel_app<'a, 'b>(app_name: &str) -> App<'a, 'b> {
             .help(&INCLUDE_CHECK_TYPE_HELP),
     );
 
+    let check_determinism = setup_subcommand_args(
+        SubCommand::with_name(CHECK_DETERMINISM)
+            .about("walk twice from the same roots and check that both walks visit the same nodes"),
+    );
+
     app_template.build()
         .version("0.0.0")
         .about("Walks the mononoke commit and/or derived data graphs, with option of performing validations and modifications")
@@ -361,6 +421,7 @@ pub fn setup_toplevel_app<'a, 'b>(app_name: &str) -> App<'a, 'b> {
         .subcommand(compression_benefit)
         .subcommand(scrub_objects)
         .subcommand(validate)
+        .subcommand(check_determinism)
 }
 
 // Add the args the "start from repo" walk types need
@@ -464,6 +525,15 @@ fn setup_subcommand_args<'a, 'b>(subcmd: App<'a, 'b>) -> App<'a, 'b> {
                 .required(false)
                 .help(&INCLUDE_EDGE_TYPE_HELP),
         )
+        .arg(
+            Arg::with_name(PRIORITY_NODE_TYPE_ARG)
+                .long(PRIORITY_NODE_TYPE_ARG)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+                .help("Node types to visit first, highest priority first. Unlisted node types are visited after all listed ones, e.g. --priority-node-type BonsaiChangeset to reach changesets before file content"),
+        )
         .arg(
             Arg::with_name(BOOKMARK_ARG)
                 .long(BOOKMARK_ARG)
@@ -484,6 +554,68 @@ fn setup_subcommand_args<'a, 'b>(subcmd: App<'a, 'b>) -> App<'a, 'b> {
                 .number_of_values(1)
                 .help("Root(s) to start traversal from in format <NodeType>:<node_key>, e.g. Bookmark:master or HgChangeset:7712b62acdc858689504945ac8965a303ded6626"),
         )
+        .arg(
+            Arg::with_name(BOUNDARY_CHANGESET_ARG)
+                .long(BOUNDARY_CHANGESET_ARG)
+                .takes_value(true)
+                .required(false)
+                .multiple(true)
+                .number_of_values(1)
+                .help("Bonsai changeset(s) not to step to, so the walk does not continue into their history unless it is reachable another way"),
+        )
+        .arg(
+            Arg::with_name(CONTENT_CHECKSUM_ARG)
+                .long(CONTENT_CHECKSUM_ARG)
+                .takes_value(false)
+                .required(false)
+                .help("Log an order-independent checksum of all the nodes visited, for comparing repos"),
+        )
+        .arg(
+            Arg::with_name(FILE_CONTENT_SAMPLE_RATE_ARG)
+                .long(FILE_CONTENT_SAMPLE_RATE_ARG)
+                .takes_value(true)
+                .required(false)
+                .help("Fraction between 0 and 1 of file contents to visit. The same contents are picked on every run. Defaults to 1, visiting all of them"),
+        )
+        .arg(
+            Arg::with_name(NODE_RATE_LIMIT_ARG)
+                .long(NODE_RATE_LIMIT_ARG)
+                .takes_value(true)
+                .multiple(true)
+                .number_of_values(1)
+                .required(false)
+                .help("Maximum steps per second to a node type, e.g. --node-rate-limit FileContent:100 so the walk does not saturate the blobstore. Steps over the limit are delayed, not skipped"),
+        )
+        .arg(
+            Arg::with_name(LOAD_SNAPSHOT_ARG)
+                .long(LOAD_SNAPSHOT_ARG)
+                .takes_value(true)
+                .required(false)
+                .help("Start from a snapshot written by --save-snapshot, skipping the nodes it visited. It must have been taken walking the same node and edge types"),
+        )
+        .arg(
+            Arg::with_name(SAVE_SNAPSHOT_ARG)
+                .long(SAVE_SNAPSHOT_ARG)
+                .takes_value(true)
+                .required(false)
+                .help("Save the visited nodes to this file once each walk completes, including each tail iteration, so a restarted tail can carry on from the last completed walk with --load-snapshot. Nothing is saved for a walk that is interrupted"),
+        )
+        .arg(
+            Arg::with_name(VISITED_BLOOM_NODES_ARG)
+                .long(VISITED_BLOOM_NODES_ARG)
+                .takes_value(true)
+                .required(false)
+                .conflicts_with_all(&[LOAD_SNAPSHOT_ARG, SAVE_SNAPSHOT_ARG])
+                .help("Track visited nodes in a bloom filter sized for this many nodes, using far less memory on large repos. Some nodes are wrongly taken as visited and not walked, see the false positive rate in the progress output"),
+        )
+        .arg(
+            Arg::with_name(VISITED_BLOOM_FALSE_POSITIVE_RATE_ARG)
+                .long(VISITED_BLOOM_FALSE_POSITIVE_RATE_ARG)
+                .takes_value(true)
+                .required(false)
+                .requires(VISITED_BLOOM_NODES_ARG)
+                .help("False positive rate to size the --visited-bloom-nodes filter for, between 0 and 1 exclusive. Defaults to 0.01"),
+        )
         .arg(
             Arg::with_name(ERROR_AS_DATA_NODE_TYPE_ARG)
                 .long(ERROR_AS_DATA_NODE_TYPE_ARG)
@@ -504,6 +636,13 @@ fn setup_subcommand_args<'a, 'b>(subcmd: App<'a, 'b>) -> App<'a, 'b> {
                 .required(false)
                 .help("Types of edges to allow the walker to convert an ErrorKind::NotTraversable to a NodeData::ErrorAsData(NotTraversable). If empty then allow all edges for the nodes specified via error-as-data-node-type"),
         )
+        .arg(
+            Arg::with_name(MAX_ERRORS_ARG)
+                .long(MAX_ERRORS_ARG)
+                .takes_value(true)
+                .required(false)
+                .help("Stop expanding the walk once this many errors have been converted to data, and fail once it winds down. Default is no limit."),
+        )
         .arg(
             Arg::with_name(INNER_BLOBSTORE_ID_ARG)
                 .long(INNER_BLOBSTORE_ID_ARG)
@@ -666,6 +805,11 @@ pub fn setup_common(
         DEFAULT_INCLUDE_NODE_TYPES,
     )?;
 
+    let node_type_priority: Vec<NodeType> = match sub_m.values_of(PRIORITY_NODE_TYPE_ARG) {
+        None => Ok(vec![]),
+        Some(values) => values.map(NodeType::from_str).collect(),
+    }?;
+
     let mut walk_roots: Vec<OutgoingEdge> = vec![];
 
     if sub_m.is_present(BOOKMARK_ARG) {
@@ -697,6 +841,18 @@ pub fn setup_common(
         walk_roots.append(&mut roots);
     }
 
+    let boundary_changesets = match sub_m.values_of(BOUNDARY_CHANGESET_ARG) {
+        None => Ok(HashSet::new()),
+        Some(values) => values.map(ChangesetId::from_str).collect(),
+    }?;
+    if !boundary_changesets.is_empty() {
+        info!(
+            logger,
+            "Walk boundary changesets {:?}",
+            sort_by_string(&boundary_changesets)
+        );
+    }
+
     if walk_roots.is_empty() {
         return Err(format_err!(
             "No walk roots provided, pass with --{} or --{}",
@@ -750,6 +906,55 @@ pub fn setup_common(
         );
     }
 
+    let max_errors = args::get_usize_opt(&sub_m, MAX_ERRORS_ARG);
+    let content_checksum = sub_m.is_present(CONTENT_CHECKSUM_ARG);
+    let file_content_sample_rate = match sub_m.value_of(FILE_CONTENT_SAMPLE_RATE_ARG) {
+        Some(rate) => {
+            let rate = rate.parse::<f64>()?;
+            if !(0.0..=1.0).contains(&rate) {
+                return Err(format_err!(
+                    "--{} must be between 0 and 1, got {}",
+                    FILE_CONTENT_SAMPLE_RATE_ARG,
+                    rate
+                ));
+            }
+            rate
+        }
+        None => 1.0,
+    };
+    let node_rate_limits = match sub_m.values_of(NODE_RATE_LIMIT_ARG) {
+        None => Ok(HashMap::new()),
+        Some(values) => values.map(parse_node_rate_limit).collect(),
+    }?;
+    if !node_rate_limits.is_empty() {
+        info!(logger, "Walk node rate limits {:?}", node_rate_limits);
+    }
+    let load_snapshot = sub_m.value_of(LOAD_SNAPSHOT_ARG).map(PathBuf::from);
+    let save_snapshot = sub_m.value_of(SAVE_SNAPSHOT_ARG).map(PathBuf::from);
+    let visited_bloom = match args::get_usize_opt(&sub_m, VISITED_BLOOM_NODES_ARG) {
+        Some(expected_nodes) => {
+            let rate = match sub_m.value_of(VISITED_BLOOM_FALSE_POSITIVE_RATE_ARG) {
+                Some(rate) => rate.parse::<f64>()?,
+                None => 0.01,
+            };
+            if !(rate > 0.0 && rate < 1.0) {
+                return Err(format_err!(
+                    "--{} must be between 0 and 1 exclusive, got {}",
+                    VISITED_BLOOM_FALSE_POSITIVE_RATE_ARG,
+                    rate
+                ));
+            }
+            info!(
+                logger,
+                "Tracking visited nodes in a bloom filter for {} nodes at false positive rate {}",
+                expected_nodes,
+                rate
+            );
+            Some((expected_nodes, rate))
+        }
+        None => None,
+    };
+
     let mysql_options = args::parse_mysql_options(&matches);
 
     let storage_id = matches.value_of(STORAGE_ID_ARG);
@@ -855,11 +1060,44 @@ pub fn setup_common(
             walk_roots,
             include_node_types,
             include_edge_types,
+            node_type_priority,
             tail_secs,
             quiet,
             progress_state,
             error_as_data_node_types,
             error_as_data_edge_types,
+            max_errors,
+            boundary_changesets,
+            content_checksum,
+            file_content_sample_rate,
+            node_rate_limits,
+            load_snapshot,
+            save_snapshot,
+            visited_bloom,
         },
     ))
 }
+
+// Parse NodeType:per_second, as passed to --node-rate-limit
+fn parse_node_rate_limit(value: &str) -> Result<(NodeType, u32), Error> {
+    let mut parts = value.splitn(2, ':');
+    match (parts.next(), parts.next()) {
+        (Some(node_type), Some(per_second)) => {
+            let node_type = NodeType::from_str(node_type)?;
+            let per_second = per_second.parse::<u32>()?;
+            if per_second == 0 {
+                return Err(format_err!(
+                    "--{} must be at least 1 per second, got {}",
+                    NODE_RATE_LIMIT_ARG,
+                    value
+                ));
+            }
+            Ok((node_type, per_second))
+        }
+        _ => Err(format_err!(
+            "--{} expects NodeType:per_second, got {}",
+            NODE_RATE_LIMIT_ARG,
+            value
+        )),
+    }
+}
@@ -34,7 +34,7 @@ use futures::{
 };
 use mononoke_types::BlobstoreBytes;
 use samplingblob::SamplingHandler;
-use slog::{info, Logger};
+use slog::{debug, info, Logger};
 use std::{
     cmp::min,
     collections::HashMap,
@@ -282,17 +282,13 @@ pub async fn compression_benefit<'a>(
     let progress_interval_secs = args::get_u64_opt(&sub_m, PROGRESS_INTERVAL_ARG);
     let progress_sample_rate = args::get_u64_opt(&sub_m, PROGRESS_SAMPLE_RATE_ARG);
 
-    cloned!(
-        walk_params.include_node_types,
-        walk_params.include_edge_types
-    );
     let mut sampling_node_types = parse_node_types(
         sub_m,
         INCLUDE_SAMPLE_NODE_TYPE_ARG,
         EXCLUDE_SAMPLE_NODE_TYPE_ARG,
         DEFAULT_INCLUDE_NODE_TYPES,
     )?;
-    sampling_node_types.retain(|i| include_node_types.contains(i));
+    sampling_node_types.retain(|i| walk_params.include_node_types.contains(i));
 
     let sizing_progress_state =
         ProgressStateMutex::new(ProgressStateCountByType::<SizingStats, SizingStats>::new(
@@ -342,20 +338,22 @@ pub async fn compression_benefit<'a>(
     };
 
     let walk_state = Arc::new(SamplingWalkVisitor::new(
-        include_node_types,
-        include_edge_types,
+        walk_params.walk_state_options(),
+        walk_params.visited(),
         sampling_node_types,
         sizing_sampler,
         sample_rate,
     ));
     walk_exact_tail::<_, _, _, _, _, PathTrackingRoute>(
         fb,
-        logger,
+        logger.clone(),
         datasources,
         walk_params,
-        walk_state,
+        walk_state.clone(),
         make_sink,
         true,
     )
-    .await
+    .await?;
+    debug!(logger, "Interned {} ids", walk_state.interned_ids());
+    Ok(())
 }
@@ -0,0 +1,241 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+//! Compact binary encoding of a walk's visited nodes, see `WalkStateCHashMap::save_snapshot`.
+//! Integers are little endian, ids are written as their raw hash bytes.
+
+use anyhow::{format_err, Error};
+use chashmap::CHashMap;
+use mercurial_types::{HgChangesetId, HgFileNodeId, HgManifestId, HgNodeHash};
+use mononoke_types::{ChangesetId, ContentId, FsnodeId, MPathHash};
+use std::{
+    cell::RefCell,
+    hash::Hash,
+    io::{Read, Write},
+};
+
+pub const SNAPSHOT_MAGIC: &[u8; 8] = b"WALKSNP1";
+
+/// Ids that can be written to a snapshot.
+pub trait SnapshotId: Sized {
+    fn write_id(&self, w: &mut dyn Write) -> Result<(), Error>;
+    fn read_id(r: &mut dyn Read) -> Result<Self, Error>;
+}
+
+// Lengths are read from the snapshot, so the buffer only grows as bytes are read
+// rather than trusting them for the allocation.
+fn read_vec(r: &mut dyn Read, len: usize) -> Result<Vec<u8>, Error> {
+    let mut buf = vec![];
+    Read::take(&mut *r, len as u64).read_to_end(&mut buf)?;
+    if buf.len() != len {
+        return Err(format_err!("Walk snapshot is truncated"));
+    }
+    Ok(buf)
+}
+
+pub fn write_u64(w: &mut dyn Write, v: u64) -> Result<(), Error> {
+    w.write_all(&v.to_le_bytes())?;
+    Ok(())
+}
+
+pub fn read_u64(r: &mut dyn Read) -> Result<u64, Error> {
+    let mut buf = [0; 8];
+    r.read_exact(&mut buf)?;
+    Ok(u64::from_le_bytes(buf))
+}
+
+pub fn write_str(w: &mut dyn Write, s: &str) -> Result<(), Error> {
+    write_u64(w, s.len() as u64)?;
+    w.write_all(s.as_bytes())?;
+    Ok(())
+}
+
+pub fn read_str(r: &mut dyn Read) -> Result<String, Error> {
+    let len = read_u64(r)? as usize;
+    Ok(String::from_utf8(read_vec(r, len)?)?)
+}
+
+pub fn write_magic(w: &mut dyn Write) -> Result<(), Error> {
+    w.write_all(SNAPSHOT_MAGIC)?;
+    Ok(())
+}
+
+pub fn read_magic(r: &mut dyn Read) -> Result<(), Error> {
+    if read_vec(r, SNAPSHOT_MAGIC.len())? != SNAPSHOT_MAGIC {
+        return Err(format_err!(
+            "Not a walk snapshot, or from an unsupported version"
+        ));
+    }
+    Ok(())
+}
+
+/// Write the count then each entry.
+pub fn write_entries<T>(
+    w: &mut dyn Write,
+    entries: Vec<T>,
+    mut write_entry: impl FnMut(&mut dyn Write, T) -> Result<(), Error>,
+) -> Result<(), Error> {
+    write_u64(w, entries.len() as u64)?;
+    for entry in entries {
+        write_entry(w, entry)?;
+    }
+    Ok(())
+}
+
+/// Read entries written by write_entries.
+pub fn read_entries(
+    r: &mut dyn Read,
+    mut read_entry: impl FnMut(&mut dyn Read) -> Result<(), Error>,
+) -> Result<(), Error> {
+    for _ in 0..read_u64(r)? {
+        read_entry(r)?;
+    }
+    Ok(())
+}
+
+/// Write the count then each entry of map, as write_entries does. CHashMap
+/// can only be iterated by value, so entries are visited in place with retain.
+/// Fails if the map changes while being written.
+pub fn write_map_entries<K, V>(
+    w: &mut dyn Write,
+    map: &CHashMap<K, V>,
+    write_entry: impl Fn(&mut dyn Write, &K, &V) -> Result<(), Error>,
+) -> Result<(), Error>
+where
+    K: Eq + Hash,
+{
+    let len = map.len();
+    write_u64(w, len as u64)?;
+    let state = RefCell::new((w, 0, Ok(())));
+    map.retain(|k, v| {
+        let (w, written, result) = &mut *state.borrow_mut();
+        if result.is_ok() {
+            *result = write_entry(&mut **w, k, v);
+            *written += 1;
+        }
+        true
+    });
+    let (_, written, result) = state.into_inner();
+    result?;
+    if written != len {
+        return Err(format_err!(
+            "Walk state changed while saving snapshot, {} entries became {}",
+            len,
+            written
+        ));
+    }
+    Ok(())
+}
+
+pub fn write_set<K>(w: &mut dyn Write, set: &CHashMap<K, ()>) -> Result<(), Error>
+where
+    K: SnapshotId + Eq + Hash,
+{
+    write_map_entries(w, set, |w, k, ()| k.write_id(w))
+}
+
+/// Read a set written by write_set into set, converting each saved id with to_key.
+pub fn read_set<S, K>(
+    r: &mut dyn Read,
+    set: &CHashMap<K, ()>,
+    mut to_key: impl FnMut(S) -> Result<K, Error>,
+) -> Result<(), Error>
+where
+    S: SnapshotId,
+    K: Eq + Hash,
+{
+    read_entries(r, |r| {
+        set.insert(to_key(S::read_id(r)?)?, ());
+        Ok(())
+    })
+}
+
+impl SnapshotId for u32 {
+    fn write_id(&self, w: &mut dyn Write) -> Result<(), Error> {
+        w.write_all(&self.to_le_bytes())?;
+        Ok(())
+    }
+
+    fn read_id(r: &mut dyn Read) -> Result<Self, Error> {
+        let mut buf = [0; 4];
+        r.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+}
+
+// Hashes written as their fixed length bytes
+macro_rules! impl_snapshot_id {
+    ($ty:ty, $len:expr, $as_bytes:expr, $from_bytes:expr) => {
+        impl SnapshotId for $ty {
+            fn write_id(&self, w: &mut dyn Write) -> Result<(), Error> {
+                w.write_all($as_bytes(self))?;
+                Ok(())
+            }
+
+            fn read_id(r: &mut dyn Read) -> Result<Self, Error> {
+                $from_bytes(&read_vec(r, $len)?[..])
+            }
+        }
+    };
+}
+
+impl_snapshot_id!(
+    ChangesetId,
+    32,
+    AsRef::<[u8]>::as_ref,
+    ChangesetId::from_bytes
+);
+impl_snapshot_id!(ContentId, 32, AsRef::<[u8]>::as_ref, ContentId::from_bytes);
+impl_snapshot_id!(FsnodeId, 32, AsRef::<[u8]>::as_ref, FsnodeId::from_bytes);
+impl_snapshot_id!(MPathHash, 32, AsRef::<[u8]>::as_ref, MPathHash::from_bytes);
+impl_snapshot_id!(
+    HgChangesetId,
+    20,
+    HgChangesetId::as_bytes,
+    HgChangesetId::from_bytes
+);
+impl_snapshot_id!(
+    HgFileNodeId,
+    20,
+    HgFileNodeId::as_bytes,
+    HgFileNodeId::from_bytes
+);
+
+impl SnapshotId for HgManifestId {
+    fn write_id(&self, w: &mut dyn Write) -> Result<(), Error> {
+        w.write_all(self.into_nodehash().as_bytes())?;
+        Ok(())
+    }
+
+    fn read_id(r: &mut dyn Read) -> Result<Self, Error> {
+        HgNodeHash::from_bytes(&read_vec(r, 20)?).map(HgManifestId::new)
+    }
+}
+
+// Ids keyed by path, as in the visited sets for manifests, filenodes and fsnodes
+impl<K: SnapshotId> SnapshotId for (Option<MPathHash>, K) {
+    fn write_id(&self, w: &mut dyn Write) -> Result<(), Error> {
+        let (path, id) = self;
+        match path {
+            Some(path) => {
+                w.write_all(&[1])?;
+                path.write_id(w)?;
+            }
+            None => w.write_all(&[0])?,
+        }
+        id.write_id(w)
+    }
+
+    fn read_id(r: &mut dyn Read) -> Result<Self, Error> {
+        let path = match read_vec(r, 1)?[0] {
+            0 => None,
+            1 => Some(MPathHash::read_id(r)?),
+            v => return Err(format_err!("Bad path marker {} in walk snapshot", v)),
+        };
+        Ok((path, K::read_id(r)?))
+    }
+}
@@ -5,14 +5,35 @@
  * GNU General Public License version 2.
  */
 
+use crate::bloom::BloomFilter;
 use crate::graph::{EdgeType, Node, NodeData, NodeType, WrappedPath};
+use crate::interner::Interner;
+use crate::rate_limit::{Reservation, TokenBucket};
+use crate::snapshot::{
+    read_entries, read_magic, read_set, read_str, read_u64, write_entries, write_magic,
+    write_map_entries, write_set, write_str, write_u64, SnapshotId,
+};
 use crate::walk::{expand_checked_nodes, OutgoingEdge, WalkVisitor};
+use anyhow::{format_err, Error};
 use chashmap::CHashMap;
 use context::CoreContext;
 use mercurial_types::{HgChangesetId, HgFileNodeId, HgManifestId};
-use mononoke_types::{ChangesetId, ContentId, FsnodeId, MPathHash};
+use mononoke_types::{hash::Context, ChangesetId, ContentId, FsnodeId, MPathHash};
 use phases::Phase;
-use std::{cmp, collections::HashSet, hash::Hash, ops::Add};
+use slog::warn;
+use std::{
+    cmp,
+    collections::{HashMap, HashSet},
+    fmt::Display,
+    hash::Hash,
+    io::{Read, Write},
+    ops::Add,
+    str::FromStr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
 
 #[derive(Clone, Copy, Default, Debug, PartialEq)]
 pub struct StepStats {
@@ -21,6 +42,7 @@ pub struct StepStats {
     pub num_direct_new: usize,
     pub num_expanded_new: usize,
     pub visited_of_type: usize,
+    pub visited_false_positive_rate: f64,
 }
 
 impl Add<StepStats> for StepStats {
@@ -32,50 +54,246 @@ impl Add<StepStats> for StepStats {
             num_direct_new: self.num_direct_new + other.num_direct_new,
             num_expanded_new: self.num_expanded_new + other.num_expanded_new,
             visited_of_type: cmp::max(self.visited_of_type, other.visited_of_type),
+            visited_false_positive_rate: self
+                .visited_false_positive_rate
+                .max(other.visited_false_positive_rate),
+        }
+    }
+}
+
+/// Tracks which nodes a walk has already visited, so they are not stepped to again.
+pub trait VisitedSet {
+    /// If the set did not have this node present, true is returned.
+    fn needs_visit(&self, node: &Node) -> bool;
+
+    /// Record visits that can only be pruned once the node data is resolved.
+    fn record_resolved_visit(&self, node: &Node, node_data: Option<&NodeData>);
+
+    /// Estimated chance that needs_visit wrongly returns false for a new node.
+    fn false_positive_rate(&self) -> f64;
+
+    /// Number of ids interned for the visited set, useful to log memory use.
+    fn interned_ids(&self) -> usize;
+
+    /// See WalkState::save_snapshot.
+    fn save_snapshot(&self, w: &mut dyn Write) -> Result<(), Error>;
+
+    /// See WalkState::load_snapshot.
+    fn load_snapshot(&self, r: &mut dyn Read) -> Result<(), Error>;
+}
+
+/// Visitors built on a WalkStateAny, so a tailing walk can reset its error
+/// count between iterations, and save and load its snapshots, see
+/// WalkState::save_snapshot.
+pub trait SnapshotWalkState {
+    fn snapshot_state(&self) -> &WalkStateAny;
+}
+
+impl<V: SnapshotWalkState> SnapshotWalkState for Arc<V> {
+    fn snapshot_state(&self) -> &WalkStateAny {
+        self.as_ref().snapshot_state()
+    }
+}
+
+/// What to walk and how, see WalkStateCHashMap::new.
+#[derive(Clone, Debug)]
+pub struct WalkStateOptions {
+    pub include_node_types: HashSet<NodeType>,
+    pub include_edge_types: HashSet<EdgeType>,
+    // Node types to step to first, highest priority first
+    pub node_type_priority: Vec<NodeType>,
+    // Once this many error nodes have been seen, stop expanding the walk
+    pub max_errors: Option<usize>,
+    // Changesets not to step to, so their ancestors are only reached by other routes
+    pub boundary_changesets: HashSet<ChangesetId>,
+    // Checksum the file contents walked, see WalkState::content_checksum
+    pub content_checksum: bool,
+    // Fraction of file contents to visit, see in_file_content_sample
+    pub file_content_sample_rate: f64,
+    // Node types that are only stepped to this many times per second
+    pub node_rate_limits: HashMap<NodeType, u32>,
+}
+
+impl Default for WalkStateOptions {
+    fn default() -> Self {
+        Self {
+            include_node_types: HashSet::new(),
+            include_edge_types: HashSet::new(),
+            node_type_priority: vec![],
+            max_errors: None,
+            boundary_changesets: HashSet::new(),
+            content_checksum: false,
+            file_content_sample_rate: 1.0,
+            node_rate_limits: HashMap::new(),
         }
     }
 }
 
 #[derive(Debug)]
-pub struct WalkStateCHashMap {
-    // TODO implement ID interning to u32 or u64 for types in more than one map
-    // e.g. ChangesetId, HgChangesetId, HgFileNodeId
+pub struct WalkState<V> {
     include_node_types: HashSet<NodeType>,
     include_edge_types: HashSet<EdgeType>,
-    visited_bcs: CHashMap<ChangesetId, ()>,
-    visited_bcs_mapping: CHashMap<ChangesetId, ()>,
-    visited_bcs_phase: CHashMap<ChangesetId, ()>,
+    node_type_priority: HashMap<NodeType, usize>,
+    // Once this many error nodes have been seen, stop expanding the walk
+    max_errors: Option<usize>,
+    // Changesets not to step to, so their ancestors are only reached by other routes
+    boundary_changesets: HashSet<ChangesetId>,
+    error_count: AtomicUsize,
+    content_checksum: Option<ContentChecksum>,
+    // Fraction of file contents to visit, see in_file_content_sample
+    file_content_sample_rate: f64,
+    // Node types that are only stepped to this many times per second
+    node_rate_limits: HashMap<NodeType, TokenBucket>,
+    visited: V,
+    visit_count: CHashMap<NodeType, usize>,
+}
+
+/// Walk state that remembers every visited node exactly.
+pub type WalkStateCHashMap = WalkState<VisitedCHashMap>;
+
+/// Walk state whose visited set is picked when the walk is set up, see Visited.
+pub type WalkStateAny = WalkState<Visited>;
+
+#[derive(Debug)]
+pub struct VisitedCHashMap {
+    // Ids that are in more than one map are interned, and the maps hold the u32
+    bcs_ids: Interner<ChangesetId>,
+    hg_cs_ids: Interner<HgChangesetId>,
+    hg_filenode_ids: Interner<HgFileNodeId>,
+    visited_bcs: CHashMap<u32, ()>,
+    visited_bcs_mapping: CHashMap<u32, ()>,
+    visited_bcs_phase: CHashMap<u32, ()>,
     visited_file: CHashMap<ContentId, ()>,
-    visited_hg_cs: CHashMap<HgChangesetId, ()>,
-    visited_hg_cs_mapping: CHashMap<HgChangesetId, ()>,
-    visited_hg_file_envelope: CHashMap<HgFileNodeId, ()>,
-    visited_hg_filenode: CHashMap<(Option<MPathHash>, HgFileNodeId), ()>,
+    visited_hg_cs: CHashMap<u32, ()>,
+    visited_hg_cs_mapping: CHashMap<u32, ()>,
+    visited_hg_file_envelope: CHashMap<u32, ()>,
+    visited_hg_filenode: CHashMap<(Option<MPathHash>, u32), ()>,
     visited_hg_manifest: CHashMap<(Option<MPathHash>, HgManifestId), ()>,
     visited_fsnode: CHashMap<(Option<MPathHash>, FsnodeId), ()>,
-    visit_count: CHashMap<NodeType, usize>,
+}
+
+/// Keeps visited nodes in a bloom filter, which takes far less memory than
+/// VisitedCHashMap on large repos. The price is that some nodes are wrongly
+/// reported as already visited, so are never stepped to.
+#[derive(Debug)]
+pub struct VisitedBloom {
+    visited: BloomFilter,
+}
+
+/// Either visited set, so the command line can choose between them.
+#[derive(Debug)]
+pub enum Visited {
+    Exact(VisitedCHashMap),
+    Bloom(VisitedBloom),
+}
+
+/// Order-independent checksum of the nodes visited by a walk.
+///
+/// Each distinct node is hashed and folded in with XOR, so neither the order
+/// nodes are visited in nor repeated visits affect the result. Node ids are
+/// content hashes, so this also covers the content of the visited nodes.
+struct ContentChecksum {
+    seen: CHashMap<[u8; 32], ()>,
+    checksum: Mutex<[u8; 32]>,
+}
+
+impl ContentChecksum {
+    fn new() -> Self {
+        Self {
+            seen: CHashMap::new(),
+            checksum: Mutex::new([0; 32]),
+        }
+    }
+
+    fn record(&self, node: &Node) {
+        let mut context = Context::new(b"walker_content_checksum");
+        context.update(checksum_key(node));
+        let mut hash = [0; 32];
+        hash.copy_from_slice(context.finish().as_ref());
+        if self.seen.insert(hash, ()).is_none() {
+            let mut checksum = self.checksum.lock().expect("lock poisoned");
+            for (c, h) in checksum.iter_mut().zip(hash.iter()) {
+                *c ^= h;
+            }
+        }
+    }
+
+    fn get(&self) -> [u8; 32] {
+        *self.checksum.lock().expect("lock poisoned")
+    }
+}
+
+/// Stable encoding of a node for ContentChecksum, so the checksum can be compared
+/// across builds: the node type name, then the raw bytes of its key.
+fn checksum_key(node: &Node) -> Vec<u8> {
+    let mut key = node.get_type().to_str().as_bytes().to_vec();
+    key.push(0);
+    // Paths are hashed, so every key has at most one variable length part, at the end
+    let push_path = |key: &mut Vec<u8>, path: &WrappedPath| match path.as_ref() {
+        Some(path) => {
+            key.push(1);
+            key.extend_from_slice(path.get_path_hash().as_ref());
+        }
+        None => key.push(0),
+    };
+    match node {
+        Node::Root | Node::PublishedBookmarks => {}
+        Node::Bookmark(name) => key.extend_from_slice(name.as_str().as_bytes()),
+        Node::BonsaiChangeset(id)
+        | Node::BonsaiHgMapping(id)
+        | Node::BonsaiPhaseMapping(id)
+        | Node::BonsaiFsnodeMapping(id) => key.extend_from_slice(id.as_ref()),
+        Node::HgBonsaiMapping(id) | Node::HgChangeset(id) => key.extend_from_slice(id.as_bytes()),
+        Node::HgManifest((path, id)) => {
+            push_path(&mut key, path);
+            key.extend_from_slice(id.into_nodehash().as_bytes());
+        }
+        Node::HgFileEnvelope(id) => key.extend_from_slice(id.as_bytes()),
+        Node::HgFileNode((path, id)) => {
+            push_path(&mut key, path);
+            key.extend_from_slice(id.as_bytes());
+        }
+        Node::FileContent(id) | Node::FileContentMetadata(id) => key.extend_from_slice(id.as_ref()),
+        Node::AliasContentMapping(alias) => key.extend_from_slice(alias.blobstore_key().as_bytes()),
+        Node::Fsnode((path, id)) => {
+            push_path(&mut key, path);
+            key.extend_from_slice(id.as_ref());
+        }
+    }
+    key
 }
 
 /// If the state did not have this value present, true is returned.
 fn record_with_path<K>(
     visited_with_path: &CHashMap<(Option<MPathHash>, K), ()>,
-    k: &(WrappedPath, K),
+    path: &WrappedPath,
+    id: K,
 ) -> bool
 where
     K: Eq + Hash + Copy,
 {
-    let (path, id) = k;
     let mpathhash_opt = path.as_ref().map(|m| m.get_path_hash());
-    !visited_with_path.insert((mpathhash_opt, *id), ()).is_some()
+    !visited_with_path.insert((mpathhash_opt, id), ()).is_some()
 }
 
-impl WalkStateCHashMap {
-    pub fn new(
-        include_node_types: HashSet<NodeType>,
-        include_edge_types: HashSet<EdgeType>,
-    ) -> Self {
+/// Whether a resolved visit should be recorded, see VisitedSet::record_resolved_visit.
+fn is_retained_resolved_visit(node: &Node, node_data: Option<&NodeData>) -> bool {
+    match (node, node_data) {
+        // Only retain visit if already public, otherwise it could mutate between walks.
+        (Node::BonsaiPhaseMapping(_), Some(NodeData::BonsaiPhaseMapping(Some(Phase::Public)))) => {
+            true
+        }
+        (Node::BonsaiHgMapping(_), Some(NodeData::BonsaiHgMapping(Some(_)))) => true,
+        _ => false,
+    }
+}
+
+impl VisitedCHashMap {
+    pub fn new() -> Self {
         Self {
-            include_node_types,
-            include_edge_types,
+            bcs_ids: Interner::new(),
+            hg_cs_ids: Interner::new(),
+            hg_filenode_ids: Interner::new(),
             visited_bcs: CHashMap::new(),
             visited_bcs_mapping: CHashMap::new(),
             visited_bcs_phase: CHashMap::new(),
@@ -86,57 +304,438 @@ impl WalkStateCHashMap {
             visited_hg_filenode: CHashMap::new(),
             visited_hg_manifest: CHashMap::new(),
             visited_fsnode: CHashMap::new(),
-            visit_count: CHashMap::new(),
         }
     }
+}
 
-    /// If the set did not have this value present, true is returned.
-    fn needs_visit(&self, outgoing: &OutgoingEdge) -> bool {
-        let target_node: &Node = &outgoing.target;
-        let k = target_node.get_type();
-        &self.visit_count.upsert(k, || 1, |old| *old += 1);
+fn write_interner<K>(w: &mut dyn Write, interner: &Interner<K>) -> Result<(), Error>
+where
+    K: SnapshotId + Eq + Hash + Copy,
+{
+    write_map_entries(w, interner.ids(), |w, k, id| {
+        k.write_id(w)?;
+        id.write_id(w)
+    })
+}
+
+/// Read the entries written by write_interner, keyed by their saved u32.
+fn read_interner<K: SnapshotId>(r: &mut dyn Read) -> Result<HashMap<u32, K>, Error> {
+    let mut saved = HashMap::new();
+    read_entries(r, |r| {
+        let k = K::read_id(r)?;
+        saved.insert(u32::read_id(r)?, k);
+        Ok(())
+    })?;
+    Ok(saved)
+}
+
+fn reintern<K>(saved: &HashMap<u32, K>, interner: &Interner<K>, id: u32) -> Result<u32, Error>
+where
+    K: Eq + Hash + Copy,
+{
+    saved
+        .get(&id)
+        .map(|k| interner.intern(*k))
+        .ok_or_else(|| format_err!("Walk snapshot refers to unknown interned id {}", id))
+}
+
+fn write_types<T: Display>(w: &mut dyn Write, types: &HashSet<T>) -> Result<(), Error> {
+    write_entries(w, sorted_names(types), |w, name| write_str(w, &name))
+}
+
+/// Fail unless the snapshot was taken walking the same types, as any types
+/// newly included now would be silently skipped below already visited nodes.
+fn check_types<T: Display>(r: &mut dyn Read, kind: &str, types: &HashSet<T>) -> Result<(), Error> {
+    let mut saved = vec![];
+    read_entries(r, |r| {
+        saved.push(read_str(r)?);
+        Ok(())
+    })?;
+    let expected = sorted_names(types);
+    if saved != expected {
+        return Err(format_err!(
+            "Walk snapshot was taken walking {} types {:?}, but this walk has {:?}",
+            kind,
+            saved,
+            expected
+        ));
+    }
+    Ok(())
+}
+
+fn sorted_names<T: Display>(types: &HashSet<T>) -> Vec<String> {
+    let mut names: Vec<_> = types.iter().map(|t| t.to_string()).collect();
+    names.sort();
+    names
+}
 
-        match &target_node {
-            Node::BonsaiChangeset(bcs_id) => self.visited_bcs.insert(*bcs_id, ()).is_none(),
+impl VisitedSet for VisitedCHashMap {
+    fn needs_visit(&self, node: &Node) -> bool {
+        match node {
+            Node::BonsaiChangeset(bcs_id) => {
+                let id = self.bcs_ids.intern(*bcs_id);
+                self.visited_bcs.insert(id, ()).is_none()
+            }
             // TODO - measure if worth tracking - the mapping is cachelib enabled.
             Node::BonsaiHgMapping(bcs_id) => {
                 // Does not insert, see record_resolved_visit
-                !self.visited_bcs_mapping.contains_key(bcs_id)
+                !self
+                    .visited_bcs_mapping
+                    .contains_key(&self.bcs_ids.intern(*bcs_id))
             }
             Node::BonsaiPhaseMapping(bcs_id) => {
                 // Does not insert, as can only prune visits once data resolved, see record_resolved_visit
-                !self.visited_bcs_phase.contains_key(bcs_id)
+                !self
+                    .visited_bcs_phase
+                    .contains_key(&self.bcs_ids.intern(*bcs_id))
             }
             Node::HgBonsaiMapping(hg_cs_id) => {
-                self.visited_hg_cs_mapping.insert(*hg_cs_id, ()).is_none()
+                let id = self.hg_cs_ids.intern(*hg_cs_id);
+                self.visited_hg_cs_mapping.insert(id, ()).is_none()
+            }
+            Node::HgChangeset(hg_cs_id) => {
+                let id = self.hg_cs_ids.intern(*hg_cs_id);
+                self.visited_hg_cs.insert(id, ()).is_none()
+            }
+            Node::HgManifest((path, id)) => record_with_path(&self.visited_hg_manifest, path, *id),
+            Node::HgFileNode((path, id)) => {
+                let id = self.hg_filenode_ids.intern(*id);
+                record_with_path(&self.visited_hg_filenode, path, id)
+            }
+            Node::HgFileEnvelope(id) => {
+                let id = self.hg_filenode_ids.intern(*id);
+                self.visited_hg_file_envelope.insert(id, ()).is_none()
             }
-            Node::HgChangeset(hg_cs_id) => self.visited_hg_cs.insert(*hg_cs_id, ()).is_none(),
-            Node::HgManifest(k) => record_with_path(&self.visited_hg_manifest, k),
-            Node::HgFileNode(k) => record_with_path(&self.visited_hg_filenode, k),
-            Node::HgFileEnvelope(id) => self.visited_hg_file_envelope.insert(*id, ()).is_none(),
             Node::FileContent(content_id) => self.visited_file.insert(*content_id, ()).is_none(),
-            Node::Fsnode(k) => record_with_path(&self.visited_fsnode, k),
+            Node::Fsnode((path, id)) => record_with_path(&self.visited_fsnode, path, *id),
             _ => true,
         }
     }
 
-    fn record_resolved_visit(&self, resolved: &OutgoingEdge, node_data: Option<&NodeData>) {
-        match (&resolved.target, node_data) {
-            (
-                Node::BonsaiPhaseMapping(bcs_id),
-                Some(NodeData::BonsaiPhaseMapping(Some(Phase::Public))),
-            ) => {
-                // Only retain visit if already public, otherwise it could mutate between walks.
-                self.visited_bcs_phase.insert(*bcs_id, ());
+    fn record_resolved_visit(&self, node: &Node, node_data: Option<&NodeData>) {
+        if !is_retained_resolved_visit(node, node_data) {
+            return;
+        }
+        match node {
+            Node::BonsaiPhaseMapping(bcs_id) => {
+                self.visited_bcs_phase
+                    .insert(self.bcs_ids.intern(*bcs_id), ());
             }
-            (Node::BonsaiHgMapping(bcs_id), Some(NodeData::BonsaiHgMapping(Some(_)))) => {
-                self.visited_bcs_mapping.insert(*bcs_id, ());
+            Node::BonsaiHgMapping(bcs_id) => {
+                self.visited_bcs_mapping
+                    .insert(self.bcs_ids.intern(*bcs_id), ());
             }
             _ => (),
         }
     }
 
+    fn false_positive_rate(&self) -> f64 {
+        0.0
+    }
+
+    fn interned_ids(&self) -> usize {
+        self.bcs_ids.len() + self.hg_cs_ids.len() + self.hg_filenode_ids.len()
+    }
+
+    fn save_snapshot(&self, w: &mut dyn Write) -> Result<(), Error> {
+        write_interner(w, &self.bcs_ids)?;
+        write_interner(w, &self.hg_cs_ids)?;
+        write_interner(w, &self.hg_filenode_ids)?;
+        write_set(w, &self.visited_bcs)?;
+        write_set(w, &self.visited_bcs_mapping)?;
+        write_set(w, &self.visited_bcs_phase)?;
+        write_set(w, &self.visited_file)?;
+        write_set(w, &self.visited_hg_cs)?;
+        write_set(w, &self.visited_hg_cs_mapping)?;
+        write_set(w, &self.visited_hg_file_envelope)?;
+        write_set(w, &self.visited_hg_filenode)?;
+        write_set(w, &self.visited_hg_manifest)?;
+        write_set(w, &self.visited_fsnode)
+    }
+
+    // Interned ids are looked up by the id they had when saved, and interned afresh
+    fn load_snapshot(&self, r: &mut dyn Read) -> Result<(), Error> {
+        let bcs_ids = read_interner(r)?;
+        let hg_cs_ids = read_interner(r)?;
+        let hg_filenode_ids = read_interner(r)?;
+        let bcs = |id| reintern(&bcs_ids, &self.bcs_ids, id);
+        let hg_cs = |id| reintern(&hg_cs_ids, &self.hg_cs_ids, id);
+        let hg_filenode = |id| reintern(&hg_filenode_ids, &self.hg_filenode_ids, id);
+        read_set(r, &self.visited_bcs, bcs)?;
+        read_set(r, &self.visited_bcs_mapping, bcs)?;
+        read_set(r, &self.visited_bcs_phase, bcs)?;
+        read_set(r, &self.visited_file, Ok)?;
+        read_set(r, &self.visited_hg_cs, hg_cs)?;
+        read_set(r, &self.visited_hg_cs_mapping, hg_cs)?;
+        read_set(r, &self.visited_hg_file_envelope, hg_filenode)?;
+        read_set(
+            r,
+            &self.visited_hg_filenode,
+            |(path, id): (Option<MPathHash>, u32)| Ok((path, hg_filenode(id)?)),
+        )?;
+        read_set(r, &self.visited_hg_manifest, Ok)?;
+        read_set(r, &self.visited_fsnode, Ok)
+    }
+}
+
+impl VisitedBloom {
+    /// Size the filter so the false positive rate is reached after expected_nodes visits.
+    pub fn new(expected_nodes: usize, false_positive_rate: f64) -> Self {
+        Self {
+            visited: BloomFilter::new(expected_nodes, false_positive_rate),
+        }
+    }
+}
+
+impl VisitedSet for VisitedBloom {
+    // Tracks the same node types as VisitedCHashMap. Nodes are hashed whole,
+    // with the enum variant keeping ids of different node types apart.
+    fn needs_visit(&self, node: &Node) -> bool {
+        match node {
+            Node::BonsaiHgMapping(_) | Node::BonsaiPhaseMapping(_) => {
+                // Does not insert, see record_resolved_visit
+                !self.visited.contains(node)
+            }
+            Node::BonsaiChangeset(_)
+            | Node::HgBonsaiMapping(_)
+            | Node::HgChangeset(_)
+            | Node::HgManifest(_)
+            | Node::HgFileNode(_)
+            | Node::HgFileEnvelope(_)
+            | Node::FileContent(_)
+            | Node::Fsnode(_) => self.visited.insert(node),
+            _ => true,
+        }
+    }
+
+    fn record_resolved_visit(&self, node: &Node, node_data: Option<&NodeData>) {
+        if is_retained_resolved_visit(node, node_data) {
+            self.visited.insert(node);
+        }
+    }
+
+    fn false_positive_rate(&self) -> f64 {
+        self.visited.false_positive_rate()
+    }
+
+    fn interned_ids(&self) -> usize {
+        0
+    }
+
+    // The filter can't list what was inserted, so there is nothing to save
+    fn save_snapshot(&self, _w: &mut dyn Write) -> Result<(), Error> {
+        Err(format_err!(
+            "Walk snapshots need visited nodes tracked exactly, not in a bloom filter"
+        ))
+    }
+
+    fn load_snapshot(&self, _r: &mut dyn Read) -> Result<(), Error> {
+        Err(format_err!(
+            "Walk snapshots need visited nodes tracked exactly, not in a bloom filter"
+        ))
+    }
+}
+
+impl VisitedSet for Visited {
+    fn needs_visit(&self, node: &Node) -> bool {
+        match self {
+            Visited::Exact(visited) => visited.needs_visit(node),
+            Visited::Bloom(visited) => visited.needs_visit(node),
+        }
+    }
+
+    fn record_resolved_visit(&self, node: &Node, node_data: Option<&NodeData>) {
+        match self {
+            Visited::Exact(visited) => visited.record_resolved_visit(node, node_data),
+            Visited::Bloom(visited) => visited.record_resolved_visit(node, node_data),
+        }
+    }
+
+    fn false_positive_rate(&self) -> f64 {
+        match self {
+            Visited::Exact(visited) => visited.false_positive_rate(),
+            Visited::Bloom(visited) => visited.false_positive_rate(),
+        }
+    }
+
+    fn interned_ids(&self) -> usize {
+        match self {
+            Visited::Exact(visited) => visited.interned_ids(),
+            Visited::Bloom(visited) => visited.interned_ids(),
+        }
+    }
+
+    fn save_snapshot(&self, w: &mut dyn Write) -> Result<(), Error> {
+        match self {
+            Visited::Exact(visited) => visited.save_snapshot(w),
+            Visited::Bloom(visited) => visited.save_snapshot(w),
+        }
+    }
+
+    fn load_snapshot(&self, r: &mut dyn Read) -> Result<(), Error> {
+        match self {
+            Visited::Exact(visited) => visited.load_snapshot(r),
+            Visited::Bloom(visited) => visited.load_snapshot(r),
+        }
+    }
+}
+
+/// Whether a file content is in the sample to visit. Content ids are hashes,
+/// so their leading bytes are uniformly distributed, and the same contents are
+/// picked on every run with the same rate.
+fn in_file_content_sample(content_id: &ContentId, sample_rate: f64) -> bool {
+    if sample_rate >= 1.0 {
+        return true;
+    }
+    let mut prefix = [0; 8];
+    prefix.copy_from_slice(&content_id.blake2().as_ref()[..8]);
+    (u64::from_be_bytes(prefix) as f64) < sample_rate * (u64::MAX as f64)
+}
+
+/// Order edges so that target types earlier in the priority list come first.
+/// Types not in the priority list go last. The sort is stable, so edges of equal
+/// priority keep the order they were produced in.
+fn sort_by_node_type_priority(
+    node_type_priority: &HashMap<NodeType, usize>,
+    outgoing: &mut Vec<OutgoingEdge>,
+) {
+    if node_type_priority.is_empty() {
+        return;
+    }
+    outgoing.sort_by_key(|e| {
+        node_type_priority
+            .get(&e.target.get_type())
+            .cloned()
+            .unwrap_or(usize::MAX)
+    });
+}
+
+impl WalkStateCHashMap {
+    pub fn new(options: WalkStateOptions) -> Self {
+        Self::with_visited(options, VisitedCHashMap::new())
+    }
+
+    /// A state that only walks the bonsai changeset graph, from the roots
+    /// through the parents of each changeset, skipping all other data.
+    #[allow(dead_code)]
+    pub fn changeset_only() -> Self {
+        Self::new(WalkStateOptions {
+            include_node_types: vec![NodeType::BonsaiChangeset].into_iter().collect(),
+            include_edge_types: vec![
+                EdgeType::BookmarkToBonsaiChangeset,
+                EdgeType::PublishedBookmarksToBonsaiChangeset,
+                EdgeType::BonsaiChangesetToBonsaiParent,
+            ]
+            .into_iter()
+            .collect(),
+            ..WalkStateOptions::default()
+        })
+    }
+}
+
+impl WalkStateAny {
+    pub fn new(options: WalkStateOptions, visited: Visited) -> Self {
+        Self::with_visited(options, visited)
+    }
+}
+
+impl<V: VisitedSet> WalkState<V> {
+    /// See VisitedSet::interned_ids.
+    pub fn interned_ids(&self) -> usize {
+        self.visited.interned_ids()
+    }
+
+    /// Save the visited nodes and visit counts, so a later walk can skip them
+    /// with load_snapshot. Nodes count as visited once they are stepped to, and
+    /// steps not yet taken are not saved, so only save once a walk completes, or
+    /// the children of those steps are not walked after loading.
+    pub fn save_snapshot<W: Write>(&self, mut writer: W) -> Result<(), Error> {
+        let w: &mut dyn Write = &mut writer;
+        write_magic(w)?;
+        write_types(w, &self.include_node_types)?;
+        write_types(w, &self.include_edge_types)?;
+        write_map_entries(w, &self.visit_count, |w, t, count| {
+            write_str(w, t.to_str())?;
+            write_u64(w, *count as u64)
+        })?;
+        self.visited.save_snapshot(w)?;
+        w.flush()?;
+        Ok(())
+    }
+
+    /// Load a snapshot from save_snapshot before starting the walk. Fails if it was
+    /// taken walking different node or edge types.
+    pub fn load_snapshot<R: Read>(&self, mut reader: R) -> Result<(), Error> {
+        let r: &mut dyn Read = &mut reader;
+        read_magic(r)?;
+        check_types(r, "node", &self.include_node_types)?;
+        check_types(r, "edge", &self.include_edge_types)?;
+        read_entries(r, |r| {
+            let t = NodeType::from_str(&read_str(r)?)?;
+            let count = read_u64(r)? as usize;
+            self.visit_count.upsert(t, || count, |old| *old += count);
+            Ok(())
+        })?;
+        self.visited.load_snapshot(r)
+    }
+
+    fn with_visited(options: WalkStateOptions, visited: V) -> Self {
+        let WalkStateOptions {
+            include_node_types,
+            include_edge_types,
+            node_type_priority,
+            max_errors,
+            boundary_changesets,
+            content_checksum,
+            file_content_sample_rate,
+            node_rate_limits,
+        } = options;
+        Self {
+            include_node_types,
+            include_edge_types,
+            node_type_priority: node_type_priority
+                .into_iter()
+                .enumerate()
+                .map(|(i, t)| (t, i))
+                .collect(),
+            max_errors,
+            boundary_changesets,
+            error_count: AtomicUsize::new(0),
+            content_checksum: if content_checksum {
+                Some(ContentChecksum::new())
+            } else {
+                None
+            },
+            file_content_sample_rate,
+            node_rate_limits: node_rate_limits
+                .into_iter()
+                .map(|(t, per_second)| (t, TokenBucket::new(per_second)))
+                .collect(),
+            visited,
+            visit_count: CHashMap::new(),
+        }
+    }
+
+    /// If the set did not have this value present, true is returned.
+    fn needs_visit(&self, outgoing: &OutgoingEdge) -> bool {
+        let target_node: &Node = &outgoing.target;
+        let k = target_node.get_type();
+        &self.visit_count.upsert(k, || 1, |old| *old += 1);
+
+        if let Node::FileContent(content_id) = target_node {
+            if !in_file_content_sample(content_id, self.file_content_sample_rate) {
+                return false;
+            }
+        }
+        self.visited.needs_visit(target_node)
+    }
+
     fn retain_edge(&self, outgoing_edge: &OutgoingEdge) -> bool {
+        if let Node::BonsaiChangeset(bcs_id) = &outgoing_edge.target {
+            if self.boundary_changesets.contains(bcs_id) {
+                return false;
+            }
+        }
         // Retain if a root, or if selected
         outgoing_edge.label.incoming_type().is_none()
             || (self
@@ -148,9 +747,34 @@ impl WalkStateCHashMap {
     fn get_visit_count(&self, t: &NodeType) -> usize {
         self.visit_count.get(t).map(|v| *v).unwrap_or(0)
     }
+
+    /// Record an error node, returning the cumulative number of errors seen.
+    fn record_error(&self) -> usize {
+        self.error_count.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Checksum of all the nodes visited so far, or all zeros if checksumming was not enabled.
+    pub fn content_checksum(&self) -> [u8; 32] {
+        self.content_checksum
+            .as_ref()
+            .map_or([0; 32], ContentChecksum::get)
+    }
+
+    /// True once the walk has seen max_errors errors and should stop.
+    pub fn error_limit_reached(&self) -> bool {
+        match self.max_errors {
+            Some(max_errors) => self.error_count.load(Ordering::Relaxed) >= max_errors,
+            None => false,
+        }
+    }
+
+    /// Forget the errors seen so far, so the next walk gets its own max_errors.
+    pub fn reset_errors(&self) {
+        self.error_count.store(0, Ordering::Relaxed);
+    }
 }
 
-impl WalkVisitor<(Node, Option<NodeData>, Option<StepStats>), ()> for WalkStateCHashMap {
+impl<V: VisitedSet> WalkVisitor<(Node, Option<NodeData>, Option<StepStats>), ()> for WalkState<V> {
     fn start_step(
         &self,
         ctx: CoreContext,
@@ -160,9 +784,16 @@ impl WalkVisitor<(Node, Option<NodeData>, Option<StepStats>), ()> for WalkStateC
         ctx
     }
 
+    // Throttled steps wait for a token, so the walk does not saturate the blobstore
+    fn reserve_step(&self, step: &OutgoingEdge) -> Option<Reservation> {
+        self.node_rate_limits
+            .get(&step.target.get_type())
+            .map(TokenBucket::reserve)
+    }
+
     fn visit(
         &self,
-        _ctx: &CoreContext,
+        ctx: &CoreContext,
         resolved: OutgoingEdge,
         node_data: Option<NodeData>,
         _route: Option<()>,
@@ -183,25 +814,444 @@ impl WalkVisitor<(Node, Option<NodeData>, Option<StepStats>), ()> for WalkStateC
         // Make sure we don't expand to types of node and edge not wanted
         outgoing.retain(|e| self.retain_edge(e));
 
-        self.record_resolved_visit(&resolved, node_data.as_ref());
+        // Higher priority node types are scheduled first
+        sort_by_node_type_priority(&self.node_type_priority, &mut outgoing);
 
-        // Stats
-        let num_expanded_new = outgoing.len();
-        let node = resolved.target;
+        self.visited
+            .record_resolved_visit(&resolved.target, node_data.as_ref());
 
+        let node = resolved.target;
         let (error_count, node_data) = match node_data {
-            Some(NodeData::ErrorAsData(_key)) => (1, None),
+            Some(NodeData::ErrorAsData(_key)) => {
+                let total_errors = self.record_error();
+                if self.max_errors == Some(total_errors) {
+                    warn!(
+                        ctx.logger(),
+                        "Reached {} errors, no longer expanding the walk", total_errors
+                    );
+                }
+                (1, None)
+            }
             Some(d) => (0, Some(d)),
             None => (0, None),
         };
+        if error_count == 0 {
+            if let Some(checksum) = &self.content_checksum {
+                checksum.record(&node);
+            }
+        }
+
+        // Once the error limit is hit, don't schedule any further steps so the walk winds down
+        if self.error_limit_reached() {
+            outgoing.clear();
+        }
+
+        // Stats
+        let num_expanded_new = outgoing.len();
         let stats = StepStats {
             error_count,
             num_direct,
             num_direct_new,
             num_expanded_new,
             visited_of_type: self.get_visit_count(&node.get_type()),
+            visited_false_positive_rate: self.visited.false_positive_rate(),
         };
 
         ((node, node_data, Some(stats)), (), outgoing)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fbinit::FacebookInit;
+    use mononoke_types::MPath;
+    use std::{str::FromStr, time::Duration};
+
+    const SAMPLE_BLAKE2: &str = "b847b8838bfe3ae13ea6f8ce2e341c51193587b8392494f6dbab7224b3b116bf";
+
+    #[test]
+    fn test_sort_by_node_type_priority() {
+        let bcs_id = ChangesetId::from_str(SAMPLE_BLAKE2).unwrap();
+        let content_id = ContentId::from_str(SAMPLE_BLAKE2).unwrap();
+        let file_edge = || {
+            OutgoingEdge::new(
+                EdgeType::BonsaiChangesetToFileContent,
+                Node::FileContent(content_id),
+            )
+        };
+        let parent_edge = || {
+            OutgoingEdge::new(
+                EdgeType::BonsaiChangesetToBonsaiParent,
+                Node::BonsaiChangeset(bcs_id),
+            )
+        };
+        let mut outgoing = vec![file_edge(), parent_edge(), file_edge(), parent_edge()];
+
+        // No priority leaves the produced order alone
+        sort_by_node_type_priority(&HashMap::new(), &mut outgoing);
+        assert_eq!(outgoing[0].target.get_type(), NodeType::FileContent);
+
+        let priority: HashMap<_, _> = vec![(NodeType::BonsaiChangeset, 0)].into_iter().collect();
+        sort_by_node_type_priority(&priority, &mut outgoing);
+        let types: Vec<_> = outgoing.iter().map(|e| e.target.get_type()).collect();
+        assert_eq!(
+            types,
+            vec![
+                NodeType::BonsaiChangeset,
+                NodeType::BonsaiChangeset,
+                NodeType::FileContent,
+                NodeType::FileContent,
+            ]
+        );
+    }
+
+    #[fbinit::test]
+    fn test_max_errors(fb: FacebookInit) {
+        let ctx = CoreContext::test_mock(fb);
+        let state = WalkStateCHashMap::new(WalkStateOptions {
+            include_node_types: vec![NodeType::BonsaiChangeset].into_iter().collect(),
+            include_edge_types: vec![EdgeType::BonsaiChangesetToBonsaiParent]
+                .into_iter()
+                .collect(),
+            max_errors: Some(2),
+            ..WalkStateOptions::default()
+        });
+        let parent_edge = |i: usize| {
+            let bcs_id = ChangesetId::from_str(&format!("{:064x}", i)).unwrap();
+            OutgoingEdge::new(
+                EdgeType::BonsaiChangesetToBonsaiParent,
+                Node::BonsaiChangeset(bcs_id),
+            )
+        };
+        let visit = |i: usize, node_data: Option<NodeData>| {
+            let resolved = parent_edge(i);
+            let ((_node, _data, stats), (), outgoing) =
+                state.visit(&ctx, resolved, node_data, None, vec![parent_edge(i + 1)]);
+            (stats.unwrap().error_count, outgoing.len())
+        };
+        let error = |i: usize| Some(NodeData::ErrorAsData(parent_edge(i).target));
+
+        // Below the limit errors are counted but the walk carries on
+        assert_eq!(visit(0, error(0)), (1, 1));
+        assert!(!state.error_limit_reached());
+
+        // Reaching the limit stops the walk expanding, for errors and successes alike
+        assert_eq!(visit(10, error(10)), (1, 0));
+        assert!(state.error_limit_reached());
+        assert_eq!(visit(20, None), (0, 0));
+        assert_eq!(visit(30, error(30)), (1, 0));
+
+        // Until the errors are reset, as between tail iterations
+        state.reset_errors();
+        assert!(!state.error_limit_reached());
+        assert_eq!(visit(40, error(40)), (1, 1));
+    }
+
+    #[fbinit::test]
+    fn test_boundary_changesets(fb: FacebookInit) {
+        let ctx = CoreContext::test_mock(fb);
+        let bcs_id = |i: usize| ChangesetId::from_str(&format!("{:064x}", i)).unwrap();
+        let parent_edge = |i: usize| {
+            OutgoingEdge::new(
+                EdgeType::BonsaiChangesetToBonsaiParent,
+                Node::BonsaiChangeset(bcs_id(i)),
+            )
+        };
+        let state = WalkStateCHashMap::new(WalkStateOptions {
+            include_node_types: vec![NodeType::BonsaiChangeset].into_iter().collect(),
+            include_edge_types: vec![EdgeType::BonsaiChangesetToBonsaiParent]
+                .into_iter()
+                .collect(),
+            boundary_changesets: vec![bcs_id(1)].into_iter().collect(),
+            ..WalkStateOptions::default()
+        });
+
+        // Walking from a merge of 1 and 2, only 2 is stepped to.
+        let (_, (), outgoing) = state.visit(
+            &ctx,
+            OutgoingEdge::new(
+                EdgeType::RootToBonsaiChangeset,
+                Node::BonsaiChangeset(bcs_id(3)),
+            ),
+            None,
+            None,
+            vec![parent_edge(1), parent_edge(2)],
+        );
+        let targets: Vec<_> = outgoing.into_iter().map(|e| e.target).collect();
+        assert_eq!(targets, vec![Node::BonsaiChangeset(bcs_id(2))]);
+    }
+
+    #[fbinit::test]
+    fn test_changeset_only(fb: FacebookInit) {
+        let ctx = CoreContext::test_mock(fb);
+        let bcs_id = |i: usize| ChangesetId::from_str(&format!("{:064x}", i)).unwrap();
+        let state = WalkStateCHashMap::changeset_only();
+
+        let (_, (), outgoing) = state.visit(
+            &ctx,
+            OutgoingEdge::new(
+                EdgeType::RootToBonsaiChangeset,
+                Node::BonsaiChangeset(bcs_id(2)),
+            ),
+            None,
+            None,
+            vec![
+                OutgoingEdge::new(
+                    EdgeType::BonsaiChangesetToBonsaiParent,
+                    Node::BonsaiChangeset(bcs_id(1)),
+                ),
+                OutgoingEdge::new(
+                    EdgeType::BonsaiChangesetToFileContent,
+                    Node::FileContent(ContentId::from_str(SAMPLE_BLAKE2).unwrap()),
+                ),
+                OutgoingEdge::new(
+                    EdgeType::BonsaiChangesetToBonsaiHgMapping,
+                    Node::BonsaiHgMapping(bcs_id(2)),
+                ),
+                OutgoingEdge::new(
+                    EdgeType::BonsaiChangesetToBonsaiFsnodeMapping,
+                    Node::BonsaiFsnodeMapping(bcs_id(2)),
+                ),
+            ],
+        );
+        let targets: Vec<_> = outgoing.into_iter().map(|e| e.target).collect();
+        assert_eq!(targets, vec![Node::BonsaiChangeset(bcs_id(1))]);
+    }
+
+    #[fbinit::test]
+    fn test_content_checksum(fb: FacebookInit) {
+        let ctx = CoreContext::test_mock(fb);
+        let bcs_id = |i: usize| ChangesetId::from_str(&format!("{:064x}", i)).unwrap();
+        let checksum = |visits: &[usize]| {
+            let state = WalkStateCHashMap::new(WalkStateOptions {
+                include_node_types: vec![NodeType::BonsaiChangeset].into_iter().collect(),
+                include_edge_types: vec![EdgeType::BonsaiChangesetToBonsaiParent]
+                    .into_iter()
+                    .collect(),
+                content_checksum: true,
+                ..WalkStateOptions::default()
+            });
+            for i in visits {
+                let resolved = OutgoingEdge::new(
+                    EdgeType::BonsaiChangesetToBonsaiParent,
+                    Node::BonsaiChangeset(bcs_id(*i)),
+                );
+                state.visit(&ctx, resolved, None, None, vec![]);
+            }
+            state.content_checksum()
+        };
+
+        // Visit order and repeated visits don't matter
+        assert_eq!(checksum(&[1, 2, 3]), checksum(&[3, 1, 2, 1]));
+        assert_ne!(checksum(&[1, 2, 3]), [0; 32]);
+        // Visiting different nodes does
+        assert_ne!(checksum(&[1, 2, 3]), checksum(&[1, 2, 4]));
+        assert_ne!(checksum(&[1, 2, 3]), checksum(&[1, 2]));
+    }
+
+    #[test]
+    fn test_checksum_key() {
+        let bcs_id = ChangesetId::from_str(&format!("{:064x}", 1)).unwrap();
+        let mut expected = b"BonsaiChangeset\0".to_vec();
+        expected.extend_from_slice(bcs_id.as_ref());
+        assert_eq!(checksum_key(&Node::BonsaiChangeset(bcs_id)), expected);
+        // The type keeps nodes with the same id apart
+        assert_ne!(
+            checksum_key(&Node::BonsaiChangeset(bcs_id)),
+            checksum_key(&Node::BonsaiHgMapping(bcs_id))
+        );
+
+        let fsnode_id = FsnodeId::from_str(SAMPLE_BLAKE2).unwrap();
+        let at_root = checksum_key(&Node::Fsnode((WrappedPath::Root, fsnode_id)));
+        let at_path = checksum_key(&Node::Fsnode((
+            WrappedPath::from(Some(MPath::new("a").unwrap())),
+            fsnode_id,
+        )));
+        assert_eq!(at_root.len(), b"Fsnode\0".len() + 1 + 32);
+        assert_eq!(at_path.len(), at_root.len() + 32);
+    }
+
+    #[fbinit::test]
+    fn test_walk_state_bloom(fb: FacebookInit) {
+        let ctx = CoreContext::test_mock(fb);
+        let state = WalkStateAny::new(
+            WalkStateOptions {
+                include_node_types: vec![NodeType::BonsaiChangeset].into_iter().collect(),
+                include_edge_types: vec![EdgeType::BonsaiChangesetToBonsaiParent]
+                    .into_iter()
+                    .collect(),
+                ..WalkStateOptions::default()
+            },
+            Visited::Bloom(VisitedBloom::new(1000, 0.01)),
+        );
+        let parent_edge = |i: usize| {
+            OutgoingEdge::new(
+                EdgeType::BonsaiChangesetToBonsaiParent,
+                Node::BonsaiChangeset(ChangesetId::from_str(&format!("{:064x}", i)).unwrap()),
+            )
+        };
+        let visit = |i: usize| {
+            let ((_node, _data, stats), (), outgoing) =
+                state.visit(&ctx, parent_edge(i), None, None, vec![parent_edge(i + 1)]);
+            (stats.unwrap(), outgoing.len())
+        };
+
+        let (stats, expanded) = visit(0);
+        assert_eq!(expanded, 1);
+        assert!(stats.visited_false_positive_rate > 0.0);
+        // Already stepped to 1 from 0
+        assert_eq!(visit(0).1, 0);
+
+        // Filling the filter to capacity gets close to the configured rate
+        let stats = (2..1000).map(|i| visit(i).0).fold(stats, |a, b| a + b);
+        assert!(
+            stats.visited_false_positive_rate > 0.001 && stats.visited_false_positive_rate < 0.05,
+            "{}",
+            stats.visited_false_positive_rate
+        );
+
+        // Nothing to save, as the filter can't list the visited nodes
+        assert!(state.save_snapshot(Vec::new()).is_err());
+    }
+
+    #[test]
+    fn test_snapshot() {
+        let state = |node_types: Vec<NodeType>| {
+            WalkStateCHashMap::new(WalkStateOptions {
+                include_node_types: node_types.into_iter().collect(),
+                include_edge_types: vec![
+                    EdgeType::BonsaiChangesetToBonsaiParent,
+                    EdgeType::HgManifestToHgFileNode,
+                ]
+                .into_iter()
+                .collect(),
+                ..WalkStateOptions::default()
+            })
+        };
+        let node_types = vec![NodeType::BonsaiChangeset, NodeType::HgFileNode];
+        let parent_edge = |i: usize| {
+            OutgoingEdge::new(
+                EdgeType::BonsaiChangesetToBonsaiParent,
+                Node::BonsaiChangeset(ChangesetId::from_str(&format!("{:064x}", i)).unwrap()),
+            )
+        };
+        let filenode_edge = |i: usize| {
+            OutgoingEdge::new(
+                EdgeType::HgManifestToHgFileNode,
+                Node::HgFileNode((
+                    WrappedPath::from(MPath::new("a/b").ok()),
+                    HgFileNodeId::from_str(&format!("{:040x}", i)).unwrap(),
+                )),
+            )
+        };
+
+        let saved = state(node_types.clone());
+        assert!((0..10).all(|i| saved.needs_visit(&parent_edge(i))));
+        assert!(saved.needs_visit(&filenode_edge(0)));
+        let mut snapshot = vec![];
+        saved.save_snapshot(&mut snapshot).unwrap();
+
+        // The resumed walk skips what was visited, and carries on the counts
+        let resumed = state(node_types);
+        resumed.load_snapshot(&snapshot[..]).unwrap();
+        assert!((0..10).all(|i| !resumed.needs_visit(&parent_edge(i))));
+        assert!(!resumed.needs_visit(&filenode_edge(0)));
+        assert!(resumed.needs_visit(&parent_edge(10)));
+        assert!(resumed.needs_visit(&filenode_edge(1)));
+        assert_eq!(resumed.get_visit_count(&NodeType::BonsaiChangeset), 21);
+
+        // Walking more types than the snapshot did is refused
+        let widened = state(vec![
+            NodeType::BonsaiChangeset,
+            NodeType::HgFileNode,
+            NodeType::FileContent,
+        ]);
+        assert!(widened.load_snapshot(&snapshot[..]).is_err());
+        assert!(state(vec![]).load_snapshot(&b"garbage"[..]).is_err());
+
+        // Lengths in the snapshot are not trusted for allocation: after the magic
+        // and the count of node types, claim a huge node type name
+        let mut truncated = snapshot[..16].to_vec();
+        truncated.extend_from_slice(&u64::MAX.to_le_bytes());
+        let resumed = state(vec![NodeType::BonsaiChangeset, NodeType::HgFileNode]);
+        assert!(resumed.load_snapshot(&truncated[..]).is_err());
+    }
+
+    #[test]
+    fn test_node_rate_limits() {
+        let state = WalkStateCHashMap::new(WalkStateOptions {
+            include_node_types: vec![NodeType::BonsaiChangeset, NodeType::FileContent]
+                .into_iter()
+                .collect(),
+            include_edge_types: vec![
+                EdgeType::BonsaiChangesetToBonsaiParent,
+                EdgeType::BonsaiChangesetToFileContent,
+            ]
+            .into_iter()
+            .collect(),
+            node_rate_limits: vec![(NodeType::BonsaiChangeset, 2)].into_iter().collect(),
+            ..WalkStateOptions::default()
+        });
+        let parent_edge = OutgoingEdge::new(
+            EdgeType::BonsaiChangesetToBonsaiParent,
+            Node::BonsaiChangeset(ChangesetId::from_str(SAMPLE_BLAKE2).unwrap()),
+        );
+        let file_edge = OutgoingEdge::new(
+            EdgeType::BonsaiChangesetToFileContent,
+            Node::FileContent(ContentId::from_str(SAMPLE_BLAKE2).unwrap()),
+        );
+
+        // A second's worth goes straight through, then steps are delayed, not refused
+        let now = Some(Reservation::Taken(Duration::from_secs(0)));
+        assert_eq!(state.reserve_step(&parent_edge), now);
+        assert_eq!(state.reserve_step(&parent_edge), now);
+        let delays: Vec<_> = (0..2)
+            .map(|_| match state.reserve_step(&parent_edge) {
+                Some(Reservation::Taken(delay)) => delay,
+                other => panic!("Expected a delay, got {:?}", other),
+            })
+            .collect();
+        assert!(delays[0] > Duration::from_secs(0));
+        assert!(delays[1] > delays[0]);
+        // Until a refill's worth is reserved, then steps retry later
+        match state.reserve_step(&parent_edge) {
+            Some(Reservation::Full(_)) => {}
+            other => panic!("Expected the bucket to be full, got {:?}", other),
+        }
+        // Other node types are not limited
+        assert!((0..10).all(|_| state.reserve_step(&file_edge).is_none()));
+    }
+
+    #[test]
+    fn test_file_content_sample_rate() {
+        let content_edge = |i: u32| {
+            let mut context = Context::new(b"test_file_content_sample_rate");
+            context.update(i.to_le_bytes());
+            OutgoingEdge::new(
+                EdgeType::BonsaiChangesetToFileContent,
+                Node::FileContent(ContentId::new(context.finish())),
+            )
+        };
+        let sampled = |rate: f64| -> Vec<u32> {
+            let state = WalkStateCHashMap::new(WalkStateOptions {
+                include_node_types: vec![NodeType::FileContent].into_iter().collect(),
+                include_edge_types: vec![EdgeType::BonsaiChangesetToFileContent]
+                    .into_iter()
+                    .collect(),
+                file_content_sample_rate: rate,
+                ..WalkStateOptions::default()
+            });
+            (0..10000)
+                .filter(|i| state.needs_visit(&content_edge(*i)))
+                .collect()
+        };
+
+        let tenth = sampled(0.1);
+        assert!(tenth.len() > 900 && tenth.len() < 1100, "{}", tenth.len());
+        // The same contents are picked by a fresh walk
+        assert_eq!(sampled(0.1), tenth);
+        assert_eq!(sampled(1.0).len(), 10000);
+        assert!(sampled(0.0).is_empty());
+    }
+}
@@ -6,15 +6,22 @@
  */
 
 use crate::setup::{RepoWalkDatasources, RepoWalkParams};
+use crate::state::{SnapshotWalkState, WalkStateAny};
 use crate::walk::{walk_exact, WalkVisitor};
 
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use cloned::cloned;
 use context::CoreContext;
 use fbinit::FacebookInit;
 use futures::{future::Future, stream::BoxStream};
+use mononoke_types::hash::Blake2;
 use scuba_ext::ScubaSampleBuilder;
-use slog::Logger;
+use slog::{info, Logger};
+use std::{
+    fs::{self, File},
+    io::{BufReader, BufWriter},
+    path::Path,
+};
 use tokio::time::{Duration, Instant};
 
 #[derive(Clone)]
@@ -36,14 +43,26 @@ where
     RunFac: 'static + Clone + Send + Sync + FnOnce(RepoWalkRun) -> SinkFac,
     SinkFac: 'static + FnOnce(BoxStream<'static, Result<VOut, Error>>) -> SinkOut + Clone + Send,
     SinkOut: Future<Output = Result<(), Error>> + 'static + Send,
-    WS: 'static + Clone + WalkVisitor<VOut, Route> + Send,
+    WS: 'static + Clone + WalkVisitor<VOut, Route> + SnapshotWalkState + Send,
     VOut: 'static + Send,
     Route: 'static + Send + Clone,
 {
     let scuba_builder = datasources.scuba_builder;
     let repo = datasources.blobrepo.await?;
     let tail_secs = walk_params.tail_secs.clone();
+    if let Some(path) = &walk_params.load_snapshot {
+        let file = File::open(path)
+            .map_err(|e| format_err!("Failed to open walk snapshot {}: {}", path.display(), e))?;
+        walk_state
+            .snapshot_state()
+            .load_snapshot(BufReader::new(file))?;
+        info!(logger, "Loaded walk snapshot {}", path.display());
+    }
+    // The clone in each iteration is moved into the walk
+    let shared_walk_state = walk_state.clone();
     loop {
+        // Each iteration is a fresh walk, so gets its own max_errors
+        shared_walk_state.snapshot_state().reset_errors();
         cloned!(make_run, repo, mut scuba_builder, walk_state,);
 
         let ctx = CoreContext::new_with_logger(fb, logger.clone());
@@ -69,6 +88,28 @@ where
         let make_sink = make_run(walk_run);
         make_sink(walk_output).await?;
 
+        // The walk stopped expanding early, so fail rather than report it as complete
+        if shared_walk_state.snapshot_state().error_limit_reached() {
+            return Err(format_err!(
+                "Walk stopped after reaching the limit of {} errors",
+                walk_params.max_errors.unwrap_or_default()
+            ));
+        }
+
+        // Cumulative over the iterations so far, as the visited nodes are
+        report_content_checksum(
+            &logger,
+            walk_params.content_checksum,
+            shared_walk_state.snapshot_state().content_checksum(),
+        );
+
+        // Only a completed walk is saved, as steps still to be taken are not part
+        // of the snapshot
+        if let Some(path) = &walk_params.save_snapshot {
+            save_snapshot(shared_walk_state.snapshot_state(), path)?;
+            info!(logger, "Saved walk snapshot {}", path.display());
+        }
+
         match tail_secs {
             Some(interval) => {
                 let start = Instant::now();
@@ -79,3 +120,24 @@ where
         }
     }
 }
+
+// Written aside then renamed, so an interrupted save leaves the previous snapshot intact
+fn save_snapshot(walk_state: &WalkStateAny, path: &Path) -> Result<(), Error> {
+    let tmp_path = path.with_extension("tmp");
+    let mut writer = BufWriter::new(File::create(&tmp_path)?);
+    walk_state.save_snapshot(&mut writer)?;
+    writer.into_inner()?.sync_all()?;
+    fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Log the content checksum from a walk, if checksumming was enabled.
+pub fn report_content_checksum(logger: &Logger, enabled: bool, checksum: [u8; 32]) {
+    if enabled {
+        info!(
+            logger,
+            "Content checksum {}",
+            Blake2::from_byte_array(checksum)
+        );
+    }
+}
@@ -17,11 +17,12 @@ use crate::progress::{
     progress_stream, report_state, sort_by_string, ProgressRecorder, ProgressRecorderUnprotected,
     ProgressReporter, ProgressReporterUnprotected, ProgressStateMutex,
 };
+use crate::rate_limit::Reservation;
 use crate::setup::{
     setup_common, EXCLUDE_CHECK_TYPE_ARG, INCLUDE_CHECK_TYPE_ARG, PROGRESS_SAMPLE_DURATION_S,
     PROGRESS_SAMPLE_RATE, VALIDATE,
 };
-use crate::state::{StepStats, WalkStateCHashMap};
+use crate::state::{SnapshotWalkState, StepStats, Visited, WalkStateAny, WalkStateOptions};
 use crate::tail::{walk_exact_tail, RepoWalkRun};
 use crate::walk::{OutgoingEdge, WalkVisitor};
 
@@ -37,7 +38,7 @@ use itertools::Itertools;
 use mononoke_types::MPath;
 use phases::Phase;
 use scuba_ext::ScubaSampleBuilder;
-use slog::{info, warn, Logger};
+use slog::{debug, info, warn, Logger};
 use stats::prelude::*;
 use stats_facebook::service_data::{get_service_data_singleton, ServiceData};
 use std::{
@@ -128,20 +129,20 @@ impl CheckOutput {
 
 struct ValidatingVisitor {
     repo_stats_key: String,
-    inner: WalkStateCHashMap,
+    inner: WalkStateAny,
     checks_by_node_type: HashMap<NodeType, HashSet<CheckType>>,
 }
 
 impl ValidatingVisitor {
     pub fn new(
         repo_stats_key: String,
-        include_node_types: HashSet<NodeType>,
-        include_edge_types: HashSet<EdgeType>,
+        options: WalkStateOptions,
+        visited: Visited,
         include_checks: HashSet<CheckType>,
     ) -> Self {
         Self {
             repo_stats_key,
-            inner: WalkStateCHashMap::new(include_node_types, include_edge_types),
+            inner: WalkStateAny::new(options, visited),
             checks_by_node_type: include_checks
                 .into_iter()
                 .group_by(|c| c.node_type())
@@ -152,6 +153,12 @@ impl ValidatingVisitor {
     }
 }
 
+impl SnapshotWalkState for ValidatingVisitor {
+    fn snapshot_state(&self) -> &WalkStateAny {
+        &self.inner
+    }
+}
+
 fn check_bonsai_phase_is_public(node_data: Option<&NodeData>) -> CheckStatus {
     match node_data {
         Some(NodeData::BonsaiPhaseMapping(Some(Phase::Public))) => CheckStatus::Pass,
@@ -193,6 +200,10 @@ impl WalkVisitor<(Node, Option<CheckData>, Option<StepStats>), Node> for Validat
         self.inner.start_step(ctx, route.map(|_| &()), step)
     }
 
+    fn reserve_step(&self, step: &OutgoingEdge) -> Option<Reservation> {
+        self.inner.reserve_step(step)
+    }
+
     fn visit(
         &self,
         ctx: &CoreContext,
@@ -500,10 +511,6 @@ pub async fn validate<'a>(
     let mut include_check_types = parse_check_types(sub_m)?;
     include_check_types.retain(|t| walk_params.include_node_types.contains(&t.node_type()));
 
-    cloned!(
-        walk_params.include_node_types,
-        walk_params.include_edge_types,
-    );
     info!(
         logger,
         "Performing check types {:?}",
@@ -512,8 +519,8 @@ pub async fn validate<'a>(
 
     let stateful_visitor = Arc::new(ValidatingVisitor::new(
         repo_stats_key.clone(),
-        include_node_types,
-        include_edge_types,
+        walk_params.walk_state_options(),
+        walk_params.visited(),
         include_check_types.clone(),
     ));
 
@@ -554,12 +561,18 @@ pub async fn validate<'a>(
     };
     walk_exact_tail(
         fb,
-        logger,
+        logger.clone(),
         datasources,
         walk_params,
-        stateful_visitor,
+        stateful_visitor.clone(),
         make_sink,
         false,
     )
-    .await
+    .await?;
+    debug!(
+        logger,
+        "Interned {} ids",
+        stateful_visitor.inner.interned_ids()
+    );
+    Ok(())
 }
@@ -6,13 +6,13 @@
  */
 
 use crate::graph::{EdgeType, FileContentData, Node, NodeData, NodeType, WrappedPath};
+use crate::rate_limit::{throttled_traversal_stream, Reservation};
 use crate::validate::{add_node_to_scuba, CHECK_FAIL, CHECK_TYPE, EDGE_TYPE};
 
 use anyhow::{format_err, Context, Error};
 use blobrepo::BlobRepo;
 use blobstore::Loadable;
 use bookmarks::{BookmarkName, BookmarkPrefix, Freshness};
-use bounded_traversal::bounded_traversal_stream;
 use cloned::cloned;
 use context::CoreContext;
 use derived_data::BonsaiDerived;
@@ -84,6 +84,9 @@ pub trait WalkVisitor<VOut, Route> {
         step: &OutgoingEdge,
     ) -> CoreContext;
 
+    // Called before the step is scheduled, to rate limit it. None if unlimited
+    fn reserve_step(&self, step: &OutgoingEdge) -> Option<Reservation>;
+
     // This can mutate the internal state.  Takes ownership and returns data, plus next step
     fn visit(
         &self,
@@ -110,6 +113,10 @@ where
         self.as_ref().start_step(ctx, route, step)
     }
 
+    fn reserve_step(&self, step: &OutgoingEdge) -> Option<Reservation> {
+        self.as_ref().reserve_step(step)
+    }
+
     fn visit(
         &self,
         ctx: &CoreContext,
@@ -692,7 +699,12 @@ where
     published_bookmarks
         .map_ok(move |published_bookmarks| {
             let published_bookmarks = Arc::new(published_bookmarks);
-            bounded_traversal_stream(scheduled_max, walk_roots, {
+            let throttle = visitor.clone();
+            // Throttled steps are deferred rather than dropped, so the walk stays complete
+            throttled_traversal_stream(
+                scheduled_max,
+                walk_roots,
+                move |(_via, walk_item)| throttle.reserve_step(walk_item),
                 move |(via, walk_item)| {
                     let ctx = visitor.start_step(ctx.clone(), via.as_ref(), &walk_item);
                     cloned!(
@@ -733,8 +745,8 @@ where
                         handle.await?
                     }
                     .boxed()
-                }
-            })
+                },
+            )
         })
         .try_flatten_stream()
         .boxed()
@@ -0,0 +1,39 @@
+/*
+ * Copyright (c) Facebook, Inc. and its affiliates.
+ *
+ * This software may be used and distributed according to the terms of the
+ * GNU General Public License version 2.
+ */
+
+use std::fmt;
+
+/// Errors that callers might want to handle specifically.
+///
+/// Other errors are reported as plain [`anyhow::Error`]s. These can be
+/// recovered from them using `downcast_ref`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DagError {
+    /// The on-disk data was written in a format this version does not
+    /// understand.
+    IncompatibleVersion { found: u32, expected: u32 },
+
+    /// The `IdMap` and segments disagree. Found by [`crate::NameDag::verify`].
+    Inconsistent { message: String },
+}
+
+impl fmt::Display for DagError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DagError::IncompatibleVersion { found, expected } => write!(
+                f,
+                "incompatible dag format version (found {}, expected {})",
+                found, expected
+            ),
+            DagError::Inconsistent { message } => {
+                write!(f, "dag and idmap are inconsistent: {}", message)
+            }
+        }
+    }
+}
+
+impl std::error::Error for DagError {}
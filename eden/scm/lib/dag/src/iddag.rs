@@ -45,7 +45,7 @@ pub struct IdDag<Store> {
 /// Guard to make sure [`IdDag`] on-disk writes are race-free.
 pub struct SyncableIdDag<Store> {
     dag: IdDag<Store>,
-    lock_file: File,
+    lock_file: Option<File>,
 }
 
 static DEFAULT_SEG_SIZE: usize = 16;
@@ -79,7 +79,28 @@ impl IdDag<IndexedLogStore> {
                 max_level,
                 new_seg_size: self.new_seg_size,
             },
-            lock_file,
+            lock_file: Some(lock_file),
+        })
+    }
+
+    /// Clone the [`IdDag`], including pending changes.
+    pub(crate) fn try_clone(&self) -> Result<Self> {
+        Ok(IdDag {
+            store: self.store.try_clone()?,
+            max_level: self.max_level,
+            new_seg_size: self.new_seg_size,
+        })
+    }
+
+    /// Return a [`SyncableIdDag`] for an in-memory [`IdDag`].
+    ///
+    /// Unlike [`IdDag::prepare_filesystem_sync`], no lock is taken and
+    /// pending changes are kept. Use [`SyncableIdDag::into_dag`] instead of
+    /// `sync` to get the result.
+    pub(crate) fn prepare_in_memory_sync(&self) -> Result<SyncableIdDag<IndexedLogStore>> {
+        Ok(SyncableIdDag {
+            dag: self.try_clone()?,
+            lock_file: None,
         })
     }
 
@@ -1149,6 +1170,24 @@ impl<Store: IdDagStore> SyncableIdDag<Store> {
         Ok(count)
     }
 
+    /// Insert flat segments, usually exported from another [`IdDag`], then
+    /// build high-level segments on top of them.
+    ///
+    /// The caller is responsible for making sure the segments do not
+    /// overlap with existing ones and leave no gaps in their groups.
+    pub(crate) fn import_flat_segments(&mut self, segments: &[Segment]) -> Result<usize> {
+        for segment in segments {
+            ensure!(
+                segment.level()? == 0,
+                "bug: import_flat_segments got a high-level segment {:?}",
+                segment
+            );
+            self.dag.store.insert_segment(segment.clone())?;
+        }
+        let count = self.dag.build_all_high_level_segments(true)?;
+        Ok(segments.len() + count)
+    }
+
     /// Write pending changes to disk. Release the exclusive lock.
     ///
     /// The newly written entries can be fetched by [`IdDag::reload`].
@@ -1167,6 +1206,11 @@ impl<Store: IdDagStore> SyncableIdDag<Store> {
         Ok(())
     }
 
+    /// Take the [`IdDag`] out, without writing to disk.
+    pub(crate) fn into_dag(self) -> IdDag<Store> {
+        self.dag
+    }
+
     /// Export non-master DAG as parent_id_func on HashMap.
     ///
     /// This can be expensive if there are a lot of non-master ids.
@@ -362,10 +362,24 @@ impl IndexedLogStore {
     }
 
     pub fn open_from_log(log: log::Log) -> Self {
-        let path = log.path().as_opt_path().unwrap().to_path_buf();
+        // In-memory logs have no path. They are never synced to disk.
+        let path = log
+            .path()
+            .as_opt_path()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
         Self { log, path }
     }
 
+    pub fn try_clone(&self) -> Result<IndexedLogStore> {
+        let log = self.log.try_clone()?;
+        let store = IndexedLogStore {
+            log,
+            path: self.path.clone(),
+        };
+        Ok(store)
+    }
+
     pub fn try_clone_without_dirty(&self) -> Result<IndexedLogStore> {
         let log = self.log.try_clone_without_dirty()?;
         let store = IndexedLogStore {
@@ -37,7 +37,7 @@ pub struct IdMap {
 /// down changes to the filesystem and release the lock.
 pub struct SyncableIdMap<'a> {
     map: &'a mut IdMap,
-    lock_file: File,
+    lock_file: Option<File>,
 }
 
 impl IdMap {
@@ -49,6 +49,13 @@ impl IdMap {
     /// with this.
     const MAGIC_CLEAR_NON_MASTER: &'static [u8] = b"CLRNM";
 
+    /// Magic prefix in `Log` of an entry that indicates "remove the name that
+    /// follows from the name->id index". Written by `rename` and
+    /// `remove_non_master` for names that no longer map to their id. Its
+    /// first byte is not a valid group, so it does not conflict with the id
+    /// of a valid entry.
+    const MAGIC_REMOVE_NAME: &'static [u8] = b"\xffRMNAME\0";
+
     /// Create an [`IdMap`] backed by the given directory.
     ///
     /// By default, only read-only operations are allowed. For writing
@@ -70,7 +77,12 @@ impl IdMap {
     }
 
     pub(crate) fn open_from_log(log: log::Log) -> Result<Self> {
-        let path = log.path().as_opt_path().unwrap().to_path_buf();
+        // In-memory logs have no path. They are never synced to disk.
+        let path = log
+            .path()
+            .as_opt_path()
+            .map(|p| p.to_path_buf())
+            .unwrap_or_default();
         Ok(Self {
             log,
             path,
@@ -93,12 +105,17 @@ impl IdMap {
                     } else {
                         panic!("bug: invalid segment {:?}", &data);
                     }
+                } else if data.starts_with(Self::MAGIC_REMOVE_NAME) {
+                    Vec::new()
                 } else {
                     vec![log::IndexOutput::Reference(0..8)]
                 }
             })
             .index("name", |data| {
-                if data.len() >= 8 {
+                assert!(Self::MAGIC_REMOVE_NAME.len() == 8);
+                if data.starts_with(Self::MAGIC_REMOVE_NAME) {
+                    vec![log::IndexOutput::Remove(data[8..].into())]
+                } else if data.len() >= 8 {
                     vec![log::IndexOutput::Reference(8..data.len() as u64)]
                 } else {
                     Vec::new()
@@ -121,6 +138,10 @@ impl IdMap {
             self.log.iter_dirty().next().is_none(),
             "programming error: prepare_filesystem_sync must be called without dirty in-memory entries",
         );
+        ensure!(
+            self.log.path().as_opt_path().is_some(),
+            "programming error: prepare_filesystem_sync called on an in-memory IdMap",
+        );
 
         // Take a filesystem lock. The file name 'lock' is taken by indexedlog
         // running on Windows, so we choose another file name here.
@@ -141,10 +162,21 @@ impl IdMap {
 
         Ok(SyncableIdMap {
             map: self,
-            lock_file,
+            lock_file: Some(lock_file),
         })
     }
 
+    /// Return a [`SyncableIdMap`] for an in-memory [`IdMap`].
+    ///
+    /// No lock is taken, and pending changes are kept, since there is no
+    /// filesystem to reload from.
+    pub(crate) fn prepare_in_memory_sync(&mut self) -> SyncableIdMap<'_> {
+        SyncableIdMap {
+            map: self,
+            lock_file: None,
+        }
+    }
+
     /// Reload from the filesystem. Discard pending changes.
     pub fn reload(&mut self) -> Result<()> {
         self.log.clear_dirty()?;
@@ -269,6 +301,39 @@ impl IdMap {
         Ok(())
     }
 
+    /// Change the name of an existing id.
+    ///
+    /// The old name will no longer resolve to the id. Errors if the id does
+    /// not exist, or if the new name already maps to an id.
+    pub fn rename(&mut self, id: Id, new_name: &[u8]) -> Result<()> {
+        let old_name = match self.find_name_by_id(id)? {
+            Some(old_name) => old_name.to_vec(),
+            None => bail!("cannot rename {} to {:?}: id does not exist", id, new_name),
+        };
+        if let Some(existing_id) = self.find_id_by_name(new_name)? {
+            bail!(
+                "cannot rename {} = {:?} to {:?}: it is already used by {}",
+                id,
+                old_name,
+                new_name,
+                existing_id
+            );
+        }
+
+        // Remove the old name from the name->id index, so lookups of it do
+        // not find the id.
+        let mut data = Vec::with_capacity(8 + old_name.len());
+        data.write_all(Self::MAGIC_REMOVE_NAME).unwrap();
+        data.write_all(&old_name).unwrap();
+        self.log.append(data)?;
+
+        let mut data = Vec::with_capacity(8 + new_name.len());
+        data.write_u64::<BigEndian>(id.0).unwrap();
+        data.write_all(new_name).unwrap();
+        self.log.append(data)?;
+        Ok(())
+    }
+
     /// Return the next unused id in the given group.
     pub fn next_free_id(&self, group: Group) -> Result<Id> {
         let cached = self.cached_next_free_ids[group.0].load(atomic::Ordering::SeqCst);
@@ -288,14 +353,21 @@ impl IdMap {
         hex_prefix: &[u8],
         limit: usize,
     ) -> Result<Vec<Cow<[u8]>>> {
-        self.log
+        let mut names = Vec::new();
+        for entry in self
+            .log
             .lookup_prefix_hex(Self::INDEX_NAME_TO_ID, hex_prefix)?
-            .take(limit)
-            .map(|entry| {
-                let (k, _v) = entry?;
-                Ok(k)
-            })
-            .collect::<Result<_>>()
+        {
+            if names.len() >= limit {
+                break;
+            }
+            let (k, _v) = entry?;
+            // Skip names that were renamed away.
+            if self.find_id_by_name(&k)?.is_some() {
+                names.push(k);
+            }
+        }
+        Ok(names)
     }
 
     // Find an unused id that is bigger than existing ids.
@@ -469,6 +541,34 @@ impl IdMap {
 impl IdMap {
     /// Mark non-master ids as "removed".
     pub fn remove_non_master(&mut self) -> Result<()> {
+        // Remove their names from the name->id index too, so the names do not
+        // resolve to ids that get re-assigned to other names.
+        let group = Group::NON_MASTER;
+        let lower_bound = group.min_id().to_bytearray();
+        let upper_bound = group.max_id().to_bytearray();
+        let range = &lower_bound[..]..=&upper_bound[..];
+        let mut names = Vec::new();
+        for item in self.log.lookup_range(Self::INDEX_ID_TO_NAME, range)? {
+            let (_, mut entries) = item?;
+            if let Some(entry) = entries.nth(0) {
+                let entry = entry?;
+                // Skip names that were since given a master id.
+                let name = &entry[8..];
+                let latest = self.log.lookup(Self::INDEX_NAME_TO_ID, name)?.nth(0);
+                if let Some(latest) = latest {
+                    if latest?[..8] == entry[..8] {
+                        names.push(name.to_vec());
+                    }
+                }
+            }
+        }
+        for name in names {
+            let mut data = Vec::with_capacity(8 + name.len());
+            data.write_all(Self::MAGIC_REMOVE_NAME).unwrap();
+            data.write_all(&name).unwrap();
+            self.log.append(data)?;
+        }
+
         self.log.append(IdMap::MAGIC_CLEAR_NON_MASTER)?;
         self.need_rebuild_non_master = false;
         // Invalidate the next free id cache.
@@ -498,6 +598,9 @@ impl fmt::Debug for IdMap {
         write!(f, "IdMap {{\n")?;
         for data in self.log.iter() {
             if let Ok(mut data) = data {
+                if data.starts_with(Self::MAGIC_REMOVE_NAME) {
+                    continue;
+                }
                 let id = data.read_u64::<BigEndian>().unwrap();
                 let mut name = Vec::with_capacity(20);
                 data.read_to_end(&mut name).unwrap();
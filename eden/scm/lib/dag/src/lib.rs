@@ -11,6 +11,7 @@
 //!
 //! Building blocks for the commit graph used by source control.
 
+pub mod errors;
 pub mod id;
 mod iddag;
 mod iddagstore;
@@ -21,6 +22,7 @@ pub mod protocol;
 mod segment;
 pub mod spanset;
 
+pub use errors::DagError;
 pub use id::{Group, Id, VertexName};
 pub use iddag::IdDag;
 pub use idmap::IdMap;
@@ -9,7 +9,9 @@
 //!
 //! Combination of IdMap and IdDag.
 
+use crate::errors::DagError;
 use crate::id::Group;
+use crate::id::Id;
 use crate::id::VertexName;
 use crate::iddag::IdDag;
 use crate::iddag::SyncableIdDag;
@@ -19,12 +21,19 @@ use crate::idmap::IdMapLike;
 use crate::idmap::SyncableIdMap;
 use crate::nameset::dag::DagSet;
 use crate::nameset::NameSet;
+use crate::segment::{Segment, SegmentFlags};
 use crate::spanset::SpanSet;
+use crate::Level;
 use anyhow::{anyhow, bail, ensure, Result};
 use indexedlog::multi;
+use indexedlog::utils::{atomic_read, atomic_write};
+use minibytes::Bytes;
+use std::collections::hash_map::Entry;
 use std::collections::{HashMap, HashSet};
-use std::path::Path;
+use std::io::{Cursor, Read};
+use std::path::{Path, PathBuf};
 use std::sync::Arc;
+use vlqencoding::{VLQDecode, VLQEncode};
 
 /// A DAG that uses VertexName instead of ids as vertexes.
 ///
@@ -37,20 +46,78 @@ pub struct NameDag {
     /// A read-only snapshot of the `IdMap` that will be shared in `NameSet`s.
     pub(crate) snapshot_map: Arc<IdMap>,
 
-    mlog: multi::MultiLog,
+    storage: Storage,
 
     /// Heads added via `add_heads` that are not flushed yet.
     pending_heads: Vec<VertexName>,
 }
 
+/// Where a [`NameDag`] writes changes to.
+enum Storage {
+    /// A directory on disk, which other processes might write to.
+    Filesystem(multi::MultiLog, PathBuf),
+
+    /// Nowhere. The map and dag as of the last write are kept so `reload`
+    /// can go back to them.
+    Memory(Box<IdMap>, Box<IdDag<IndexedLogStore>>),
+}
+
+/// Segment statistics of a [`NameDag`]. See [`NameDag::stats`].
+///
+/// Long flat segments mean the graph is mostly linear and cheap to query.
+/// Many single-vertex segments usually mean heavy branching and merging.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DagStats {
+    /// Number of flat (level 0) segments.
+    pub flat_segment_count: usize,
+
+    /// Number of flat segments covering exactly one vertex.
+    pub single_vertex_segment_count: usize,
+
+    /// Average number of vertexes covered by a flat segment.
+    pub average_flat_segment_length: f64,
+
+    /// Number of segments above level 0.
+    pub high_level_segment_count: usize,
+
+    /// Highest segment level.
+    pub max_level: Level,
+
+    /// Number of vertexes in the master group.
+    pub master_group_size: u64,
+
+    /// Number of vertexes in the non-master group.
+    pub non_master_group_size: u64,
+}
+
+/// Version of the on-disk format. Bump this when `IdMap` or `IdDag` change
+/// their on-disk representation in an incompatible way.
+const FORMAT_VERSION: u32 = 1;
+
+/// Name of the file storing `FORMAT_VERSION` in the `NameDag` directory.
+const VERSION_FILE: &str = "version";
+
 impl NameDag {
     pub fn open(path: impl AsRef<Path>) -> Result<Self> {
         let path = path.as_ref();
+        let created = match path.read_dir() {
+            Ok(mut entries) => entries.next().is_none(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => true,
+            Err(e) => return Err(e.into()),
+        };
+        // Checked before opening the logs, as a newer format might not open
+        // cleanly, or might be changed by opening it.
+        if !created {
+            Self::check_format_version(path)?;
+        }
         let opts = multi::OpenOptions::from_name_opts(vec![
             ("idmap", IdMap::log_open_options()),
             ("iddag", IndexedLogStore::log_open_options()),
         ]);
         let mut mlog = opts.open(path)?;
+        if created {
+            Self::write_format_version(path)?;
+        }
         let mut logs = mlog.detach_logs();
         let dag_log = logs.pop().unwrap();
         let map_log = logs.pop().unwrap();
@@ -61,11 +128,66 @@ impl NameDag {
             dag,
             map,
             snapshot_map,
-            mlog,
+            storage: Storage::Filesystem(mlog, path.to_path_buf()),
             pending_heads: Default::default(),
         })
     }
 
+    /// Create an empty `NameDag` that lives in memory.
+    ///
+    /// It behaves like one opened from an empty directory, except that
+    /// nothing is written to disk, and changes are lost on drop.
+    pub fn in_memory() -> Result<Self> {
+        let map_log = IdMap::log_open_options().open(())?;
+        let dag_log = IndexedLogStore::log_open_options().open(())?;
+        let map = IdMap::open_from_log(map_log)?;
+        let dag = IdDag::open_from_store(IndexedLogStore::open_from_log(dag_log))?;
+        let snapshot_map = Arc::new(map.try_clone()?);
+        let storage = Storage::Memory(Box::new(map.try_clone()?), Box::new(dag.try_clone()?));
+        Ok(Self {
+            dag,
+            map,
+            snapshot_map,
+            storage,
+            pending_heads: Default::default(),
+        })
+    }
+
+    /// Check the format version stored in `path`. Directories without a
+    /// version file predate it and use the first version. The version file
+    /// is added by the next write, so opening stays read-only.
+    fn check_format_version(path: &Path) -> Result<()> {
+        let version_path = path.join(VERSION_FILE);
+        match atomic_read(&version_path) {
+            Ok(data) => {
+                let found = std::str::from_utf8(&data)
+                    .ok()
+                    .and_then(|s| s.trim().parse::<u32>().ok())
+                    .ok_or_else(|| anyhow!("malformed dag version file: {:?}", data))?;
+                if found != FORMAT_VERSION {
+                    return Err(DagError::IncompatibleVersion {
+                        found,
+                        expected: FORMAT_VERSION,
+                    }
+                    .into());
+                }
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => return Err(e.into()),
+        }
+        Ok(())
+    }
+
+    /// Write the current format version to `path`, unless it is already
+    /// there.
+    fn write_format_version(path: &Path) -> Result<()> {
+        let version_path = path.join(VERSION_FILE);
+        if !version_path.exists() {
+            atomic_write(&version_path, FORMAT_VERSION.to_string(), false)?;
+        }
+        Ok(())
+    }
+
     /// Add vertexes and their ancestors to the on-disk DAG.
     ///
     /// This is similar to calling `add_heads` followed by `flush`.
@@ -85,15 +207,7 @@ impl NameDag {
             &self.pending_heads,
         );
         // Already include specified nodes?
-        if master_names.iter().all(|n| {
-            is_ok_some(
-                self.map
-                    .find_id_by_name_with_max_group(n.as_ref(), Group::MASTER),
-            )
-        }) && non_master_names
-            .iter()
-            .all(|n| is_ok_some(self.map.find_id_by_name(n.as_ref())))
-        {
+        if self.contains_all(master_names, non_master_names) {
             return Ok(());
         }
 
@@ -101,27 +215,29 @@ impl NameDag {
         //
         // Reload meta. This drops in-memory changes, which is fine because we have
         // checked there are no in-memory changes at the beginning.
-        let lock = self.mlog.lock()?;
-        let mut map = self.map.prepare_filesystem_sync()?;
-        let mut dag = self.dag.prepare_filesystem_sync()?;
-
-        // Build.
-        build(
-            &mut map,
-            &mut dag,
-            parent_names_func,
-            master_names,
-            non_master_names,
-        )?;
-
-        // Write to disk.
-        map.sync()?;
-        dag.sync(std::iter::once(&mut self.dag))?;
-        self.mlog.write_meta(&lock)?;
+        self.write_with(|map, dag| {
+            build(map, dag, parent_names_func, master_names, non_master_names)
+        })
+    }
 
-        // Update snapshot_map.
-        self.snapshot_map = Arc::new(self.map.try_clone()?);
-        Ok(())
+    /// Test if `add_heads_and_flush` would be a no-op for the given names.
+    ///
+    /// That is, `master_names` are all in the master group, and
+    /// `non_master_names` are all in the DAG. This only checks the in-memory
+    /// `IdMap` and does not take the filesystem lock.
+    pub fn contains_all(
+        &self,
+        master_names: &[VertexName],
+        non_master_names: &[VertexName],
+    ) -> bool {
+        master_names.iter().all(|n| {
+            is_ok_some(
+                self.map
+                    .find_id_by_name_with_max_group(n.as_ref(), Group::MASTER),
+            )
+        }) && non_master_names
+            .iter()
+            .all(|n| is_ok_some(self.map.find_id_by_name(n.as_ref())))
     }
 
     /// Add vertexes and their ancestors to the in-memory DAG.
@@ -214,13 +330,139 @@ impl NameDag {
     }
 
     /// Reload segments from disk. This discards in-memory content.
+    ///
+    /// For an in-memory `NameDag`, this goes back to the state after the
+    /// last write.
     pub fn reload(&mut self) -> Result<()> {
-        self.map.reload()?;
-        self.dag.reload()?;
+        match &self.storage {
+            Storage::Filesystem(..) => {
+                self.map.reload()?;
+                self.dag.reload()?;
+            }
+            Storage::Memory(map, dag) => {
+                self.map = map.try_clone()?;
+                self.dag = dag.try_clone()?;
+            }
+        }
         self.pending_heads.clear();
         Ok(())
     }
 
+    /// Take the lock, reload, call `f` to change the map and dag, then write
+    /// the changes.
+    ///
+    /// In-memory `NameDag`s have nothing to lock or reload from, and "write"
+    /// means to remember the state for `reload`.
+    fn write_with<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&mut SyncableIdMap, &mut SyncableIdDag<IndexedLogStore>) -> Result<()>,
+    {
+        match &mut self.storage {
+            Storage::Filesystem(mlog, path) => {
+                let lock = mlog.lock()?;
+                Self::write_format_version(path)?;
+                let mut map = self.map.prepare_filesystem_sync()?;
+                let mut dag = self.dag.prepare_filesystem_sync()?;
+                f(&mut map, &mut dag)?;
+                map.sync()?;
+                dag.sync(std::iter::once(&mut self.dag))?;
+                mlog.write_meta(&lock)?;
+            }
+            Storage::Memory(saved_map, saved_dag) => {
+                self.map = saved_map.try_clone()?;
+                let mut map = self.map.prepare_in_memory_sync();
+                let mut dag = saved_dag.prepare_in_memory_sync()?;
+                f(&mut map, &mut dag)?;
+                map.sync()?;
+                self.dag = dag.into_dag();
+                **saved_map = self.map.try_clone()?;
+                **saved_dag = self.dag.try_clone()?;
+            }
+        }
+
+        // Update snapshot_map.
+        self.snapshot_map = Arc::new(self.map.try_clone()?);
+        Ok(())
+    }
+
+    /// Rename vertexes without changing the graph, and write the change to
+    /// disk.
+    ///
+    /// `mapping` maps old names to new names. Only the `IdMap` is updated, so
+    /// this is much cheaper than rebuilding segments when a rewrite changes
+    /// names but not topology. Errors if an old name does not exist, or if a
+    /// new name is already used.
+    pub fn remap_names(&mut self, mapping: &HashMap<Box<[u8]>, Box<[u8]>>) -> Result<()> {
+        ensure!(
+            self.pending_heads.is_empty(),
+            "ProgrammingError: remap_names called with pending heads ({:?})",
+            &self.pending_heads,
+        );
+
+        // Take lock. This reloads the map and dag from disk.
+        self.write_with(|map, _dag| {
+            // Resolve all old names before renaming, so one rename cannot affect
+            // the lookup of another.
+            let mut renames = Vec::with_capacity(mapping.len());
+            for (old_name, new_name) in mapping {
+                match map.find_id_by_name(old_name)? {
+                    Some(id) => renames.push((id, new_name)),
+                    None => bail!("{:?} does not exist in DAG", old_name),
+                }
+            }
+            for (id, new_name) in renames {
+                map.rename(id, new_name)?;
+            }
+            Ok(())
+        })
+    }
+
+    /// Remove vertexes from the non-master group, and write the change to
+    /// disk.
+    ///
+    /// Non-master descendants of the given names are removed too. The rest of
+    /// the non-master group gets re-assigned, the same way as when master
+    /// commits are added. Errors if a name does not exist, or belongs to the
+    /// master group.
+    pub fn remove_non_master(&mut self, names: &[Box<[u8]>]) -> Result<()> {
+        ensure!(
+            self.pending_heads.is_empty(),
+            "ProgrammingError: remove_non_master called with pending heads ({:?})",
+            &self.pending_heads,
+        );
+
+        // Take lock. This reloads the map and dag from disk.
+        self.write_with(|map, dag| {
+            for name in names {
+                match map.find_id_by_name(name)? {
+                    Some(id) if id.group() == Group::NON_MASTER => {}
+                    Some(_) => bail!("{:?} is in the master group and cannot be removed", name),
+                    None => bail!("{:?} does not exist in DAG", name),
+                }
+            }
+
+            // Find the non-master descendants of `names`.
+            let mut parents = non_master_parent_names(map, dag)?;
+            let mut children: HashMap<&VertexName, Vec<&VertexName>> = HashMap::new();
+            for (name, parent_names) in parents.iter() {
+                for parent in parent_names {
+                    children.entry(parent).or_default().push(name);
+                }
+            }
+            let mut removed = HashSet::new();
+            let mut to_visit: Vec<VertexName> =
+                names.iter().map(|n| VertexName::copy_from(n)).collect();
+            while let Some(name) = to_visit.pop() {
+                if let Some(child_names) = children.get(&name) {
+                    to_visit.extend(child_names.iter().map(|&n| n.clone()));
+                }
+                removed.insert(name);
+            }
+            parents.retain(|name, _| !removed.contains(name));
+            rebuild_non_master_from_parents(map, dag, parents)
+        })
+    }
+
     /// Sort a `NameSet` topologically.
     pub fn sort(&self, set: &NameSet) -> Result<NameSet> {
         if set.is_topo_sorted() {
@@ -251,6 +493,330 @@ impl NameDag {
             .collect()
     }
 
+    /// Pick a single "best" greatest common ancestor of `a` and `b`.
+    ///
+    /// In a criss-cross merge there can be multiple greatest common
+    /// ancestors. Pick the one with the largest generation number, that is,
+    /// the one closest to the heads. Ties are broken by picking the one with
+    /// the largest id so the answer is deterministic.
+    ///
+    /// If there are no common ancestors, return None.
+    pub fn best_common_ancestor(&self, a: VertexName, b: VertexName) -> Result<Option<VertexName>> {
+        let a = self.map.vertex_id(a)?;
+        let b = self.map.vertex_id(b)?;
+        let gcas = self.dag.gca_all((a, b))?;
+        // `gcas` iterates in descending order. Only replace on a strictly
+        // larger generation so the largest id wins a tie.
+        let mut best: Option<(u64, Id)> = None;
+        let mut low_generations = HashMap::new();
+        for id in gcas.iter() {
+            let generation = self.generation_by_id(id, &mut low_generations)?;
+            match best {
+                Some((best_generation, _)) if best_generation >= generation => {}
+                _ => best = Some((generation, id)),
+            }
+        }
+        match best {
+            None => Ok(None),
+            Some((_, id)) => Ok(Some(self.map.vertex_name(id)?)),
+        }
+    }
+
+    /// Find a shortest path from `from` to `to`, where each step goes from a
+    /// vertex to one of its parents or children.
+    ///
+    /// The path includes both ends. Return None if the vertexes are not
+    /// connected, or if no path was found before visiting a million vertexes.
+    pub fn shortest_path(&self, from: &[u8], to: &[u8]) -> Result<Option<Vec<Box<[u8]>>>> {
+        const MAX_VISITED: usize = 1_000_000;
+
+        let from = self.map.vertex_id(VertexName::copy_from(from))?;
+        let to = self.map.vertex_id(VertexName::copy_from(to))?;
+        let neighbors = |id: Id| -> Result<Vec<Id>> {
+            let mut ids = self.dag.parent_ids(id)?;
+            ids.extend(self.dag.children(id)?.iter());
+            Ok(ids)
+        };
+
+        // Search from both ends. Each side maps the vertexes it reached to
+        // the vertex it reached them from, and their distance from its end.
+        let mut from_seen: HashMap<Id, (Id, usize)> = HashMap::new();
+        from_seen.insert(from, (from, 0));
+        let mut to_seen: HashMap<Id, (Id, usize)> = HashMap::new();
+        to_seen.insert(to, (to, 0));
+        let mut from_frontier = vec![from];
+        let mut to_frontier = vec![to];
+        let mut meeting = if from == to { Some(from) } else { None };
+
+        while meeting.is_none() && !from_frontier.is_empty() && !to_frontier.is_empty() {
+            if from_seen.len() + to_seen.len() > MAX_VISITED {
+                return Ok(None);
+            }
+            // Grow the smaller side by a whole level, so the shortest of the
+            // paths found while doing so is a shortest path overall.
+            let (frontier, seen, other_seen) = if from_frontier.len() <= to_frontier.len() {
+                (&mut from_frontier, &mut from_seen, &to_seen)
+            } else {
+                (&mut to_frontier, &mut to_seen, &from_seen)
+            };
+            let mut next = Vec::new();
+            let mut best: Option<(usize, Id)> = None;
+            for &id in frontier.iter() {
+                let distance = seen[&id].1 + 1;
+                for neighbor in neighbors(id)? {
+                    if seen.contains_key(&neighbor) {
+                        continue;
+                    }
+                    seen.insert(neighbor, (id, distance));
+                    if let Some(&(_, other_distance)) = other_seen.get(&neighbor) {
+                        let length = distance + other_distance;
+                        if best.iter().all(|&(best_length, _)| length < best_length) {
+                            best = Some((length, neighbor));
+                        }
+                    }
+                    next.push(neighbor);
+                }
+            }
+            *frontier = next;
+            meeting = best.map(|(_, id)| id);
+        }
+
+        let meeting = match meeting {
+            Some(meeting) => meeting,
+            None => return Ok(None),
+        };
+        let mut path = vec![meeting];
+        let mut id = meeting;
+        while id != from {
+            id = from_seen[&id].0;
+            path.push(id);
+        }
+        path.reverse();
+        let mut id = meeting;
+        while id != to {
+            id = to_seen[&id].0;
+            path.push(id);
+        }
+        let names = path
+            .into_iter()
+            .map(|id| Ok(Box::from(self.map.vertex_name(id)?.as_ref())))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Some(names))
+    }
+
+    /// Get the generation number of a vertex. That is the length of the
+    /// longest path from the vertex to a root. Roots have generation 0.
+    ///
+    /// Return None if the vertex does not exist in the DAG.
+    ///
+    /// Generation numbers are not stored. Calculating one visits each flat
+    /// segment below the vertex once.
+    pub fn generation(&self, name: VertexName) -> Result<Option<u64>> {
+        match self.map.find_id_by_name(name.as_ref())? {
+            Some(id) => Ok(Some(self.generation_by_id(id, &mut HashMap::new())?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the ancestors of `heads` whose generation number is at least the
+    /// generation number of one of the `heads` they descend from, minus `k`.
+    ///
+    /// This approximates the last `k` commits of history below the heads.
+    /// Unlike following parents `k` times, a long side branch merged in
+    /// recently only contributes its last few commits.
+    ///
+    /// Like [`NameDag::generation`], this visits every flat segment below the
+    /// heads to calculate their generation numbers.
+    pub fn within_generations(&self, heads: &[VertexName], k: u64) -> Result<NameSet> {
+        let mut low_generations = HashMap::new();
+        let mut spans = SpanSet::empty();
+        for head in heads {
+            let head = self.map.vertex_id(head.clone())?;
+            let min_generation = self
+                .generation_by_id(head, &mut low_generations)?
+                .saturating_sub(k);
+            // Generation numbers decrease towards the roots, so stop at the
+            // first vertex of a branch below `min_generation`.
+            let mut to_visit = vec![head];
+            let mut visited = SpanSet::empty();
+            while let Some(id) = to_visit.pop() {
+                if visited.contains(id) {
+                    continue;
+                }
+                let (low, parents) = self.flat_segment_low_and_parents(id)?;
+                let low_generation = self.generation_by_id(low, &mut low_generations)?;
+                if low_generation >= min_generation {
+                    visited.push(low..=id);
+                    to_visit.extend(parents);
+                } else if low_generation + (id.0 - low.0) >= min_generation {
+                    visited.push((id - (low_generation + (id.0 - low.0) - min_generation))..=id);
+                }
+            }
+            spans = spans.union(&visited);
+        }
+        Ok(NameSet::from_spans_idmap(spans, self.snapshot_map.clone()))
+    }
+
+    /// Get the heads of the given names, that is the names without
+    /// descendants among the given names.
+    ///
+    /// This summarizes a set of commits, for example the commits of a push,
+    /// into its tips.
+    pub fn heads_of(&self, names: &[&[u8]]) -> Result<NameSet> {
+        let ids = names
+            .iter()
+            .map(|name| self.map.vertex_id(VertexName::copy_from(name)))
+            .collect::<Result<Vec<_>>>()?;
+        let spans = self.dag.heads_ancestors(SpanSet::from_spans(ids))?;
+        Ok(NameSet::from_spans_idmap(spans, self.snapshot_map.clone()))
+    }
+
+    /// Sort `names` so that ancestors come before their descendants, for
+    /// example parents before their children.
+    ///
+    /// Vertexes are assigned ids in topological order, so this is a sort by
+    /// id and does not need to look at the graph. Duplicated names are kept.
+    pub fn toposort(&self, names: &[&[u8]]) -> Result<Vec<Box<[u8]>>> {
+        let mut ids = names
+            .iter()
+            .map(|name| self.map.vertex_id(VertexName::copy_from(name)))
+            .collect::<Result<Vec<_>>>()?;
+        ids.sort_unstable();
+        ids.into_iter()
+            .map(|id| Ok(Box::from(self.map.vertex_name(id)?.as_ref())))
+            .collect()
+    }
+
+    /// Check that the `IdMap` and segments agree with each other.
+    ///
+    /// Each group must have the same next free id in both, and every id
+    /// covered by segments must have a name. The first problem found is
+    /// reported as [`DagError::Inconsistent`]. This visits every id, so it is
+    /// meant for diagnosing and repairing, not for regular use.
+    pub fn verify(&self) -> Result<()> {
+        let inconsistent =
+            |message: String| -> anyhow::Error { DagError::Inconsistent { message }.into() };
+        for &group in Group::ALL.iter() {
+            let dag_next = self.dag.next_free_id(0, group)?;
+            let map_next = self.map.next_free_id(group)?;
+            if dag_next != map_next {
+                return Err(inconsistent(format!(
+                    "next free id is {} in segments, but {} in IdMap",
+                    dag_next, map_next
+                )));
+            }
+        }
+        for id in self.dag.all()?.iter().rev() {
+            if self.map.find_name_by_id(id)?.is_none() {
+                return Err(inconsistent(format!(
+                    "{} is covered by segments, but has no name in IdMap",
+                    id
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    /// Collect segment statistics, useful for diagnosing graphs that do not
+    /// compress well into segments.
+    pub fn stats(&self) -> Result<DagStats> {
+        let mut stats = DagStats {
+            max_level: self.dag.max_level()?,
+            ..Default::default()
+        };
+        for &group in Group::ALL.iter() {
+            let size = self.dag.next_free_id(0, group)?.0 - group.min_id().0;
+            if group == Group::MASTER {
+                stats.master_group_size = size;
+            } else {
+                stats.non_master_group_size = size;
+            }
+            for level in 0..=stats.max_level {
+                let segments = self.dag.next_segments(group.min_id(), level)?;
+                if level > 0 {
+                    stats.high_level_segment_count += segments.len();
+                    continue;
+                }
+                stats.flat_segment_count += segments.len();
+                for segment in segments {
+                    if segment.span()?.count() == 1 {
+                        stats.single_vertex_segment_count += 1;
+                    }
+                }
+            }
+        }
+        let vertex_count = stats.master_group_size + stats.non_master_group_size;
+        if stats.flat_segment_count > 0 {
+            stats.average_flat_segment_length =
+                vertex_count as f64 / stats.flat_segment_count as f64;
+        }
+        Ok(stats)
+    }
+
+    /// Names assigned after `watermark`, sorted by id.
+    ///
+    /// Only ids in the same group as `watermark` are considered. Useful for
+    /// polling for new vertexes: pass the largest id seen so far.
+    pub fn names_since(&self, watermark: Id) -> Result<Vec<(Id, Box<[u8]>)>> {
+        let next_free = self.map.next_free_id(watermark.group())?;
+        let mut result = Vec::new();
+        let mut id = watermark + 1;
+        while id < next_free {
+            if let Some(name) = self.map.find_name_by_id(id)? {
+                result.push((id, name.to_vec().into_boxed_slice()));
+            }
+            id = id + 1;
+        }
+        Ok(result)
+    }
+
+    /// Length of the longest path from `id` to a root. Roots have generation 0.
+    ///
+    /// Inside a flat segment the only parent of each vertex is the previous
+    /// one, so only the generation of the lowest id of each flat segment is
+    /// calculated, and kept in `low_generations` to be reused across calls.
+    fn generation_by_id(&self, id: Id, low_generations: &mut HashMap<Id, u64>) -> Result<u64> {
+        let (low, parents) = self.flat_segment_low_and_parents(id)?;
+        let mut to_calculate = vec![(low, parents)];
+        while let Some((low, parents)) = to_calculate.last().cloned() {
+            if low_generations.contains_key(&low) {
+                to_calculate.pop();
+                continue;
+            }
+            let mut generation = 0;
+            let mut missing = None;
+            for parent in parents {
+                let (parent_low, parent_parents) = self.flat_segment_low_and_parents(parent)?;
+                match low_generations.get(&parent_low) {
+                    Some(low_generation) => {
+                        generation = generation.max(low_generation + (parent.0 - parent_low.0) + 1)
+                    }
+                    None => {
+                        missing = Some((parent_low, parent_parents));
+                        break;
+                    }
+                }
+            }
+            match missing {
+                Some(parent) => to_calculate.push(parent),
+                None => {
+                    low_generations.insert(low, generation);
+                    to_calculate.pop();
+                }
+            }
+        }
+        Ok(low_generations[&low] + (id.0 - low.0))
+    }
+
+    /// The lowest id of the flat segment containing `id`, and its parents.
+    fn flat_segment_low_and_parents(&self, id: Id) -> Result<(Id, Vec<Id>)> {
+        let segment = self
+            .dag
+            .find_flat_segment_including_id(id)?
+            .ok_or_else(|| anyhow!("id {} is not covered by dag", id))?;
+        Ok((segment.span()?.low, segment.parents()?))
+    }
+
     /// Return parent relationship for non-master vertexes reachable from heads
     /// added by `add_heads`.
     fn pending_graph(&self) -> Result<HashMap<VertexName, Vec<VertexName>>> {
@@ -270,12 +836,215 @@ impl NameDag {
         Ok(parents_map)
     }
 
-    // TODO: Consider implementing these:
-    // - NamedSpanSet - SpanSet wrapper that only exposes "names".
-    //   - Potentially, it has to implement smartset-like interfaces.
-    // - On NameDag, methods wrapping dag algorithms that uses NamedSpanSet
-    //   as input and output.
-    // Before those APIs, LowLevelAccess might have to be used by callsites.
+    // Dag algorithms taking and returning names are in the `impl NameDag`
+    // block below, using [`NameSet`] backed by id spans (`DagSet`) to avoid
+    // translating every vertex.
+}
+
+// Segment bundles. Those transfer segments and names between `NameDag`s
+// without recalculating segments on the destination.
+//
+// Serialization format:
+//
+// ```plain,ignore
+// BUNDLE := VERSION (1B) + vlq(SEGMENT_COUNT) + SEGMENT* + vlq(NAME_COUNT) + NAME*
+// SEGMENT := vlq(LEN) + flat segment bytes (see `Segment`)
+// NAME := vlq(ID) + vlq(LEN) + name bytes
+// ```
+
+const SEGMENT_BUNDLE_VERSION: u8 = 1;
+
+impl NameDag {
+    /// Export flat segments and names covering the ancestors of `heads`.
+    ///
+    /// The result can be loaded into an empty `NameDag` using
+    /// [`NameDag::import_segments`].
+    ///
+    /// Ids in a group have to be contiguous. So the bundle covers all ids up
+    /// to the largest ancestor id in each group, which might include a few
+    /// vertexes that are not ancestors of `heads`. Those can have parents
+    /// above the largest ancestor id of another group, for example a
+    /// non-master vertex branching from a later master vertex, so their
+    /// ancestors are covered too.
+    pub fn export_segments(&self, heads: &[VertexName]) -> Result<Vec<u8>> {
+        let mut head_ids = SpanSet::empty();
+        for head in heads {
+            head_ids.push(self.map.vertex_id(head.clone())?);
+        }
+        let mut covered = self.dag.ancestors(head_ids)?;
+        loop {
+            let mut ranges = SpanSet::empty();
+            for &group in Group::ALL.iter() {
+                let group_ids: SpanSet = (group.min_id()..=group.max_id()).into();
+                if let Some(max_id) = covered.intersection(&group_ids).max() {
+                    ranges.push(group.min_id()..=max_id);
+                }
+            }
+            let closure = self.dag.ancestors(ranges)?;
+            if closure.count() == covered.count() {
+                break;
+            }
+            covered = closure;
+        }
+
+        let mut segments = Vec::new();
+        let mut names = Vec::new();
+        for &group in Group::ALL.iter() {
+            let group_ids: SpanSet = (group.min_id()..=group.max_id()).into();
+            let max_id = match covered.intersection(&group_ids).max() {
+                Some(id) => id,
+                None => continue,
+            };
+            for segment in self.dag.next_segments(group.min_id(), 0)? {
+                let span = segment.span()?;
+                if span.low > max_id {
+                    break;
+                }
+                // A prefix of a flat segment is still a valid flat segment.
+                // But its head is no longer known to be the only head.
+                let mut flags = segment.flags()?;
+                let high = if span.high > max_id {
+                    flags.remove(SegmentFlags::ONLY_HEAD);
+                    max_id
+                } else {
+                    span.high
+                };
+                segments.push(Segment::new(flags, 0, span.low, high, &segment.parents()?));
+            }
+            for id in group.min_id().to(max_id) {
+                names.push((id, self.map.vertex_name(id)?));
+            }
+        }
+
+        let mut buf = vec![SEGMENT_BUNDLE_VERSION];
+        buf.write_vlq(segments.len())?;
+        for segment in segments.iter() {
+            buf.write_vlq(segment.0.len())?;
+            buf.extend_from_slice(&segment.0);
+        }
+        buf.write_vlq(names.len())?;
+        for (id, name) in names.iter() {
+            buf.write_vlq(id.0)?;
+            buf.write_vlq(name.as_ref().len())?;
+            buf.extend_from_slice(name.as_ref());
+        }
+        Ok(buf)
+    }
+
+    /// Import segments and names exported by [`NameDag::export_segments`],
+    /// and write them to disk.
+    ///
+    /// The `NameDag` must be empty.
+    pub fn import_segments(&mut self, bundle: &[u8]) -> Result<()> {
+        ensure!(
+            self.pending_heads.is_empty(),
+            "ProgrammingError: import_segments called with pending heads ({:?})",
+            &self.pending_heads,
+        );
+        let SegmentBundle { segments, names } = SegmentBundle::decode(bundle)?;
+
+        // Take lock. Similar to `add_heads_and_flush`.
+        self.write_with(|map, dag| {
+            for &group in Group::ALL.iter() {
+                ensure!(
+                    map.next_free_id(group)? == group.min_id(),
+                    "cannot import segments into a non-empty NameDag"
+                );
+            }
+
+            for (id, name) in names {
+                map.insert(id, name.as_ref())?;
+            }
+            dag.import_flat_segments(&segments)?;
+            Ok(())
+        })
+    }
+}
+
+/// Decoded content of a bundle produced by [`NameDag::export_segments`].
+struct SegmentBundle {
+    segments: Vec<Segment>,
+    names: Vec<(Id, VertexName)>,
+}
+
+impl SegmentBundle {
+    /// Parse a bundle. Check that segments are flat and leave no gaps, their
+    /// parents are in the bundle, and every id covered by them has a name.
+    fn decode(bundle: &[u8]) -> Result<Self> {
+        let mut cur = Cursor::new(bundle);
+        let read_bytes = |cur: &mut Cursor<&[u8]>| -> Result<Vec<u8>> {
+            let len: usize = cur.read_vlq()?;
+            let mut bytes = vec![0; len];
+            cur.read_exact(&mut bytes)?;
+            Ok(bytes)
+        };
+
+        let mut version = [0u8];
+        cur.read_exact(&mut version)?;
+        ensure!(
+            version[0] == SEGMENT_BUNDLE_VERSION,
+            "unsupported segment bundle version: {}",
+            version[0]
+        );
+
+        let segment_count: usize = cur.read_vlq()?;
+        let mut segments = Vec::with_capacity(segment_count);
+        let mut covered = SpanSet::empty();
+        let mut parents = SpanSet::empty();
+        let mut next_id: Option<Id> = None;
+        for _ in 0..segment_count {
+            let segment = Segment(Bytes::from(read_bytes(&mut cur)?));
+            ensure!(
+                segment.level()? == 0,
+                "segment bundle contains a high-level segment {:?}",
+                &segment
+            );
+            let span = segment.span()?;
+            let expected_low = match next_id {
+                Some(id) if id.group() == span.low.group() => id,
+                _ => span.low.group().min_id(),
+            };
+            ensure!(
+                span.low == expected_low,
+                "segment bundle has a gap before {:?}",
+                span.low
+            );
+            next_id = Some(span.high + 1);
+            covered.push(span);
+            for parent in segment.parents()? {
+                parents.push(parent);
+            }
+            segments.push(segment);
+        }
+        ensure!(
+            parents.difference(&covered).is_empty(),
+            "segment bundle has segments with parents outside the bundle"
+        );
+
+        let name_count: usize = cur.read_vlq()?;
+        let mut names = Vec::with_capacity(name_count);
+        let mut named = SpanSet::empty();
+        for _ in 0..name_count {
+            let id = Id(cur.read_vlq()?);
+            let name = VertexName::copy_from(&read_bytes(&mut cur)?);
+            named.push(id);
+            names.push((id, name));
+        }
+        ensure!(
+            named.count() == names.len() as u64 && named.difference(&covered).is_empty(),
+            "segment bundle has names that are duplicated or not covered by segments"
+        );
+        ensure!(
+            covered.difference(&named).is_empty(),
+            "segment bundle has ids without names"
+        );
+        ensure!(
+            cur.position() == bundle.len() as u64,
+            "segment bundle has trailing data"
+        );
+
+        Ok(Self { segments, names })
+    }
 }
 
 // Dag operations. Those are just simple wrappers around [`IdDag`].
@@ -329,6 +1098,14 @@ impl NameDag {
         Ok(NameSet::from_spans_idmap(spans, self.snapshot_map.clone()))
     }
 
+    /// Calculates roots of the whole DAG, that is vertexes without parents.
+    ///
+    /// There is more than one root if the DAG has unrelated histories.
+    pub fn all_roots(&self) -> Result<NameSet> {
+        let spans = self.dag.roots(self.dag.all()?)?;
+        Ok(NameSet::from_spans_idmap(spans, self.snapshot_map.clone()))
+    }
+
     /// Calculates one "greatest common ancestor" of the given set.
     ///
     /// If there are no common ancestors, return None.
@@ -361,6 +1138,33 @@ impl NameDag {
         self.dag.is_ancestor(ancestor_id, descendant_id)
     }
 
+    /// Tests if `ancestor` is an ancestor of `descendant` for each
+    /// `(ancestor, descendant)` pair, returning the answers in input order.
+    ///
+    /// This is faster than calling `is_ancestor` for each pair, as the
+    /// ancestors of each distinct descendant are only calculated once.
+    pub fn is_ancestor_batch(&self, pairs: &[(&[u8], &[u8])]) -> Result<Vec<bool>> {
+        let ids = pairs
+            .iter()
+            .map(|(ancestor, descendant)| {
+                Ok((
+                    self.map.vertex_id(VertexName::copy_from(ancestor))?,
+                    self.map.vertex_id(VertexName::copy_from(descendant))?,
+                ))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        let mut ancestors: HashMap<Id, SpanSet> = HashMap::new();
+        ids.into_iter()
+            .map(|(ancestor_id, descendant_id)| {
+                let set = match ancestors.entry(descendant_id) {
+                    Entry::Occupied(entry) => entry.into_mut(),
+                    Entry::Vacant(entry) => entry.insert(self.dag.ancestors(descendant_id)?),
+                };
+                Ok(set.contains(ancestor_id))
+            })
+            .collect()
+    }
+
     /// Calculates "heads" of the ancestors of the given set. That is,
     /// Find Y, which is the smallest subset of set X, where `ancestors(Y)` is
     /// `ancestors(X)`.
@@ -442,6 +1246,15 @@ pub fn rebuild_non_master(
 ) -> Result<()> {
     // backup part of the named graph in memory.
     let parents = non_master_parent_names(map, dag)?;
+    rebuild_non_master_from_parents(map, dag, parents)
+}
+
+/// Replace the non-master group with the graph described by `parents`.
+fn rebuild_non_master_from_parents(
+    map: &mut SyncableIdMap,
+    dag: &mut SyncableIdDag<IndexedLogStore>,
+    parents: HashMap<VertexName, Vec<VertexName>>,
+) -> Result<()> {
     let mut heads = parents
         .keys()
         .collect::<HashSet<_>>()
@@ -17,6 +17,10 @@ use std::sync::Arc;
 
 /// A set backed by [`SpanSet`] + [`IdMap`].
 /// Efficient for DAG calculation.
+///
+/// Iteration order is defined by ids: `iter` yields names in descending id
+/// order (heads first, reverse-topological), and `iter_rev` yields them in
+/// ascending id order (roots first, topological).
 pub struct DagSet {
     pub(crate) spans: SpanSet,
     pub(crate) map: Arc<IdMap>,
@@ -247,6 +251,26 @@ pub(crate) mod tests {
         })
     }
 
+    #[test]
+    fn test_dag_iter_order() -> Result<()> {
+        with_dag(|dag| -> Result<()> {
+            let names = |iter: Result<Box<dyn NameIter>>| -> Result<String> {
+                let names = iter?
+                    .map(|n| Ok(String::from_utf8_lossy(n?.as_ref()).to_string()))
+                    .collect::<Result<Vec<String>>>()?;
+                Ok(names.join(" "))
+            };
+            let set = dag.ancestors("D G".into())?;
+            assert_eq!(names(set.iter())?, "G F E D C B A");
+            assert_eq!(names(set.iter_rev())?, "A B C D E F G");
+
+            let set = dag.range("B".into(), "F".into())?;
+            assert_eq!(names(set.iter())?, "F E B");
+            assert_eq!(names(set.iter_rev())?, "B E F");
+            Ok(())
+        })
+    }
+
     #[test]
     fn test_sort() -> Result<()> {
         with_dag(|dag| -> Result<()> {
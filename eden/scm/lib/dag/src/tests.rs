@@ -5,14 +5,17 @@
  * GNU General Public License version 2.
  */
 
+use crate::errors::DagError;
 use crate::id::{Group, Id, VertexName};
 use crate::iddag::FirstAncestorConstraint;
 use crate::protocol::{Process, RequestLocationToName, RequestNameToLocation};
+use crate::segment::{Segment, SegmentFlags};
 use crate::IdMap;
 use crate::NameDag;
 use crate::NameSet;
 use crate::SpanSet;
 use anyhow::Result;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 use tempfile::tempdir;
 
 // Example from segmented-changelog.pdf
@@ -87,6 +90,10 @@ fn test_namedag() -> Result<()> {
     assert_eq!(expand(dag.roots(nameset("E G H J I K D"))?), "I D E");
     assert_eq!(dag.gca_one(nameset("J K"))?, Some(v("I")));
     assert_eq!(expand(dag.gca_all(nameset("J K"))?), "I H");
+    assert_eq!(dag.gca_one(nameset(""))?, None);
+    assert_eq!(expand(dag.gca_all(nameset(""))?), "");
+    let err = dag.gca_all(nameset("J X")).unwrap_err().to_string();
+    assert!(err.contains("X"), "error should name the vertex: {}", err);
     assert_eq!(expand(dag.common_ancestors(nameset("G H"))?), "E B A");
     assert!(dag.is_ancestor(v("B"), v("K"))?);
     assert!(!dag.is_ancestor(v("K"), v("B"))?);
@@ -509,6 +516,679 @@ Lv1: R0-2[] 3-3[0, 1]"#
     );
 }
 
+#[test]
+fn test_namedag_best_common_ancestor() -> Result<()> {
+    // C and D are criss-cross merges of A and B. A is closer to the heads.
+    let ascii = r#"
+            B---C
+             \ /
+        Z---A---D"#;
+    let result = build_segments(ascii, "C D", 3);
+    let dag = &result.name_dag;
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+
+    assert_eq!(dag.best_common_ancestor(v("C"), v("D"))?, Some(v("A")));
+    assert_eq!(dag.best_common_ancestor(v("C"), v("A"))?, Some(v("A")));
+    assert_eq!(dag.best_common_ancestor(v("Z"), v("B"))?, None);
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_generation() -> Result<()> {
+    let result = build_segments(ASCII_DAG1, "L", 3);
+    let dag = &result.name_dag;
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+
+    assert_eq!(dag.generation(v("A"))?, Some(0));
+    assert_eq!(dag.generation(v("C"))?, Some(0));
+    assert_eq!(dag.generation(v("B"))?, Some(1));
+    // E merges B and D. H is 3 hops down from E.
+    assert_eq!(dag.generation(v("E"))?, Some(2));
+    assert_eq!(dag.generation(v("H"))?, Some(5));
+    assert_eq!(dag.generation(v("Z"))?, None);
+
+    // Matches the longest path calculated from the parents directly, with
+    // many small segments and merges.
+    let result = build_segments(ASCII_DAG2, "W", 3);
+    let dag = &result.name_dag;
+    let parents = drawdag::parse(ASCII_DAG2);
+    fn longest_path(name: &str, parents: &BTreeMap<String, BTreeSet<String>>) -> u64 {
+        parents[name]
+            .iter()
+            .map(|p| longest_path(p, parents) + 1)
+            .max()
+            .unwrap_or(0)
+    }
+    for name in parents.keys() {
+        assert_eq!(
+            dag.generation(v(name))?,
+            Some(longest_path(name, &parents)),
+            "generation of {}",
+            name
+        );
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_parent_names() -> Result<()> {
+    let result = build_segments(
+        r#"
+        A---B---C---e
+             \     /
+              d----"#,
+        "C e",
+        3,
+    );
+    let dag = &result.name_dag;
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+
+    assert_eq!(dag.parent_names(v("A"))?, vec![]);
+    assert_eq!(dag.parent_names(v("C"))?, vec![v("B")]);
+    // Non-master vertexes can have parents in either group.
+    assert_eq!(dag.parent_names(v("d"))?, vec![v("B")]);
+    assert_eq!(dag.parent_names(v("e"))?, vec![v("C"), v("d")]);
+    assert!(dag.parent_names(v("Z")).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_remap_names() -> Result<()> {
+    let mut result = build_segments(ASCII_DAG1, "L", 3);
+    let dag = &mut result.name_dag;
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+    let b = |name: &str| -> Box<[u8]> { name.as_bytes().to_vec().into_boxed_slice() };
+
+    let parents_before = dag.parent_names(v("E"))?;
+    let mapping: HashMap<_, _> = vec![(b("E"), b("X"))].into_iter().collect();
+    dag.remap_names(&mapping)?;
+
+    // The new name takes over the vertex and its position in the graph.
+    assert_eq!(dag.parent_names(v("X"))?, parents_before);
+    assert_eq!(dag.generation(v("X"))?, Some(2));
+    let x = NameSet::from_static_names(vec![v("X")]);
+    assert!(dag.children(x.clone())?.contains(&v("F"))?);
+    assert!(dag.ancestors(x)?.contains(&v("D"))?);
+    assert!(dag.parent_names(v("F"))?.contains(&v("X")));
+
+    // The old name no longer resolves.
+    assert!(dag.parent_names(v("E")).is_err());
+    assert_eq!(dag.generation(v("E"))?, None);
+
+    // New names must not collide with existing ones.
+    let mapping: HashMap<_, _> = vec![(b("X"), b("A"))].into_iter().collect();
+    assert!(dag.remap_names(&mapping).is_err());
+    let mapping: HashMap<_, _> = vec![(b("Z"), b("Y"))].into_iter().collect();
+    assert!(dag.remap_names(&mapping).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_in_memory() -> Result<()> {
+    let mut dag = NameDag::in_memory()?;
+    let parents = drawdag::parse("A-B-C-D-E");
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+    let parents_by_name = |name: VertexName| -> Result<Vec<VertexName>> {
+        Ok(parents[&String::from_utf8(name.as_ref().to_vec()).unwrap()]
+            .iter()
+            .map(|p| v(p))
+            .collect())
+    };
+
+    dag.add_heads_and_flush(&parents_by_name, &[v("C")], &[])?;
+    dag.add_heads(&parents_by_name, &[v("E")])?;
+    assert_eq!(dag.all()?.count()?, 5);
+
+    // Flushed vertexes survive reload, pending ones do not.
+    dag.reload()?;
+    assert_eq!(dag.all()?.count()?, 3);
+
+    // flush re-assigns pending vertexes to the master group.
+    dag.add_heads(&parents_by_name, &[v("E")])?;
+    dag.flush(&[v("E")])?;
+    dag.reload()?;
+    assert_eq!(dag.all()?.count()?, 5);
+    assert_eq!(dag.stats()?.master_group_size, 5);
+    assert_eq!(dag.parent_names(v("E"))?, vec![v("D")]);
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_assign_many_heads() -> Result<()> {
+    // A chain of 10k vertexes. `assign_head` stops at assigned vertexes, so
+    // passing many heads to one build does not traverse the chain again for
+    // each head.
+    let n = 10000;
+    let v = |i: usize| -> VertexName { VertexName::copy_from(format!("{}", i).as_bytes()) };
+    let calls = std::cell::Cell::new(0);
+    let parents = |name: VertexName| -> Result<Vec<VertexName>> {
+        calls.set(calls.get() + 1);
+        let i: usize = std::str::from_utf8(name.as_ref())?.parse()?;
+        Ok(if i == 0 { vec![] } else { vec![v(i - 1)] })
+    };
+
+    let heads: Vec<VertexName> = (1..=n / 10).map(|i| v(i * 10 - 1)).collect();
+    let mut batch = NameDag::in_memory()?;
+    batch.add_heads_and_flush(&parents, &heads, &[])?;
+    // Once per vertex to assign ids, once more to build segments.
+    assert_eq!(batch.all()?.count()?, n);
+    assert!(calls.get() <= 2 * n);
+
+    let mut single = NameDag::in_memory()?;
+    for head in heads.iter() {
+        single.add_heads_and_flush(&parents, &[head.clone()], &[])?;
+    }
+    assert_eq!(
+        batch.names_since(Group::MASTER.min_id())?,
+        single.names_since(Group::MASTER.min_id())?
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_contains_all() -> Result<()> {
+    let result = build_segments(
+        r#"
+        A---B---c"#,
+        "B c",
+        3,
+    );
+    let dag = &result.name_dag;
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+
+    assert!(dag.contains_all(&[], &[]));
+    assert!(dag.contains_all(&[v("A"), v("B")], &[v("c")]));
+    // "c" exists, but not in the master group.
+    assert!(!dag.contains_all(&[v("c")], &[]));
+    assert!(!dag.contains_all(&[v("B")], &[v("Z")]));
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_remove_non_master() -> Result<()> {
+    let mut result = build_segments(
+        r#"
+        A---B---c---d
+             \
+              e"#,
+        "B d e",
+        3,
+    );
+    let dag = &mut result.name_dag;
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+    let b = |name: &str| -> Box<[u8]> { name.as_bytes().to_vec().into_boxed_slice() };
+
+    // Master vertexes and unknown names are rejected.
+    assert!(dag.remove_non_master(&[b("B")]).is_err());
+    assert!(dag.remove_non_master(&[b("Z")]).is_err());
+    assert_eq!(dag.all()?.count()?, 5);
+
+    // Removing "c" also removes its descendant "d".
+    dag.remove_non_master(&[b("c")])?;
+    assert_eq!(dag.all()?.count()?, 3);
+    assert!(dag.parent_names(v("c")).is_err());
+    assert!(dag.parent_names(v("d")).is_err());
+    assert_eq!(dag.parent_names(v("e"))?, vec![v("B")]);
+
+    // The change is written to disk.
+    let dag = NameDag::open(result.dir.path().join("n"))?;
+    assert_eq!(dag.all()?.count()?, 3);
+    assert_eq!(dag.stats()?.non_master_group_size, 1);
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_stats() -> Result<()> {
+    let linear = build_segments("A-B-C-D-E-F-G-H", "H", 3).name_dag.stats()?;
+    assert_eq!(linear.flat_segment_count, 1);
+    assert_eq!(linear.single_vertex_segment_count, 0);
+    assert_eq!(linear.average_flat_segment_length, 8.0);
+    assert_eq!(linear.master_group_size, 8);
+    assert_eq!(linear.non_master_group_size, 0);
+
+    let branchy = build_segments(
+        r#"
+          B---D---F---H
+         / \ / \ / \ /
+        A---C---E---G---I---j"#,
+        "H I j",
+        3,
+    )
+    .name_dag
+    .stats()?;
+    assert_eq!(branchy.master_group_size, 9);
+    assert_eq!(branchy.non_master_group_size, 1);
+    assert!(branchy.flat_segment_count > linear.flat_segment_count);
+    assert!(branchy.single_vertex_segment_count > 0);
+    assert!(branchy.average_flat_segment_length < linear.average_flat_segment_length);
+    assert_eq!(
+        branchy.average_flat_segment_length,
+        10.0 / branchy.flat_segment_count as f64
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_names_since() -> Result<()> {
+    let mut result = build_segments(ASCII_DAG1, "H", 3);
+    let dag = &mut result.name_dag;
+    let watermark = dag.map.find_id_by_name(b"H")?.unwrap();
+    assert!(dag.names_since(watermark)?.is_empty());
+
+    let parents = drawdag::parse(ASCII_DAG1);
+    let parents_by_name = |name: VertexName| -> Result<Vec<VertexName>> {
+        Ok(parents[&String::from_utf8(name.as_ref().to_vec()).unwrap()]
+            .iter()
+            .map(|p| VertexName::copy_from(p.as_bytes()))
+            .collect())
+    };
+    dag.add_heads_and_flush(&parents_by_name, &[VertexName::copy_from(b"L")], &[])?;
+
+    let new_names = dag.names_since(watermark)?;
+    assert!(new_names.windows(2).all(|w| w[0].0 < w[1].0));
+    assert!(new_names.iter().all(|(id, _)| *id > watermark));
+    let mut names: Vec<String> = new_names
+        .iter()
+        .map(|(_, name)| String::from_utf8_lossy(name).to_string())
+        .collect();
+    names.sort();
+    assert_eq!(names, ["I", "J", "K", "L"]);
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_within_generations() -> Result<()> {
+    let result = build_segments(ASCII_DAG1, "L", 3);
+    let dag = &result.name_dag;
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+    let within = |heads: &[&str], k: u64| -> Result<String> {
+        let heads: Vec<VertexName> = heads.iter().map(|name| v(name)).collect();
+        let mut names = dag
+            .within_generations(&heads, k)?
+            .iter()?
+            .map(|n| Ok(String::from_utf8_lossy(n?.as_ref()).to_string()))
+            .collect::<Result<Vec<String>>>()?;
+        names.sort();
+        Ok(names.join(" "))
+    };
+
+    // H is at generation 5. F and G are at generations 3 and 4.
+    assert_eq!(dag.generation(v("H"))?, Some(5));
+    assert_eq!(within(&["H"], 2)?, "F G H");
+    assert_eq!(within(&["H"], 0)?, "H");
+    // I (generation 5) and J (generation 6) are not ancestors of H.
+    assert_eq!(within(&["H", "J"], 1)?, "G H I J");
+    // E merges D, which is only 1 away from the root C.
+    assert_eq!(within(&["E"], 1)?, "B D E");
+    assert_eq!(within(&["E"], 10)?, "A B C D E");
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_heads_of() -> Result<()> {
+    let result = build_segments(ASCII_DAG1, "L", 3);
+    let dag = &result.name_dag;
+    let heads_of = |names: &[&str]| -> Result<String> {
+        let names: Vec<&[u8]> = names.iter().map(|name| name.as_bytes()).collect();
+        let mut heads = dag
+            .heads_of(&names)?
+            .iter()?
+            .map(|n| Ok(String::from_utf8_lossy(n?.as_ref()).to_string()))
+            .collect::<Result<Vec<String>>>()?;
+        heads.sort();
+        Ok(heads.join(" "))
+    };
+
+    assert_eq!(heads_of(&["F", "G", "H"])?, "H");
+    assert_eq!(heads_of(&["H", "G", "F"])?, "H");
+    // Two independent chains
+    assert_eq!(heads_of(&["A", "B", "C", "D"])?, "B D");
+    assert_eq!(heads_of(&["G", "H", "I", "J"])?, "H J");
+    // Not contiguous, F is still an ancestor of H
+    assert_eq!(heads_of(&["F", "H"])?, "H");
+    assert_eq!(heads_of(&["A", "E", "I"])?, "I");
+    assert!(heads_of(&["A", "X"]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_toposort() -> Result<()> {
+    // Merges, and a non-master branch starting from a master commit.
+    let text = r#"
+              B---D---\
+             /     \   \
+            A---C---E---F---G
+                         \
+                          h---i"#;
+    let result = build_segments(text, "G i", 3);
+    let dag = &result.name_dag;
+    let parents = drawdag::parse(text);
+
+    let mut names: Vec<&String> = parents.keys().collect();
+    names.sort();
+    names.reverse();
+    let names: Vec<&[u8]> = names.iter().map(|name| name.as_bytes()).collect();
+    let sorted: Vec<String> = dag
+        .toposort(&names)?
+        .iter()
+        .map(|name| String::from_utf8_lossy(name).to_string())
+        .collect();
+    assert_eq!(sorted.len(), 9);
+
+    let position: HashMap<&str, usize> = sorted
+        .iter()
+        .enumerate()
+        .map(|(i, name)| (name.as_str(), i))
+        .collect();
+    for (child, child_parents) in parents.iter() {
+        for parent in child_parents {
+            assert!(
+                position[parent.as_str()] < position[child.as_str()],
+                "{} should come before {} in {:?}",
+                parent,
+                child,
+                sorted
+            );
+        }
+    }
+    assert!(dag.toposort(&[b"A", b"X"]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_is_ancestor_batch() -> Result<()> {
+    let result = build_segments(ASCII_DAG1, "L", 3);
+    let dag = &result.name_dag;
+    let pairs: Vec<(&[u8], &[u8])> = [
+        ("A", "L"),
+        ("L", "A"),
+        ("C", "E"),
+        ("I", "H"),
+        ("H", "L"),
+        ("E", "E"),
+        ("D", "J"),
+        ("J", "K"),
+    ]
+    .iter()
+    .map(|(a, b)| (a.as_bytes(), b.as_bytes()))
+    .collect();
+
+    let batch = dag.is_ancestor_batch(&pairs)?;
+    assert_eq!(
+        batch,
+        vec![true, false, true, false, true, true, true, true]
+    );
+    for (&(ancestor, descendant), &is_ancestor) in pairs.iter().zip(batch.iter()) {
+        assert_eq!(
+            dag.is_ancestor(
+                VertexName::copy_from(ancestor),
+                VertexName::copy_from(descendant)
+            )?,
+            is_ancestor
+        );
+    }
+    assert!(dag.is_ancestor_batch(&[(&b"A"[..], &b"X"[..])]).is_err());
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_all_roots() -> Result<()> {
+    let roots = |dag: &NameDag| -> Result<String> {
+        let mut roots = dag
+            .all_roots()?
+            .iter()?
+            .map(|n| Ok(String::from_utf8_lossy(n?.as_ref()).to_string()))
+            .collect::<Result<Vec<String>>>()?;
+        roots.sort();
+        Ok(roots.join(" "))
+    };
+
+    // A and C are unrelated roots, later merged by E.
+    let result = build_segments(ASCII_DAG1, "L", 3);
+    assert_eq!(roots(&result.name_dag)?, "A C");
+
+    // Roots in the non-master group are included.
+    let result = build_segments(
+        r#"
+            A-B
+            c-d"#,
+        "B d",
+        3,
+    );
+    assert_eq!(roots(&result.name_dag)?, "A c");
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_shortest_path() -> Result<()> {
+    let result = build_segments(
+        r#"
+            C-D-\     /--I--J--\
+        A-B------E-F-G-H--------K--L
+                              X-Y"#,
+        "L Y",
+        3,
+    );
+    let dag = &result.name_dag;
+    let path = |from: &str, to: &str| -> Result<Option<String>> {
+        Ok(dag
+            .shortest_path(from.as_bytes(), to.as_bytes())?
+            .map(|names| {
+                names
+                    .iter()
+                    .map(|name| String::from_utf8_lossy(name).to_string())
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }))
+    };
+
+    assert_eq!(path("A", "L")?.as_deref(), Some("A B E F G H K L"));
+    assert_eq!(path("L", "A")?.as_deref(), Some("L K H G F E B A"));
+    // Paths can go through children as well as parents.
+    assert_eq!(path("C", "A")?.as_deref(), Some("C D E B A"));
+    assert_eq!(path("H", "J")?.as_deref(), Some("H K J"));
+    assert_eq!(path("E", "E")?.as_deref(), Some("E"));
+    // X and Y are not connected to the rest.
+    assert_eq!(path("A", "Y")?, None);
+    assert_eq!(path("X", "Y")?.as_deref(), Some("X Y"));
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_export_import_segments() -> Result<()> {
+    let result = build_segments(ASCII_DAG2, "W", 3);
+    let dag = &result.name_dag;
+    let v = |name: &str| -> VertexName { VertexName::copy_from(name.as_bytes()) };
+    let expand = |set: NameSet| -> Result<String> {
+        Ok(set
+            .iter()?
+            .map(|n| Ok(String::from_utf8_lossy(n?.as_ref()).to_string()))
+            .collect::<Result<Vec<String>>>()?
+            .join(" "))
+    };
+
+    let bundle = dag.export_segments(&[v("W")])?;
+    let dir = tempdir()?;
+    let mut imported = NameDag::open(dir.path().join("imported"))?;
+    imported.dag.set_new_segment_size(3);
+    imported.import_segments(&bundle)?;
+
+    assert_eq!(format!("{:?}", imported.dag), format!("{:?}", dag.dag));
+    assert_eq!(expand(imported.all()?)?, expand(dag.all()?)?);
+    for name in ["E", "K", "P", "W"].iter() {
+        let set = NameSet::from_static_names(vec![v(name)]);
+        assert_eq!(
+            expand(imported.ancestors(set.clone())?)?,
+            expand(dag.ancestors(set.clone())?)?
+        );
+        assert_eq!(
+            expand(imported.descendants(set.clone())?)?,
+            expand(dag.descendants(set)?)?
+        );
+    }
+    assert_eq!(
+        imported.gca_one(NameSet::from_static_names(vec![v("O"), v("K")]))?,
+        dag.gca_one(NameSet::from_static_names(vec![v("O"), v("K")]))?
+    );
+
+    // Importing into a non-empty NameDag is an error.
+    assert!(imported.import_segments(&bundle).is_err());
+
+    // A partial export only covers ancestors of the given heads (plus
+    // vertexes with smaller ids).
+    let bundle = dag.export_segments(&[v("H")])?;
+    let dir = tempdir()?;
+    let mut partial = NameDag::open(dir.path().join("partial"))?;
+    partial.import_segments(&bundle)?;
+    let set = NameSet::from_static_names(vec![v("H")]);
+    assert_eq!(
+        expand(partial.ancestors(set.clone())?)?,
+        expand(dag.ancestors(set)?)?
+    );
+    assert!(partial.is_ancestor(v("A"), v("H"))?);
+    assert!(partial.map.find_id_by_name(b"W")?.is_none());
+
+    // "y" is exported with "x" as it has a smaller id, so its master
+    // ancestors are exported too.
+    let text = r#"
+            A---B---C---D
+                 \       \
+                  x       y"#;
+    let result = build_segments(text, "D y x", 3);
+    let bundle = result.name_dag.export_segments(&[v("x")])?;
+    let dir = tempdir()?;
+    let mut branch = NameDag::open(dir.path().join("branch"))?;
+    branch.import_segments(&bundle)?;
+    branch.verify()?;
+    assert_eq!(expand(branch.all()?)?, expand(result.name_dag.all()?)?);
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_import_segments_missing_parents() -> Result<()> {
+    // Master segments 0..=1 and 2..=2, the second with a parent not in the
+    // bundle.
+    let mut bundle = vec![1, 2];
+    for segment in [
+        Segment::new(SegmentFlags::HAS_ROOT, 0, Id(0), Id(1), &[]),
+        Segment::new(SegmentFlags::empty(), 0, Id(2), Id(2), &[Id(5)]),
+    ]
+    .iter()
+    {
+        bundle.push(segment.0.len() as u8);
+        bundle.extend_from_slice(&segment.0);
+    }
+    bundle.push(3);
+    for (id, name) in [(0, b"A"), (1, b"B"), (2, b"C")].iter() {
+        bundle.extend_from_slice(&[*id, 1]);
+        bundle.extend_from_slice(&name[..]);
+    }
+
+    let dir = tempdir()?;
+    let mut dag = NameDag::open(dir.path().join("n"))?;
+    let err = dag
+        .import_segments(&bundle)
+        .err()
+        .expect("import should fail");
+    assert_eq!(
+        err.to_string(),
+        "segment bundle has segments with parents outside the bundle"
+    );
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_verify() -> Result<()> {
+    let mut result = build_segments(ASCII_DAG1, "L", 3);
+    let dag = &mut result.name_dag;
+    dag.verify()?;
+
+    // A name without segments.
+    let id = dag.map.next_free_id(Group::MASTER)?;
+    dag.map.insert(id, b"X")?;
+    let err = dag.verify().err().expect("verify should fail");
+    assert_eq!(
+        err.to_string(),
+        "dag and idmap are inconsistent: next free id is 12 in segments, but 13 in IdMap"
+    );
+    match err.downcast_ref::<DagError>() {
+        Some(DagError::Inconsistent { .. }) => {}
+        other => panic!("unexpected error: {:?}", other),
+    }
+
+    Ok(())
+}
+
+#[test]
+fn test_namedag_format_version() -> Result<()> {
+    let dir = tempdir()?;
+    let path = dir.path().join("n");
+    NameDag::open(&path)?;
+    assert_eq!(std::fs::read_to_string(path.join("version"))?, "1");
+    NameDag::open(&path)?;
+
+    std::fs::write(path.join("version"), "0")?;
+    let err = NameDag::open(&path).err().expect("open should fail");
+    assert_eq!(
+        err.downcast_ref::<DagError>(),
+        Some(&DagError::IncompatibleVersion {
+            found: 0,
+            expected: 1
+        })
+    );
+    assert_eq!(
+        err.to_string(),
+        "incompatible dag format version (found 0, expected 1)"
+    );
+
+    // A directory from before the version file is only upgraded on write.
+    std::fs::remove_file(path.join("version"))?;
+    let mut dag = NameDag::open(&path)?;
+    assert!(!path.join("version").exists());
+    let parents_by_name = |_: VertexName| -> Result<Vec<VertexName>> { Ok(Vec::new()) };
+    dag.add_heads_and_flush(&parents_by_name, &[VertexName::copy_from(b"A")], &[])?;
+    assert_eq!(std::fs::read_to_string(path.join("version"))?, "1");
+
+    // A newer format is rejected before its logs are opened, and left as is.
+    let newer_path = dir.path().join("newer");
+    NameDag::open(&newer_path)?;
+    std::fs::write(newer_path.join("version"), "2")?;
+    std::fs::remove_dir_all(newer_path.join("idmap"))?;
+    std::fs::write(newer_path.join("idmap"), "from a newer format")?;
+    let err = NameDag::open(&newer_path).err().expect("open should fail");
+    assert_eq!(
+        err.downcast_ref::<DagError>(),
+        Some(&DagError::IncompatibleVersion {
+            found: 2,
+            expected: 1
+        })
+    );
+    assert_eq!(
+        std::fs::read_to_string(newer_path.join("idmap"))?,
+        "from a newer format"
+    );
+
+    Ok(())
+}
+
 #[test]
 fn test_parents() {
     let result = build_segments(ASCII_DAG1, "L", 3);
@@ -14,12 +14,16 @@
 
 #![deny(warnings)]
 
-use std::fs::File;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::fmt::Debug;
+use std::fs::{self, File};
+use std::hash::Hash;
 use std::io::{prelude::*, stdin, stdout};
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{anyhow, Result};
+use curl::easy::Easy;
 use serde_json::Value;
 use structopt::StructOpt;
 
@@ -34,23 +38,180 @@ enum Command {
     Data(Args),
     History(Args),
     Tree(Args),
+    #[structopt(about = "Print the differences between two request JSON files")]
+    Diff(DiffArgs),
+}
+
+#[derive(Debug, StructOpt)]
+struct DiffArgs {
+    #[structopt(help = "Kind of request in both files (data, history or tree)")]
+    kind: RequestKind,
+    #[structopt(help = "Old input JSON file")]
+    left: PathBuf,
+    #[structopt(help = "New input JSON file")]
+    right: PathBuf,
+}
+
+#[derive(Debug)]
+enum RequestKind {
+    Data,
+    History,
+    Tree,
+}
+
+impl FromStr for RequestKind {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "data" => Ok(RequestKind::Data),
+            "history" => Ok(RequestKind::History),
+            "tree" => Ok(RequestKind::Tree),
+            _ => Err(anyhow!("unknown request kind: {}", s)),
+        }
+    }
+}
+
+/// How `--input` is formatted.
+#[derive(Debug)]
+enum InputFormat {
+    /// A JSON request, or an array of them.
+    Json,
+    /// One `<path> <hash>` key per line.
+    Lines,
+}
+
+impl FromStr for InputFormat {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "json" => Ok(InputFormat::Json),
+            "lines" => Ok(InputFormat::Lines),
+            _ => Err(anyhow!("unknown input format: {}", s)),
+        }
+    }
 }
 
 #[derive(Debug, StructOpt)]
 struct Args {
-    #[structopt(long, short, help = "Input JSON file (stdin is used if omitted)")]
+    #[structopt(
+        long,
+        short,
+        help = "Input JSON file or http(s) URL (stdin is used if omitted). An array of \
+                requests makes a batch"
+    )]
     input: Option<PathBuf>,
-    #[structopt(long, short, help = "Output CBOR file (stdout is used if omitted)")]
+    #[structopt(
+        long,
+        short,
+        help = "Output CBOR file (stdout is used if omitted). For a batch, the requests are \
+                concatenated, or written to numbered files if this is a directory"
+    )]
     output: Option<PathBuf>,
+    #[structopt(
+        long,
+        default_value = "json",
+        help = "Input format: json, or lines of \"<path> <hash>\" keys for data and history \
+                requests, which can repeat paths"
+    )]
+    format: InputFormat,
+    #[structopt(
+        long,
+        help = "Merge into the existing output file (if present) instead of overwriting it"
+    )]
+    append: bool,
+    #[structopt(
+        long,
+        help = "Fail if the same hash appears more than once in the input"
+    )]
+    require_distinct_hashes: bool,
+    #[structopt(
+        long,
+        value_name = "url",
+        help = "Print a curl command to stderr that posts the generated payload to the given URL"
+    )]
+    print_curl: Option<String>,
+    #[structopt(
+        long,
+        help = "Sort keys and hashes so the same request always produces the same bytes. \
+                This changes the output compared to a run without this flag"
+    )]
+    canonical: bool,
+    #[structopt(
+        long,
+        help = "Decode the generated payload, print it to stderr, and fail if it does not match \
+                the request"
+    )]
+    verify: bool,
 }
 
 macro_rules! convert {
     ($args:ident, $parse_fn:ident) => {{
-        let json = read_input($args.input)?;
-        let req = $parse_fn(&json)?;
-        let bytes = serde_cbor::to_vec(&req)?;
-        eprintln!("Generated request: {:#?}", &req);
-        write_output($args.output, &bytes)
+        let (reqs, batch) = match $args.format {
+            InputFormat::Json => {
+                let json = read_input($args.input)?;
+                match json {
+                    Value::Array(jsons) if jsons.is_empty() => {
+                        return Err(anyhow!("input array contains no requests"));
+                    }
+                    Value::Array(jsons) => (
+                        jsons.iter().map($parse_fn).collect::<Result<Vec<_>>>()?,
+                        true,
+                    ),
+                    json => (vec![$parse_fn(&json)?], false),
+                }
+            }
+            InputFormat::Lines => {
+                let keys = parse_key_lines(&read_input_text($args.input)?)?;
+                (vec![KeysRequest::from_keys(keys)?], false)
+            }
+        };
+        if batch && $args.append {
+            return Err(anyhow!("--append cannot be used with a batch of requests"));
+        }
+
+        let mut payloads = Vec::new();
+        for mut req in reqs {
+            if $args.require_distinct_hashes {
+                check_distinct_hashes(&req)?;
+            }
+            if $args.append {
+                let path = $args
+                    .output
+                    .as_ref()
+                    .ok_or_else(|| anyhow!("--append requires --output"))?;
+                if path.exists() {
+                    req = append_to_file(path, req)?;
+                }
+            }
+            if $args.canonical {
+                req.canonicalize();
+            }
+            eprintln!("Generated request: {:#?}", &req);
+            let bytes = serde_cbor::to_vec(&req)?;
+            if $args.verify {
+                verify_round_trip(&req, &bytes)?;
+            }
+            payloads.push(bytes);
+        }
+
+        match $args.output {
+            Some(dir) if batch && dir.is_dir() => {
+                for path in write_batch(&dir, &payloads)? {
+                    if let Some(url) = &$args.print_curl {
+                        eprintln!("{}", curl_command(url, Some(&path)));
+                    }
+                }
+                Ok(())
+            }
+            output => {
+                if let Some(url) = &$args.print_curl {
+                    eprintln!("{}", curl_command(url, output.as_deref()));
+                }
+                write_output(output, &payloads.concat())
+            }
+        }
     }};
 }
 
@@ -59,9 +220,337 @@ fn main() -> Result<()> {
         Command::Data(args) => convert!(args, parse_data_req),
         Command::History(args) => convert!(args, parse_history_req),
         Command::Tree(args) => convert!(args, parse_tree_req),
+        Command::Diff(args) => {
+            let left = read_input(Some(args.left))?;
+            let right = read_input(Some(args.right))?;
+            let changes = match args.kind {
+                RequestKind::Data => parse_data_req(&left)?.diff(&parse_data_req(&right)?),
+                RequestKind::History => parse_history_req(&left)?.diff(&parse_history_req(&right)?),
+                RequestKind::Tree => parse_tree_req(&left)?.diff(&parse_tree_req(&right)?),
+            };
+            for change in changes {
+                println!("{}", change);
+            }
+            Ok(())
+        }
+    }
+}
+
+/// A request kind that can be compared by the `diff` subcommand.
+trait DiffRequest {
+    /// Describe how `other` differs from `self`, one change per line:
+    /// `+` for added entries, `-` for removed ones and `~` for changed ones.
+    fn diff(&self, other: &Self) -> Vec<String>;
+}
+
+impl DiffRequest for DataRequest {
+    fn diff(&self, other: &Self) -> Vec<String> {
+        diff_keys(&self.keys, &other.keys)
+    }
+}
+
+impl DiffRequest for HistoryRequest {
+    fn diff(&self, other: &Self) -> Vec<String> {
+        let mut changes = diff_keys(&self.keys, &other.keys);
+        changes.extend(diff_value("depth", &self.depth, &other.depth));
+        changes
+    }
+}
+
+impl DiffRequest for TreeRequest {
+    fn diff(&self, other: &Self) -> Vec<String> {
+        let mut changes = diff_value("rootdir", &self.rootdir, &other.rootdir);
+        changes.extend(diff_hashes("mfnodes", &self.mfnodes, &other.mfnodes));
+        changes.extend(diff_hashes(
+            "basemfnodes",
+            &self.basemfnodes,
+            &other.basemfnodes,
+        ));
+        changes.extend(diff_value("depth", &self.depth, &other.depth));
+        changes
+    }
+}
+
+/// Compare keys by path, ignoring their order. A path whose only hash was
+/// replaced by another is reported as changed rather than removed and added.
+fn diff_keys(left: &[Key], right: &[Key]) -> Vec<String> {
+    fn by_path(keys: &[Key]) -> BTreeMap<&str, BTreeSet<String>> {
+        let mut paths: BTreeMap<&str, BTreeSet<String>> = BTreeMap::new();
+        for key in keys {
+            paths
+                .entry(key.path.as_str())
+                .or_default()
+                .insert(key.hgid.to_hex());
+        }
+        paths
+    }
+
+    let left = by_path(left);
+    let right = by_path(right);
+    let empty = BTreeSet::new();
+    let paths: BTreeSet<&str> = left.keys().chain(right.keys()).cloned().collect();
+
+    let mut changes = Vec::new();
+    for path in paths {
+        let old = left.get(path).unwrap_or(&empty);
+        let new = right.get(path).unwrap_or(&empty);
+        let removed: Vec<_> = old.difference(new).collect();
+        let added: Vec<_> = new.difference(old).collect();
+        match (removed.as_slice(), added.as_slice()) {
+            ([old], [new]) => changes.push(format!("~ {:?}: {} -> {}", path, old, new)),
+            _ => {
+                changes.extend(removed.iter().map(|h| format!("- {:?}: {}", path, h)));
+                changes.extend(added.iter().map(|h| format!("+ {:?}: {}", path, h)));
+            }
+        }
+    }
+    changes
+}
+
+fn diff_hashes(field: &str, left: &[HgId], right: &[HgId]) -> Vec<String> {
+    let left: BTreeSet<String> = left.iter().map(|h| h.to_hex()).collect();
+    let right: BTreeSet<String> = right.iter().map(|h| h.to_hex()).collect();
+    let removed = left
+        .difference(&right)
+        .map(|h| format!("- {}: {}", field, h));
+    let added = right
+        .difference(&left)
+        .map(|h| format!("+ {}: {}", field, h));
+    removed.chain(added).collect()
+}
+
+fn diff_value<T: std::fmt::Debug + PartialEq>(field: &str, left: &T, right: &T) -> Vec<String> {
+    if left == right {
+        Vec::new()
+    } else {
+        vec![format!("~ {}: {:?} -> {:?}", field, left, right)]
+    }
+}
+
+/// A request kind whose existing payload can be extended by `--append`.
+trait AppendRequest: Sized {
+    /// Name of the subcommand producing this kind of request.
+    const KIND: &'static str;
+
+    /// Field names of the serialized request. Used to make sure an existing
+    /// file holds the same kind of request, since serde would otherwise
+    /// happily decode e.g. a history request as a data request.
+    const FIELDS: &'static [&'static str];
+
+    fn from_cbor(bytes: &[u8]) -> Result<Self>;
+
+    /// Merge `other` into `self`, skipping entries already present.
+    fn append(&mut self, other: Self);
+}
+
+impl AppendRequest for DataRequest {
+    const KIND: &'static str = "data";
+    const FIELDS: &'static [&'static str] = &["keys"];
+
+    fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+
+    fn append(&mut self, other: Self) {
+        extend_dedup(&mut self.keys, other.keys);
+    }
+}
+
+impl AppendRequest for HistoryRequest {
+    const KIND: &'static str = "history";
+    const FIELDS: &'static [&'static str] = &["keys", "depth"];
+
+    fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+
+    fn append(&mut self, other: Self) {
+        extend_dedup(&mut self.keys, other.keys);
+        if other.depth.is_some() {
+            self.depth = other.depth;
+        }
+    }
+}
+
+impl AppendRequest for TreeRequest {
+    const KIND: &'static str = "tree";
+    const FIELDS: &'static [&'static str] = &["rootdir", "mfnodes", "basemfnodes", "depth"];
+
+    fn from_cbor(bytes: &[u8]) -> Result<Self> {
+        Ok(serde_cbor::from_slice(bytes)?)
+    }
+
+    fn append(&mut self, other: Self) {
+        self.rootdir = other.rootdir;
+        extend_dedup(&mut self.mfnodes, other.mfnodes);
+        extend_dedup(&mut self.basemfnodes, other.basemfnodes);
+        if other.depth.is_some() {
+            self.depth = other.depth;
+        }
+    }
+}
+
+/// A request kind that can be put in a canonical form by `--canonical`.
+trait CanonicalRequest {
+    /// Sort the entries of the request, so that requests with the same
+    /// entries serialize to the same bytes whatever order they were given in.
+    fn canonicalize(&mut self);
+}
+
+impl CanonicalRequest for DataRequest {
+    fn canonicalize(&mut self) {
+        sort_keys(&mut self.keys);
+    }
+}
+
+impl CanonicalRequest for HistoryRequest {
+    fn canonicalize(&mut self) {
+        sort_keys(&mut self.keys);
+    }
+}
+
+impl CanonicalRequest for TreeRequest {
+    fn canonicalize(&mut self) {
+        self.mfnodes.sort();
+        self.basemfnodes.sort();
+    }
+}
+
+/// Sort keys by path, then by hash.
+fn sort_keys(keys: &mut [Key]) {
+    keys.sort_by(|a, b| (&a.path, &a.hgid).cmp(&(&b.path, &b.hgid)));
+}
+
+/// A request kind that can be built from the keys of `--format lines`.
+trait KeysRequest: Sized {
+    fn from_keys(keys: Vec<Key>) -> Result<Self>;
+}
+
+impl KeysRequest for DataRequest {
+    fn from_keys(keys: Vec<Key>) -> Result<Self> {
+        Ok(DataRequest { keys })
+    }
+}
+
+impl KeysRequest for HistoryRequest {
+    fn from_keys(keys: Vec<Key>) -> Result<Self> {
+        Ok(HistoryRequest { keys, depth: None })
+    }
+}
+
+impl KeysRequest for TreeRequest {
+    fn from_keys(_keys: Vec<Key>) -> Result<Self> {
+        Err(anyhow!("tree requests cannot be read from lines of keys"))
+    }
+}
+
+/// A request kind whose hashes can be checked by `--require-distinct-hashes`.
+trait RequestHashes {
+    /// Every hash in the request, along with a description of where it was
+    /// used for error messages.
+    fn hashes(&self) -> Vec<(String, HgId)>;
+}
+
+impl RequestHashes for DataRequest {
+    fn hashes(&self) -> Vec<(String, HgId)> {
+        key_hashes(&self.keys)
     }
 }
 
+impl RequestHashes for HistoryRequest {
+    fn hashes(&self) -> Vec<(String, HgId)> {
+        key_hashes(&self.keys)
+    }
+}
+
+impl RequestHashes for TreeRequest {
+    fn hashes(&self) -> Vec<(String, HgId)> {
+        let mfnodes = self.mfnodes.iter().map(|h| ("mfnodes".to_string(), *h));
+        let basemfnodes = self
+            .basemfnodes
+            .iter()
+            .map(|h| ("basemfnodes".to_string(), *h));
+        mfnodes.chain(basemfnodes).collect()
+    }
+}
+
+fn key_hashes(keys: &[Key]) -> Vec<(String, HgId)> {
+    keys.iter()
+        .map(|k| (format!("path {:?}", k.path.as_str()), k.hgid))
+        .collect()
+}
+
+/// Fail if any hash is used more than once, which usually means a hash was
+/// pasted into the wrong place when writing the input by hand.
+fn check_distinct_hashes<R: RequestHashes>(req: &R) -> Result<()> {
+    let mut seen: HashMap<HgId, String> = HashMap::new();
+    for (location, hash) in req.hashes() {
+        if let Some(first) = seen.get(&hash) {
+            return Err(anyhow!(
+                "hash {} is used more than once: for {} and for {}",
+                hash.to_hex(),
+                first,
+                location
+            ));
+        }
+        seen.insert(hash, location);
+    }
+    Ok(())
+}
+
+fn extend_dedup<T: Clone + Eq + Hash>(items: &mut Vec<T>, new: Vec<T>) {
+    let mut seen: HashSet<T> = items.iter().cloned().collect();
+    for item in new {
+        if seen.insert(item.clone()) {
+            items.push(item);
+        }
+    }
+}
+
+/// Decode the CBOR payload generated for `req`, and check it decodes to the
+/// same request.
+fn verify_round_trip<R: AppendRequest + PartialEq + Debug>(req: &R, bytes: &[u8]) -> Result<()> {
+    let decoded = R::from_cbor(bytes)?;
+    eprintln!("Decoded request: {:#?}", &decoded);
+    if &decoded != req {
+        return Err(anyhow!("{} request changed when decoded", R::KIND));
+    }
+    Ok(())
+}
+
+/// Decode an existing CBOR request payload, checking that it is of the
+/// same kind as `R`, and merge `new` into it.
+fn append_request<R: AppendRequest>(existing: &[u8], new: R) -> Result<R> {
+    let value: serde_cbor::Value = serde_cbor::from_slice(existing)?;
+    let fields = match value {
+        serde_cbor::Value::Map(map) => map
+            .into_keys()
+            .map(|k| match k {
+                serde_cbor::Value::Text(k) => Ok(k),
+                _ => Err(anyhow!("request field names must be strings")),
+            })
+            .collect::<Result<HashSet<_>>>()?,
+        _ => return Err(anyhow!("existing request must be a CBOR map")),
+    };
+    let expected = R::FIELDS.iter().map(|f| f.to_string()).collect();
+    if fields != expected {
+        return Err(anyhow!(
+            "existing file does not contain a {} request",
+            R::KIND
+        ));
+    }
+
+    let mut req = R::from_cbor(existing)?;
+    req.append(new);
+    Ok(req)
+}
+
+fn append_to_file<R: AppendRequest>(path: &Path, new: R) -> Result<R> {
+    eprintln!("Appending to existing file: {:?}", path);
+    let existing = fs::read(path)?;
+    append_request(&existing, new)
+}
+
 fn parse_data_req(json: &Value) -> Result<DataRequest> {
     let json = json
         .as_object()
@@ -79,6 +568,31 @@ fn parse_data_req(json: &Value) -> Result<DataRequest> {
     Ok(DataRequest { keys })
 }
 
+/// Parse lines of `<path> <hash>` into keys, in order. Unlike a JSON object,
+/// this can give several hashes for the same path. Blank lines are skipped.
+fn parse_key_lines(text: &str) -> Result<Vec<Key>> {
+    let mut keys = Vec::new();
+    for (i, line) in text.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() {
+            continue;
+        }
+        // Paths may contain spaces, but hashes can't.
+        let mut parts = line.rsplitn(2, ' ');
+        match (parts.next(), parts.next()) {
+            (Some(hash), Some(path)) => keys.push(make_key(path.trim_end(), hash)?),
+            _ => {
+                return Err(anyhow!(
+                    "line {}: expected \"<path> <hash>\", got {:?}",
+                    i + 1,
+                    line
+                ));
+            }
+        }
+    }
+    Ok(keys)
+}
+
 fn parse_history_req(json: &Value) -> Result<HistoryRequest> {
     let json = json
         .as_object()
@@ -123,12 +637,15 @@ fn parse_tree_req(json: &Value) -> Result<TreeRequest> {
     let mfnodes = obj
         .get("mfnodes")
         .ok_or_else(|| anyhow!("missing field: mfnodes"))?;
-    let mfnodes = parse_hashes(mfnodes)?;
+    let mfnodes = parse_hashes("mfnodes", mfnodes)?;
+    if mfnodes.is_empty() {
+        return Err(anyhow!("mfnodes must contain at least one hash"));
+    }
 
     let basemfnodes = obj
         .get("basemfnodes")
         .ok_or_else(|| anyhow!("missing field: basemfnodes"))?;
-    let basemfnodes = parse_hashes(basemfnodes)?;
+    let basemfnodes = parse_hashes("basemfnodes", basemfnodes)?;
 
     let depth = obj
         .get("depth")
@@ -143,45 +660,96 @@ fn parse_tree_req(json: &Value) -> Result<TreeRequest> {
     })
 }
 
-fn parse_hashes(json: &Value) -> Result<Vec<HgId>> {
+fn parse_hashes(field: &str, json: &Value) -> Result<Vec<HgId>> {
     let array = json
         .as_array()
         .ok_or_else(|| anyhow!("node hashes must be a passed as an array"))?;
     let mut hashes = Vec::new();
-    for hex in array {
+    for (i, hex) in array.iter().enumerate() {
         let hex = hex
             .as_str()
             .ok_or_else(|| anyhow!("node hashes must be strings"))?;
-        let hash = HgId::from_str(hex)?;
+        let hash = parse_hash(hex, || format!("{}[{}]", field, i))?;
         hashes.push(hash);
     }
     Ok(hashes)
 }
 
+/// Parse a hex hash. `location` describes where the hash was used, for the
+/// error message.
+fn parse_hash(hex: &str, location: impl Fn() -> String) -> Result<HgId> {
+    if hex.len() != HgId::hex_len() {
+        return Err(anyhow!(
+            "invalid hash for {}: expected {} hex chars, got {} ({:?})",
+            location(),
+            HgId::hex_len(),
+            hex.len(),
+            hex
+        ));
+    }
+    HgId::from_str(hex).map_err(|e| anyhow!("invalid hash for {}: {} ({:?})", location(), e, hex))
+}
+
 fn make_key(path: &str, hash: &str) -> Result<Key> {
     let path = if path.is_empty() {
         RepoPathBuf::new()
     } else {
         RepoPathBuf::from_string(path.to_string())?
     };
-    let hgid = HgId::from_str(hash)?;
+    let hgid = parse_hash(hash, || format!("path {:?}", path.as_str()))?;
     Ok(Key::new(path, hgid))
 }
 
 fn read_input(path: Option<PathBuf>) -> Result<Value> {
+    Ok(serde_json::from_str(&read_input_text(path)?)?)
+}
+
+fn read_input_text(path: Option<PathBuf>) -> Result<String> {
     Ok(match path {
-        Some(path) => {
-            eprintln!("Reading from file: {:?}", &path);
-            let file = File::open(&path)?;
-            serde_json::from_reader(file)?
-        }
+        Some(path) => match input_url(&path) {
+            Some(url) => fetch_url(url)?,
+            None => {
+                eprintln!("Reading from file: {:?}", &path);
+                fs::read_to_string(&path)?
+            }
+        },
         None => {
             eprintln!("Reading from stdin");
-            serde_json::from_reader(stdin())?
+            let mut text = String::new();
+            stdin().read_to_string(&mut text)?;
+            text
         }
     })
 }
 
+/// The URL named by an input path, if it has an `http://` or `https://` scheme.
+fn input_url(path: &Path) -> Option<&str> {
+    path.to_str()
+        .filter(|s| s.starts_with("http://") || s.starts_with("https://"))
+}
+
+fn fetch_url(url: &str) -> Result<String> {
+    eprintln!("Reading from URL: {}", url);
+    let fetch = || -> Result<Vec<u8>> {
+        let mut body = Vec::new();
+        let mut easy = Easy::new();
+        easy.url(url)?;
+        easy.follow_location(true)?;
+        easy.fail_on_error(true)?;
+        {
+            let mut transfer = easy.transfer();
+            transfer.write_function(|data| {
+                body.extend_from_slice(data);
+                Ok(data.len())
+            })?;
+            transfer.perform()?;
+        }
+        Ok(body)
+    };
+    let body = fetch().map_err(|e| anyhow!("failed to fetch {}: {}", url, e))?;
+    String::from_utf8(body).map_err(|e| anyhow!("response from {} is not UTF-8: {}", url, e))
+}
+
 fn write_output(path: Option<PathBuf>, content: &[u8]) -> Result<()> {
     match path {
         Some(path) => {
@@ -195,3 +763,302 @@ fn write_output(path: Option<PathBuf>, content: &[u8]) -> Result<()> {
     }
     Ok(())
 }
+
+/// Write each payload of a batch to its own file in `dir`, named after its
+/// position in the batch (`req-0.cbor`, `req-1.cbor`, ...).
+fn write_batch(dir: &Path, payloads: &[Vec<u8>]) -> Result<Vec<PathBuf>> {
+    let mut paths = Vec::new();
+    for (i, payload) in payloads.iter().enumerate() {
+        let path = dir.join(format!("req-{}.cbor", i));
+        write_output(Some(path.clone()), payload)?;
+        paths.push(path);
+    }
+    Ok(paths)
+}
+
+/// Build a curl command posting the payload in `output` (or stdin, if the
+/// payload is written to stdout) to `url`.
+fn curl_command(url: &str, output: Option<&Path>) -> String {
+    let data = match output {
+        Some(path) => format!("@{}", path.display()),
+        None => "@-".to_string(),
+    };
+    format!(
+        "curl -X POST -H {} --data-binary {} {}",
+        shell_quote("Content-Type: application/cbor"),
+        shell_quote(&data),
+        shell_quote(url)
+    )
+}
+
+fn shell_quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', "'\\''"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(path: &str, byte: u8) -> Key {
+        make_key(path, &HgId::from_byte_array([byte; 20]).to_hex()).unwrap()
+    }
+
+    #[test]
+    fn test_append_data_request() -> Result<()> {
+        let existing = serde_cbor::to_vec(&DataRequest {
+            keys: vec![key("a", 1)],
+        })?;
+        let new = DataRequest {
+            keys: vec![key("b", 2), key("c", 3)],
+        };
+
+        let req = append_request(&existing, new)?;
+        assert_eq!(req.keys, vec![key("a", 1), key("b", 2), key("c", 3)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_dedup() -> Result<()> {
+        let existing = serde_cbor::to_vec(&DataRequest {
+            keys: vec![key("a", 1)],
+        })?;
+        let new = DataRequest {
+            keys: vec![key("a", 1), key("b", 2)],
+        };
+
+        let req = append_request(&existing, new)?;
+        assert_eq!(req.keys, vec![key("a", 1), key("b", 2)]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_require_distinct_hashes() -> Result<()> {
+        let json = serde_json::json!({
+            "a": HgId::from_byte_array([1; 20]).to_hex(),
+            "b": HgId::from_byte_array([2; 20]).to_hex(),
+            "c": HgId::from_byte_array([1; 20]).to_hex(),
+        });
+        let req = parse_data_req(&json)?;
+        assert_eq!(req.keys.len(), 3);
+
+        let err = check_distinct_hashes(&req).unwrap_err().to_string();
+        assert!(err.contains(&HgId::from_byte_array([1; 20]).to_hex()));
+        assert!(err.contains("path \"a\"") && err.contains("path \"c\""));
+
+        let req = DataRequest {
+            keys: vec![key("a", 1), key("b", 2)],
+        };
+        check_distinct_hashes(&req)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_curl_command() {
+        let cmd = curl_command(
+            "https://localhost:8000/repo/data",
+            Some(Path::new("/tmp/req.cbor")),
+        );
+        assert_eq!(
+            cmd,
+            "curl -X POST -H 'Content-Type: application/cbor' \
+             --data-binary '@/tmp/req.cbor' 'https://localhost:8000/repo/data'"
+        );
+
+        let cmd = curl_command("http://host/it's", None);
+        assert!(cmd.contains("--data-binary '@-'"));
+        assert!(cmd.ends_with("'http://host/it'\\''s'"));
+    }
+
+    #[test]
+    fn test_diff_data_request() -> Result<()> {
+        let hex = |byte: u8| HgId::from_byte_array([byte; 20]).to_hex();
+        let left = parse_data_req(&serde_json::json!({
+            "a": hex(1),
+            "b": hex(2),
+            "c": hex(3),
+        }))?;
+        let right = parse_data_req(&serde_json::json!({
+            "c": hex(3),
+            "b": hex(4),
+            "a": hex(1),
+        }))?;
+
+        assert!(left.diff(&left).is_empty());
+        assert_eq!(
+            left.diff(&right),
+            vec![format!("~ \"b\": {} -> {}", hex(2), hex(4))]
+        );
+
+        let right = DataRequest {
+            keys: vec![key("a", 1), key("d", 5)],
+        };
+        assert_eq!(
+            left.diff(&right),
+            vec![
+                format!("- \"b\": {}", hex(2)),
+                format!("- \"c\": {}", hex(3)),
+                format!("+ \"d\": {}", hex(5)),
+            ]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_canonical_data_request() -> Result<()> {
+        let mut first = DataRequest {
+            keys: vec![key("b", 2), key("a", 3), key("a", 1)],
+        };
+        let mut second = DataRequest {
+            keys: vec![key("a", 1), key("b", 2), key("a", 3)],
+        };
+        assert_ne!(serde_cbor::to_vec(&first)?, serde_cbor::to_vec(&second)?);
+
+        first.canonicalize();
+        second.canonicalize();
+        assert_eq!(first.keys, vec![key("a", 1), key("a", 3), key("b", 2)]);
+        assert_eq!(serde_cbor::to_vec(&first)?, serde_cbor::to_vec(&second)?);
+        Ok(())
+    }
+
+    #[test]
+    fn test_tree_request_requires_mfnodes() -> Result<()> {
+        let hex = HgId::from_byte_array([1; 20]).to_hex();
+        let req = parse_tree_req(&serde_json::json!({
+            "rootdir": "",
+            "mfnodes": [hex],
+            "basemfnodes": [],
+            "depth": 1,
+        }))?;
+        assert_eq!(req.mfnodes, vec![HgId::from_byte_array([1; 20])]);
+        assert_eq!(req.depth, Some(1));
+
+        let err = parse_tree_req(&serde_json::json!({
+            "rootdir": "",
+            "mfnodes": [],
+            "basemfnodes": [hex],
+        }))
+        .unwrap_err();
+        assert!(err
+            .to_string()
+            .contains("mfnodes must contain at least one hash"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_invalid_hash_error() {
+        let err = make_key("dir/a", "abc").unwrap_err().to_string();
+        assert_eq!(
+            err,
+            "invalid hash for path \"dir/a\": expected 40 hex chars, got 3 (\"abc\")"
+        );
+        let err = make_key("dir/a", &"x".repeat(40)).unwrap_err().to_string();
+        assert!(err.starts_with("invalid hash for path \"dir/a\": "));
+
+        let hex = HgId::from_byte_array([1; 20]).to_hex();
+        let err = parse_hashes("mfnodes", &serde_json::json!([hex, "abc"]))
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("invalid hash for mfnodes[1]: expected 40 hex chars"));
+    }
+
+    #[test]
+    fn test_verify_round_trip() -> Result<()> {
+        let req = HistoryRequest {
+            keys: vec![key("a", 1), key("b", 2)],
+            depth: Some(3),
+        };
+        verify_round_trip(&req, &serde_cbor::to_vec(&req)?)?;
+
+        let req = TreeRequest {
+            rootdir: RepoPathBuf::new(),
+            mfnodes: vec![HgId::from_byte_array([1; 20])],
+            basemfnodes: vec![],
+            depth: None,
+        };
+        verify_round_trip(&req, &serde_cbor::to_vec(&req)?)?;
+
+        let other = TreeRequest {
+            rootdir: RepoPathBuf::new(),
+            mfnodes: vec![HgId::from_byte_array([1; 20])],
+            basemfnodes: vec![],
+            depth: Some(1),
+        };
+        assert!(verify_round_trip(&req, &serde_cbor::to_vec(&other)?).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_key_lines() -> Result<()> {
+        let hex = |byte: u8| HgId::from_byte_array([byte; 20]).to_hex();
+        let text = format!(
+            "b {}\n\na {}\nwith space {}\nb {}\n",
+            hex(1),
+            hex(2),
+            hex(3),
+            hex(4)
+        );
+        let keys = parse_key_lines(&text)?;
+        // Order and repeated paths are kept.
+        assert_eq!(
+            keys,
+            vec![key("b", 1), key("a", 2), key("with space", 3), key("b", 4)]
+        );
+
+        let err = parse_key_lines(&format!("a {}\nmissing-hash\n", hex(1)))
+            .unwrap_err()
+            .to_string();
+        assert!(err.starts_with("line 2: "));
+        assert!(TreeRequest::from_keys(keys).is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_url() {
+        assert_eq!(
+            input_url(Path::new("https://example.com/req.json")),
+            Some("https://example.com/req.json")
+        );
+        assert_eq!(
+            input_url(Path::new("http://localhost:8000/req.json")),
+            Some("http://localhost:8000/req.json")
+        );
+        assert_eq!(input_url(Path::new("fixtures/req.json")), None);
+        assert_eq!(input_url(Path::new("http.json")), None);
+    }
+
+    #[test]
+    fn test_write_batch() -> Result<()> {
+        let dir = std::env::temp_dir().join(format!("make_req-test-{}", std::process::id()));
+        fs::create_dir_all(&dir)?;
+        let payloads = vec![
+            serde_cbor::to_vec(&DataRequest {
+                keys: vec![key("a", 1)],
+            })?,
+            serde_cbor::to_vec(&DataRequest {
+                keys: vec![key("b", 2)],
+            })?,
+        ];
+
+        let paths = write_batch(&dir, &payloads)?;
+        assert_eq!(paths, vec![dir.join("req-0.cbor"), dir.join("req-1.cbor")]);
+        for (path, payload) in paths.iter().zip(payloads.iter()) {
+            assert_eq!(&fs::read(path)?, payload);
+        }
+        fs::remove_dir_all(&dir)?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_append_kind_mismatch() -> Result<()> {
+        let existing = serde_cbor::to_vec(&HistoryRequest {
+            keys: vec![key("a", 1)],
+            depth: None,
+        })?;
+        let new = DataRequest {
+            keys: vec![key("b", 2)],
+        };
+
+        assert!(append_request(&existing, new).is_err());
+        Ok(())
+    }
+}
@@ -7,16 +7,43 @@
 
 #![deny(warnings)]
 
-use std::{fmt, io, path::Path};
+use std::{
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
 
 use anyhow::{Context, Result};
 
 #[cfg(target_os = "linux")]
-use self::linux::fstype as fstype_imp;
+use self::linux::mount_source as mount_source_imp;
+#[cfg(target_os = "linux")]
+use self::linux::{
+    atime_behavior as atime_behavior_imp, bind_mount_source as bind_mount_source_imp,
+    fs_uuid as fs_uuid_imp, fstype as fstype_imp, fstype_from_fd as fstype_from_fd_imp,
+    is_encrypted as is_encrypted_imp, list_filesystems as list_filesystems_imp,
+    register_fs_magic as register_fs_magic_imp,
+};
+#[cfg(target_os = "macos")]
+use self::macos::mount_source as mount_source_imp;
 #[cfg(target_os = "macos")]
-use self::macos::fstype as fstype_imp;
+use self::macos::{
+    fs_uuid as fs_uuid_imp, fstype as fstype_imp, fstype_from_fd as fstype_from_fd_imp,
+    is_encrypted as is_encrypted_imp, list_filesystems as list_filesystems_imp,
+};
+#[cfg(unix)]
+use self::unix::fs_stats as fs_stats_imp;
+#[cfg(windows)]
+use self::windows::{
+    fs_stats as fs_stats_imp, fs_uuid as fs_uuid_imp, fstype as fstype_imp,
+    fstype_from_handle as fstype_from_handle_imp,
+};
+#[cfg(unix)]
+use std::os::unix::io::RawFd;
 #[cfg(windows)]
-use self::windows::fstype as fstype_imp;
+use std::os::windows::io::RawHandle;
 
 #[derive(Clone, Eq, PartialEq, Ord, PartialOrd, Debug)]
 pub enum FsType {
@@ -28,6 +55,7 @@ pub enum FsType {
     BTRFS,
     XFS,
     NFS,
+    CIFS,
     FUSE,
     TMPFS,
     /// The catch-all type for the unknown filesystems. The content of the string is as returned
@@ -46,6 +74,7 @@ impl fmt::Display for FsType {
             FsType::BTRFS => write!(f, "Btrfs"),
             FsType::XFS => write!(f, "XFS"),
             FsType::NFS => write!(f, "NFS"),
+            FsType::CIFS => write!(f, "CIFS"),
             FsType::FUSE => write!(f, "FUSE"),
             FsType::TMPFS => write!(f, "tmpfs"),
             FsType::Unknown(fstype) => write!(f, "Unknown({})", fstype),
@@ -53,6 +82,68 @@ impl fmt::Display for FsType {
     }
 }
 
+impl FsType {
+    /// Whether memory-mapped I/O can be relied upon on this filesystem.
+    ///
+    /// EdenFS, network filesystems and FUSE filesystems are known to not
+    /// support mmap reliably. Everything else is assumed to be local.
+    pub fn supports_mmap(&self) -> bool {
+        match self {
+            FsType::EDENFS | FsType::NFS | FsType::CIFS | FsType::FUSE => false,
+            FsType::Unknown(fstype) => {
+                let fstype = fstype.to_lowercase();
+                !(fstype == "cifs"
+                    || fstype == "smb"
+                    || fstype == "smbfs"
+                    || fstype == "nfs"
+                    || fstype == "fuse"
+                    || fstype.starts_with("fuse.")
+                    || fstype.starts_with("osxfuse"))
+            }
+            _ => true,
+        }
+    }
+}
+
+/// How a filesystem updates file access times.
+#[derive(Clone, Copy, Eq, PartialEq, Debug)]
+pub enum AtimeMode {
+    /// Access times are updated on every access.
+    Strict,
+    /// Access times are only updated if they are older than the modification
+    /// time, or more than a day old.
+    Relatime,
+    /// Access times are never updated.
+    Noatime,
+    /// The behavior could not be determined.
+    Unknown,
+}
+
+/// A mounted filesystem.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct MountInfo {
+    /// Where the filesystem is mounted.
+    pub mount_point: PathBuf,
+    /// The filesystem type, as reported in the mount table.
+    pub fstype: FsType,
+}
+
+/// Space and inode usage of a filesystem.
+#[derive(Clone, Eq, PartialEq, Debug)]
+pub struct FsStats {
+    pub fs_type: FsType,
+    pub total_bytes: u64,
+    pub free_bytes: u64,
+    /// Free space available to unprivileged users.
+    pub available_bytes: u64,
+    /// None if the filesystem does not report inode counts.
+    pub total_inodes: Option<u64>,
+    pub free_inodes: Option<u64>,
+    /// Where a network filesystem is mounted from, as reported by the mount
+    /// table (ex. "server:/export" for NFS). None for other filesystems.
+    pub remote: Option<String>,
+}
+
 #[cfg(windows)]
 mod windows {
     use super::*;
@@ -60,10 +151,13 @@ mod windows {
     use std::{os::windows::ffi::OsStrExt, ptr::null_mut};
 
     use winapi::shared::minwindef::{DWORD, MAX_PATH};
-    use winapi::um::fileapi::{CreateFileW, GetVolumeInformationByHandleW, OPEN_EXISTING};
+    use winapi::um::fileapi::{
+        CreateFileW, GetDiskFreeSpaceExW, GetVolumeInformationByHandleW, OPEN_EXISTING,
+    };
     use winapi::um::handleapi::{CloseHandle, INVALID_HANDLE_VALUE};
     use winapi::um::winnt::{
         FILE_GENERIC_READ, FILE_SHARE_DELETE, FILE_SHARE_READ, FILE_SHARE_WRITE, HANDLE,
+        ULARGE_INTEGER,
     };
 
     const FILE_ATTRIBUTE_NORMAL: u32 = 0x02000000;
@@ -115,11 +209,14 @@ mod windows {
 
     pub fn fstype(path: &Path) -> Result<FsType> {
         let win_handle = open_share(path)?;
+        fstype_from_handle(win_handle.handle)
+    }
 
+    pub fn fstype_from_handle(handle: HANDLE) -> Result<FsType> {
         let mut fstype = [0u16; MAX_PATH];
         let exit_sts = unsafe {
             GetVolumeInformationByHandleW(
-                win_handle.handle,
+                handle,
                 null_mut(),
                 0,
                 null_mut(),
@@ -139,6 +236,59 @@ mod windows {
 
         Ok(fstype.into())
     }
+
+    /// Windows has no filesystem UUID, the volume serial number is used instead.
+    pub fn fs_uuid(path: &Path) -> Result<Option<String>> {
+        let win_handle = open_share(path)?;
+
+        let mut serial: DWORD = 0;
+        let exit_sts = unsafe {
+            GetVolumeInformationByHandleW(
+                win_handle.handle,
+                null_mut(),
+                0,
+                &mut serial,
+                null_mut(),
+                null_mut(),
+                null_mut(),
+                0,
+            )
+        };
+
+        if exit_sts == 0 {
+            return Ok(None);
+        }
+        Ok(Some(format!(
+            "{:04X}-{:04X}",
+            serial >> 16,
+            serial & 0xffff
+        )))
+    }
+
+    /// NTFS has no fixed inode table, so inode counts are not reported.
+    pub fn fs_stats(path: &Path) -> Result<FsStats> {
+        let mut root: Vec<u16> = path.as_os_str().encode_wide().collect();
+        root.push(0);
+
+        let mut available: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+        let mut total: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+        let mut free: ULARGE_INTEGER = unsafe { std::mem::zeroed() };
+        let exit_sts =
+            unsafe { GetDiskFreeSpaceExW(root.as_ptr(), &mut available, &mut total, &mut free) };
+        if exit_sts == 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        Ok(FsStats {
+            fs_type: fstype(path)?,
+            total_bytes: unsafe { *total.QuadPart() },
+            free_bytes: unsafe { *free.QuadPart() },
+            available_bytes: unsafe { *available.QuadPart() },
+            total_inodes: None,
+            free_inodes: None,
+            remote: None,
+        })
+    }
 }
 
 #[cfg(unix)]
@@ -158,6 +308,54 @@ mod unix {
             Err(io::Error::last_os_error().into())
         }
     }
+
+    pub fn get_fstatfs(fd: RawFd) -> Result<libc::statfs> {
+        let mut fs_stat: libc::statfs = unsafe { zeroed() };
+        if unsafe { libc::fstatfs(fd, &mut fs_stat) } == 0 {
+            Ok(fs_stat)
+        } else {
+            Err(io::Error::last_os_error().into())
+        }
+    }
+
+    /// Check whether the directory open as `fd` has a `.eden` entry.
+    pub fn has_dot_eden(fd: RawFd) -> bool {
+        let name = CString::new(".eden").unwrap();
+        unsafe { libc::faccessat(fd, name.as_ptr(), libc::F_OK, 0) == 0 }
+    }
+
+    pub fn fs_stats(path: &Path) -> Result<FsStats> {
+        let fs_stat = get_statfs(path)?;
+        // Block counts are in units of the fundamental block size, which
+        // Linux reports separately from the preferred I/O size.
+        #[cfg(target_os = "linux")]
+        let block_size = fs_stat.f_frsize as u64;
+        #[cfg(not(target_os = "linux"))]
+        let block_size = fs_stat.f_bsize as u64;
+        // Filesystems without an inode table (ex. btrfs, some network
+        // filesystems) report zero inodes.
+        let (total_inodes, free_inodes) = if fs_stat.f_files == 0 {
+            (None, None)
+        } else {
+            (Some(fs_stat.f_files as u64), Some(fs_stat.f_ffree as u64))
+        };
+
+        let fs_type = fstype_imp(path)?;
+        let remote = match fs_type {
+            FsType::NFS | FsType::CIFS => mount_source_imp(path, &fs_stat),
+            _ => None,
+        };
+
+        Ok(FsStats {
+            fs_type,
+            total_bytes: fs_stat.f_blocks as u64 * block_size,
+            free_bytes: fs_stat.f_bfree as u64 * block_size,
+            available_bytes: fs_stat.f_bavail as u64 * block_size,
+            total_inodes,
+            free_inodes,
+            remote,
+        })
+    }
 }
 
 #[cfg(target_os = "linux")]
@@ -165,19 +363,38 @@ mod linux {
     use super::*;
 
     use std::collections::HashMap;
+    use std::ffi::OsString;
     use std::os::linux::fs::MetadataExt;
+    use std::os::unix::ffi::OsStringExt;
+    use std::path::PathBuf;
+    use std::sync::RwLock;
+
+    use once_cell::sync::Lazy;
+
+    /// Filesystem types registered at runtime, for magic numbers not known
+    /// to this crate.
+    static REGISTERED_MAGICS: Lazy<RwLock<HashMap<i64, &'static str>>> =
+        Lazy::new(|| RwLock::new(HashMap::new()));
 
     /// These filesystem types are not in libc yet
     const BTRFS_SUPER_MAGIC: i64 = 0x9123683e;
+    const CIFS_MAGIC_NUMBER: i64 = 0xff534d42;
     const FUSE_SUPER_MAGIC: i64 = 0x65735546;
+    const OVERLAYFS_SUPER_MAGIC: i64 = 0x794c7630;
+    const SQUASHFS_MAGIC: i64 = 0x73717368;
     const XFS_SUPER_MAGIC: i64 = 0x58465342;
+    const ZFS_SUPER_MAGIC: i64 = 0x2fc12fc1;
 
     impl From<i64> for FsType {
         fn from(f_type: i64) -> Self {
             match f_type {
                 BTRFS_SUPER_MAGIC => FsType::BTRFS,
+                CIFS_MAGIC_NUMBER => FsType::CIFS,
                 FUSE_SUPER_MAGIC => FsType::FUSE,
                 XFS_SUPER_MAGIC => FsType::XFS,
+                OVERLAYFS_SUPER_MAGIC => FsType::Unknown("overlay".to_string()),
+                SQUASHFS_MAGIC => FsType::Unknown("squashfs".to_string()),
+                ZFS_SUPER_MAGIC => FsType::Unknown("zfs".to_string()),
                 libc::EXT4_SUPER_MAGIC => FsType::EXT4,
                 libc::NFS_SUPER_MAGIC => FsType::NFS,
                 libc::TMPFS_MAGIC => FsType::TMPFS,
@@ -201,21 +418,34 @@ mod linux {
                 libc::REISERFS_SUPER_MAGIC => FsType::Unknown("reiserfs".to_string()),
                 libc::SMB_SUPER_MAGIC => FsType::Unknown("smb".to_string()),
                 libc::USBDEVICE_SUPER_MAGIC => FsType::Unknown("usbdevice".to_string()),
-                _ => FsType::Unknown(format!("{:#X}", f_type)),
+                _ => match REGISTERED_MAGICS.read().unwrap().get(&f_type) {
+                    Some(name) => FsType::Unknown(name.to_string()),
+                    None => FsType::Unknown(format!("{:#X}", f_type)),
+                },
             }
         }
     }
 
-    fn get_type(f_type: i64, path: &Path) -> Result<FsType> {
+    pub fn register_fs_magic(magic: i64, name: &'static str) {
+        REGISTERED_MAGICS.write().unwrap().insert(magic, name);
+    }
+
+    /// Refine FUSE filesystems using `has_dot_eden` and the major:minor
+    /// device number from `dev_major_minor`, which are only called for them.
+    fn get_type(
+        f_type: i64,
+        has_dot_eden: impl FnOnce() -> bool,
+        dev_major_minor: impl FnOnce() -> Option<String>,
+    ) -> Result<FsType> {
         let result = FsType::from(f_type);
         if result == FsType::FUSE {
             // .eden is present in all directories in an EdenFS mount.
-            if path.join(".eden").exists() {
+            if has_dot_eden() {
                 return Ok(FsType::EDENFS);
             } else {
                 // Take some efforts to find out the actual filesystem.
                 // This works for Linux block devices.
-                if let Some(major_minor) = get_dev_major_minor(path) {
+                if let Some(major_minor) = dev_major_minor() {
                     let props = find_udev_properties(&major_minor);
                     if let Some(name) = props.get("E:ID_FS_TYPE") {
                         if name == "ntfs" {
@@ -247,16 +477,291 @@ mod linux {
 
     /// Get the "st_dev". Return the major:minor form (ex. "8:1").
     fn get_dev_major_minor(path: &Path) -> Option<String> {
-        path.symlink_metadata().ok().map(|m| {
-            let st_dev = m.st_dev();
-            let (major, minor) = unsafe { (libc::major(st_dev), libc::minor(st_dev)) };
-            format!("{}:{}", major, minor)
-        })
+        path.symlink_metadata()
+            .ok()
+            .map(|m| format_major_minor(m.st_dev()))
+    }
+
+    fn format_major_minor(st_dev: libc::dev_t) -> String {
+        // Safe functions in newer libc releases, unsafe in older ones
+        #[allow(unused_unsafe)]
+        let (major, minor) = unsafe { (libc::major(st_dev), libc::minor(st_dev)) };
+        format!("{}:{}", major, minor)
     }
 
     pub fn fstype(path: &Path) -> Result<FsType> {
         let fs_stat = super::unix::get_statfs(path)?;
-        get_type(fs_stat.f_type, path)
+        get_type(
+            fs_stat.f_type,
+            || path.join(".eden").exists(),
+            || get_dev_major_minor(path),
+        )
+    }
+
+    pub fn fstype_from_fd(fd: RawFd) -> Result<FsType> {
+        let fs_stat = super::unix::get_fstatfs(fd)?;
+        get_type(
+            fs_stat.f_type,
+            || super::unix::has_dot_eden(fd),
+            || {
+                let mut stat: libc::stat = unsafe { std::mem::zeroed() };
+                if unsafe { libc::fstat(fd, &mut stat) } != 0 {
+                    return None;
+                }
+                Some(format_major_minor(stat.st_dev))
+            },
+        )
+    }
+
+    pub fn fs_uuid(path: &Path) -> Result<Option<String>> {
+        let st_dev = path.symlink_metadata()?.st_dev();
+
+        // udev knows the UUID of block devices it has probed.
+        if let Some(major_minor) = get_dev_major_minor(path) {
+            let props = find_udev_properties(&major_minor);
+            if let Some(uuid) = props.get("E:ID_FS_UUID") {
+                return Ok(Some(uuid.clone()));
+            }
+        }
+
+        // Otherwise look for the device in /dev/disk/by-uuid.
+        let entries = match std::fs::read_dir("/dev/disk/by-uuid") {
+            Ok(entries) => entries,
+            Err(_) => return Ok(None),
+        };
+        for entry in entries {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(_) => continue,
+            };
+            match std::fs::metadata(entry.path()) {
+                Ok(m) if m.st_rdev() == st_dev => {
+                    return Ok(entry.file_name().to_str().map(|s| s.to_string()));
+                }
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    pub fn is_encrypted(path: &Path) -> io::Result<Option<bool>> {
+        path.symlink_metadata()?;
+        Ok(get_dev_major_minor(path)
+            .and_then(|major_minor| is_dm_crypt(&Path::new("/sys/dev/block").join(major_minor))))
+    }
+
+    /// Check whether the block device at `dev_dir` (its directory in sysfs)
+    /// is a dm-crypt mapping, or is stacked on top of one (ex. LVM on LUKS).
+    /// Return None if the device is not known to sysfs, which is the case for
+    /// filesystems not backed by a block device.
+    pub fn is_dm_crypt(dev_dir: &Path) -> Option<bool> {
+        // Guard against unexpected loops in the device stack.
+        const MAX_DEPTH: usize = 8;
+
+        fn walk(dev_dir: &Path, depth: usize) -> bool {
+            if let Ok(uuid) = std::fs::read_to_string(dev_dir.join("dm/uuid")) {
+                if uuid.starts_with("CRYPT-") {
+                    return true;
+                }
+            }
+            if depth >= MAX_DEPTH {
+                return false;
+            }
+            match std::fs::read_dir(dev_dir.join("slaves")) {
+                Ok(entries) => entries
+                    .filter_map(|entry| entry.ok())
+                    .any(|entry| walk(&entry.path(), depth + 1)),
+                Err(_) => false,
+            }
+        }
+
+        if dev_dir.is_dir() {
+            Some(walk(dev_dir, 0))
+        } else {
+            None
+        }
+    }
+
+    pub fn atime_behavior(path: &Path) -> io::Result<AtimeMode> {
+        let path = path.canonicalize()?;
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+        Ok(atime_mode_from_mountinfo(&mountinfo, &path))
+    }
+
+    /// Find the mount containing `path` in `mountinfo` (in the format of
+    /// `/proc/self/mountinfo`). Return its mount point and fields.
+    fn find_mount<'a>(mountinfo: &'a str, path: &Path) -> Option<(PathBuf, Vec<&'a str>)> {
+        let mut found: Option<(PathBuf, Vec<&str>)> = None;
+        for line in mountinfo.lines() {
+            let fields: Vec<_> = line.split(' ').collect();
+            if fields.len() < 6 {
+                continue;
+            }
+            let mount_point = unescape_mount_point(fields[4]);
+            if !path.starts_with(&mount_point) {
+                continue;
+            }
+            // The deepest mount point wins. For the same mount point, later
+            // entries are mounted on top of earlier ones.
+            let deeper = match &found {
+                Some((found_point, _)) => {
+                    mount_point.components().count() >= found_point.components().count()
+                }
+                None => true,
+            };
+            if deeper {
+                found = Some((mount_point, fields));
+            }
+        }
+        found
+    }
+
+    /// Find the mount containing `path` in `mountinfo` and read its atime
+    /// mode from the per-mount options.
+    pub fn atime_mode_from_mountinfo(mountinfo: &str, path: &Path) -> AtimeMode {
+        match find_mount(mountinfo, path) {
+            Some((_, fields)) => {
+                let options: Vec<_> = fields[5].split(',').collect();
+                if options.contains(&"noatime") {
+                    AtimeMode::Noatime
+                } else if options.contains(&"relatime") {
+                    AtimeMode::Relatime
+                } else {
+                    AtimeMode::Strict
+                }
+            }
+            None => AtimeMode::Unknown,
+        }
+    }
+
+    pub fn list_filesystems(root: &Path) -> io::Result<Vec<MountInfo>> {
+        let root = root.canonicalize()?;
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+        Ok(filesystems_from_mountinfo(&mountinfo, &root))
+    }
+
+    /// List the mounts in `mountinfo` whose mount point is `root` or below
+    /// it, in mount order. Of mounts stacked on the same mount point, only
+    /// the visible (last) one is returned.
+    pub fn filesystems_from_mountinfo(mountinfo: &str, root: &Path) -> Vec<MountInfo> {
+        let mut mounts: Vec<MountInfo> = Vec::new();
+        for line in mountinfo.lines() {
+            let fields: Vec<_> = line.split(' ').collect();
+            if fields.len() < 6 {
+                continue;
+            }
+            let mount_point = unescape_mount_point(fields[4]);
+            if !mount_point.starts_with(root) {
+                continue;
+            }
+            // The optional fields are terminated by a single "-", followed by
+            // the filesystem type.
+            let fstype = match fields[6..]
+                .iter()
+                .position(|&field| field == "-")
+                .and_then(|i| fields.get(6 + i + 1))
+            {
+                Some(fstype) => fstype_from_name(fstype),
+                None => continue,
+            };
+            mounts.retain(|mount| mount.mount_point != mount_point);
+            mounts.push(MountInfo {
+                mount_point,
+                fstype,
+            });
+        }
+        mounts
+    }
+
+    /// Map a filesystem type name from the mount table to a `FsType`.
+    fn fstype_from_name(name: &str) -> FsType {
+        match name {
+            "ext4" => FsType::EXT4,
+            "btrfs" => FsType::BTRFS,
+            "xfs" => FsType::XFS,
+            "nfs" | "nfs4" => FsType::NFS,
+            "cifs" | "smb3" => FsType::CIFS,
+            "tmpfs" => FsType::TMPFS,
+            "fuse" => FsType::FUSE,
+            "fuse.edenfs" => FsType::EDENFS,
+            _ => FsType::Unknown(name.to_string()),
+        }
+    }
+
+    pub fn mount_source(path: &Path, _fs_stat: &libc::statfs) -> Option<String> {
+        let path = path.canonicalize().ok()?;
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo").ok()?;
+        mount_source_from_mountinfo(&mountinfo, &path)
+    }
+
+    /// Find the mount containing `path` in `mountinfo` and return its mount
+    /// source, which follows the filesystem type.
+    pub fn mount_source_from_mountinfo(mountinfo: &str, path: &Path) -> Option<String> {
+        let (_, fields) = find_mount(mountinfo, path)?;
+        let separator = 6 + fields[6..].iter().position(|&field| field == "-")?;
+        let source = fields.get(separator + 2)?;
+        Some(unescape_mount_point(source).to_string_lossy().into_owned())
+    }
+
+    pub fn bind_mount_source(path: &Path) -> io::Result<Option<PathBuf>> {
+        let path = path.canonicalize()?;
+        let mountinfo = std::fs::read_to_string("/proc/self/mountinfo")?;
+        Ok(bind_mount_source_from_mountinfo(&mountinfo, &path))
+    }
+
+    /// Find the mount containing `path` in `mountinfo` and, if it is a bind
+    /// mount, return the directory it exposes.
+    ///
+    /// Bind mounts are mounts whose root is not the root of the filesystem.
+    /// The root is relative to the filesystem, so it is joined to a mount
+    /// point where the whole filesystem is visible if there is one.
+    pub fn bind_mount_source_from_mountinfo(mountinfo: &str, path: &Path) -> Option<PathBuf> {
+        let (mount_point, fields) = find_mount(mountinfo, path)?;
+        let root = unescape_mount_point(fields[3]);
+        if root == Path::new("/") {
+            return None;
+        }
+        let device = fields[2];
+        let fs_mount_point = mountinfo.lines().find_map(|line| {
+            let fields: Vec<_> = line.split(' ').collect();
+            if fields.len() >= 6 && fields[2] == device && fields[3] == "/" {
+                Some(unescape_mount_point(fields[4]))
+            } else {
+                None
+            }
+        });
+        match fs_mount_point {
+            Some(fs_mount_point) if fs_mount_point != mount_point => {
+                Some(fs_mount_point.join(root.strip_prefix("/").unwrap_or(&root)))
+            }
+            _ => Some(root),
+        }
+    }
+
+    /// Mount points in mountinfo have spaces, tabs, newlines and backslashes
+    /// escaped as octal (ex. "\040" for a space).
+    fn unescape_mount_point(escaped: &str) -> PathBuf {
+        let bytes = escaped.as_bytes();
+        let mut result = Vec::with_capacity(bytes.len());
+        let mut i = 0;
+        while i < bytes.len() {
+            let octal = bytes.get(i + 1..i + 4).and_then(|digits| {
+                std::str::from_utf8(digits)
+                    .ok()
+                    .and_then(|digits| u8::from_str_radix(digits, 8).ok())
+            });
+            match octal {
+                Some(byte) if bytes[i] == b'\\' => {
+                    result.push(byte);
+                    i += 4;
+                }
+                _ => {
+                    result.push(bytes[i]);
+                    i += 1;
+                }
+            }
+        }
+        PathBuf::from(OsString::from_vec(result))
     }
 }
 
@@ -264,13 +769,24 @@ mod linux {
 mod macos {
     use super::*;
 
-    use std::ffi::CStr;
+    use std::ffi::{CStr, CString};
+    use std::mem::{size_of, zeroed};
+    use std::os::unix::ffi::OsStrExt;
+
+    /// Buffer layout returned by getattrlist for ATTR_VOL_UUID.
+    #[repr(C, packed(4))]
+    struct VolUuidAttr {
+        length: u32,
+        uuid: [u8; 16],
+    }
 
     impl<'a> From<&'a str> for FsType {
         fn from(value: &'a str) -> Self {
             match value {
                 "apfs" => FsType::APFS,
                 "hfs" => FsType::HFS,
+                "nfs" => FsType::NFS,
+                "smbfs" => FsType::CIFS,
                 "osxfuse_eden" => FsType::EDENFS,
                 _ => FsType::Unknown(value.to_string()),
             }
@@ -279,9 +795,123 @@ mod macos {
 
     pub fn fstype(path: &Path) -> Result<FsType> {
         let fs_stat = super::unix::get_statfs(path)?;
+        get_type(&fs_stat, || path.join(".eden").exists())
+    }
+
+    pub fn fstype_from_fd(fd: RawFd) -> Result<FsType> {
+        let fs_stat = super::unix::get_fstatfs(fd)?;
+        get_type(&fs_stat, || super::unix::has_dot_eden(fd))
+    }
+
+    fn get_type(fs_stat: &libc::statfs, has_dot_eden: impl FnOnce() -> bool) -> Result<FsType> {
         let fs = unsafe { CStr::from_ptr(fs_stat.f_fstypename.as_ptr()) };
+        let result = FsType::from(fs.to_str()?);
+        // EdenFS can be mounted over NFS. .eden is present in all
+        // directories in an EdenFS mount.
+        if result == FsType::NFS && has_dot_eden() {
+            return Ok(FsType::EDENFS);
+        }
+        Ok(result)
+    }
+
+    pub fn mount_source(_path: &Path, fs_stat: &libc::statfs) -> Option<String> {
+        let source = unsafe { CStr::from_ptr(fs_stat.f_mntfromname.as_ptr()) };
+        Some(source.to_string_lossy().into_owned())
+    }
+
+    pub fn fs_uuid(path: &Path) -> Result<Option<String>> {
+        let cstr = CString::new(path.as_os_str().as_bytes())?;
+        let mut attrs: libc::attrlist = unsafe { zeroed() };
+        attrs.bitmapcount = libc::ATTR_BIT_MAP_COUNT;
+        attrs.volattr = libc::ATTR_VOL_INFO | libc::ATTR_VOL_UUID;
+        let mut buf: VolUuidAttr = unsafe { zeroed() };
+        let exit_sts = unsafe {
+            libc::getattrlist(
+                cstr.as_ptr(),
+                &mut attrs as *mut libc::attrlist as *mut libc::c_void,
+                &mut buf as *mut VolUuidAttr as *mut libc::c_void,
+                size_of::<VolUuidAttr>(),
+                0,
+            )
+        };
+        if exit_sts != 0 {
+            return Err(io::Error::last_os_error().into());
+        }
+
+        // Some filesystems (ex. network ones) do not have an UUID.
+        let uuid = buf.uuid;
+        if uuid.iter().all(|&b| b == 0) {
+            return Ok(None);
+        }
+        let hex: String = uuid.iter().map(|b| format!("{:02X}", b)).collect();
+        Ok(Some(format!(
+            "{}-{}-{}-{}-{}",
+            &hex[0..8],
+            &hex[8..12],
+            &hex[12..16],
+            &hex[16..20],
+            &hex[20..32]
+        )))
+    }
+
+    pub fn list_filesystems(root: &Path) -> io::Result<Vec<MountInfo>> {
+        let root = root.canonicalize()?;
+        let mut mounts: *mut libc::statfs = std::ptr::null_mut();
+        let count = unsafe { libc::getmntinfo(&mut mounts, libc::MNT_NOWAIT) };
+        if count <= 0 {
+            return Err(io::Error::last_os_error());
+        }
+        // The buffer is owned by libc and reused by later calls.
+        let mounts = unsafe { std::slice::from_raw_parts(mounts, count as usize) };
+        let mut result = Vec::new();
+        for mount in mounts {
+            let mount_point = unsafe { CStr::from_ptr(mount.f_mntonname.as_ptr()) };
+            let mount_point = PathBuf::from(std::ffi::OsStr::from_bytes(mount_point.to_bytes()));
+            if !mount_point.starts_with(&root) {
+                continue;
+            }
+            let fstype = unsafe { CStr::from_ptr(mount.f_fstypename.as_ptr()) };
+            result.push(MountInfo {
+                mount_point,
+                fstype: fstype.to_string_lossy().as_ref().into(),
+            });
+        }
+        Ok(result)
+    }
 
-        Ok(fs.to_str()?.into())
+    /// Ask `diskutil` about FileVault on the volume mounted at the mount
+    /// point of `path`. There is no public API for this.
+    pub fn is_encrypted(path: &Path) -> io::Result<Option<bool>> {
+        let fs_stat =
+            super::unix::get_statfs(path).map_err(|e| io::Error::new(io::ErrorKind::Other, e))?;
+        let mount_point = unsafe { CStr::from_ptr(fs_stat.f_mntonname.as_ptr()) };
+        let mount_point = match mount_point.to_str() {
+            Ok(mount_point) => mount_point,
+            Err(_) => return Ok(None),
+        };
+
+        let output = match std::process::Command::new("diskutil")
+            .args(&["info", mount_point])
+            .output()
+        {
+            Ok(output) if output.status.success() => output,
+            _ => return Ok(None),
+        };
+        Ok(filevault_from_diskutil_info(&String::from_utf8_lossy(
+            &output.stdout,
+        )))
+    }
+
+    /// Read the "FileVault: Yes/No" line of `diskutil info` output.
+    pub fn filevault_from_diskutil_info(info: &str) -> Option<bool> {
+        info.lines().find_map(|line| {
+            let mut words = line.splitn(2, ':');
+            match (words.next()?.trim(), words.next()?.trim()) {
+                ("FileVault", "Yes") => Some(true),
+                ("FileVault", "No") => Some(false),
+                _ => None,
+            }
+        })
     }
 }
 
@@ -298,3 +928,422 @@ pub fn fstype(path: impl AsRef<Path>) -> Result<FsType> {
 
     fstype_imp(path).with_context(|| format!("Cannot determine filesystem type for {:?}", path))
 }
+
+/// Remembers filesystem types for a while, for callers looking up the same
+/// paths repeatedly.
+pub struct CachedFsInfo {
+    ttl: Duration,
+    entries: Mutex<HashMap<PathBuf, (Instant, FsType)>>,
+}
+
+impl CachedFsInfo {
+    /// Create a cache whose entries are looked up again once older than
+    /// `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Get the filesystem type on the given `path`, like `fstype`. Paths are
+    /// canonicalized, so different paths to the same directory share an
+    /// entry.
+    pub fn lookup(&self, path: impl AsRef<Path>) -> Result<FsType> {
+        let path = path.as_ref();
+        let path = if path == Path::new("") {
+            Path::new(".")
+        } else {
+            path
+        };
+        let path = path
+            .canonicalize()
+            .with_context(|| format!("Cannot determine filesystem type for {:?}", path))?;
+
+        if let Some((time, fstype)) = self.entries.lock().unwrap().get(&path) {
+            if time.elapsed() < self.ttl {
+                return Ok(fstype.clone());
+            }
+        }
+        // Not holding the lock while looking up, as that can be slow on
+        // network filesystems.
+        let fstype = fstype(&path)?;
+        self.entries
+            .lock()
+            .unwrap()
+            .insert(path, (Instant::now(), fstype.clone()));
+        Ok(fstype)
+    }
+}
+
+impl Default for CachedFsInfo {
+    /// Entries are kept for 5 seconds.
+    fn default() -> Self {
+        Self::new(Duration::from_secs(5))
+    }
+}
+
+/// Get the filesystem type of the file or directory open as `fd`.
+///
+/// Unlike `fstype`, this is not affected by the path being replaced after
+/// the file was opened.
+#[cfg(any(target_os = "linux", target_os = "macos"))]
+pub fn fstype_from_fd(fd: RawFd) -> Result<FsType> {
+    fstype_from_fd_imp(fd)
+        .with_context(|| format!("Cannot determine filesystem type for fd {}", fd))
+}
+
+/// Get the filesystem type of the file or directory open as `handle`.
+#[cfg(windows)]
+pub fn fstype_from_handle(handle: RawHandle) -> Result<FsType> {
+    fstype_from_handle_imp(handle as _).context("Cannot determine filesystem type for handle")
+}
+
+/// Get the UUID of the filesystem on the given `path`.
+///
+/// This identifies the filesystem across remounts. Return None if the
+/// filesystem has no UUID, or it cannot be found.
+pub fn get_fs_uuid(path: impl AsRef<Path>) -> Result<Option<String>> {
+    let path = path.as_ref();
+    fs_uuid_imp(path).with_context(|| format!("Cannot determine filesystem UUID for {:?}", path))
+}
+
+/// Name the filesystem type with the given `statfs` magic number.
+///
+/// This is for filesystems this crate does not know about. Their type is
+/// then reported as `FsType::Unknown(name)`. The magic numbers of known
+/// filesystems cannot be overridden.
+#[cfg(target_os = "linux")]
+pub fn register_fs_magic(magic: i64, name: &'static str) {
+    register_fs_magic_imp(magic, name)
+}
+
+/// Get the filesystem type, and space and inode usage, of the filesystem
+/// containing the repo at `repo_root`.
+pub fn get_repo_fs_stats(repo_root: impl AsRef<Path>) -> Result<FsStats> {
+    let repo_root = repo_root.as_ref();
+    fs_stats_imp(repo_root)
+        .with_context(|| format!("Cannot determine filesystem stats for {:?}", repo_root))
+}
+
+/// Check whether memory-mapped I/O is safe to use on the given `path`.
+pub fn supports_mmap(path: impl AsRef<Path>) -> Result<bool> {
+    Ok(fstype(path)?.supports_mmap())
+}
+
+/// Get how access times are updated on the filesystem containing `path`.
+///
+/// This is read from the mount options on Linux. Other platforms always
+/// return `AtimeMode::Unknown`.
+pub fn atime_behavior(path: impl AsRef<Path>) -> io::Result<AtimeMode> {
+    #[cfg(target_os = "linux")]
+    {
+        atime_behavior_imp(path.as_ref())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        Ok(AtimeMode::Unknown)
+    }
+}
+
+/// If `path` is on a bind mount, get the directory the bind mount exposes.
+///
+/// The returned path is on the filesystem the bind mount was made from, or
+/// relative to that filesystem's root if it is not mounted elsewhere. Return
+/// None for other mounts, and on platforms other than Linux.
+pub fn get_bind_mount_source(path: impl AsRef<Path>) -> io::Result<Option<PathBuf>> {
+    #[cfg(target_os = "linux")]
+    {
+        bind_mount_source_imp(path.as_ref())
+    }
+    #[cfg(not(target_os = "linux"))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
+
+/// List the filesystems mounted at or below `root`, including the one
+/// mounted at `root` itself if any.
+///
+/// This reads the mount table on Linux and macOS. Other platforms return an
+/// error.
+pub fn list_filesystems_under(root: impl AsRef<Path>) -> io::Result<Vec<MountInfo>> {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        list_filesystems_imp(root.as_ref())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = root;
+        Err(io::Error::new(
+            io::ErrorKind::Other,
+            "listing filesystems is not supported on this platform",
+        ))
+    }
+}
+
+/// Check whether the filesystem containing `path` is on an encrypted volume.
+///
+/// On Linux this looks for a dm-crypt mapping (ex. LUKS) backing the
+/// filesystem, and on macOS it checks FileVault. Return None if this cannot
+/// be determined, including on Windows, where BitLocker status is not
+/// available without elevated privileges.
+pub fn is_encrypted(path: impl AsRef<Path>) -> io::Result<Option<bool>> {
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    {
+        is_encrypted_imp(path.as_ref())
+    }
+    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
+    {
+        let _ = path;
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_supports_mmap() {
+        assert!(!FsType::EDENFS.supports_mmap());
+        assert!(!FsType::NFS.supports_mmap());
+        assert!(!FsType::FUSE.supports_mmap());
+        assert!(!FsType::CIFS.supports_mmap());
+        assert!(!FsType::Unknown("cifs".to_string()).supports_mmap());
+        assert!(!FsType::Unknown("smbfs".to_string()).supports_mmap());
+        assert!(!FsType::Unknown("fuse.sshfs".to_string()).supports_mmap());
+
+        assert!(FsType::EXT4.supports_mmap());
+        assert!(FsType::BTRFS.supports_mmap());
+        assert!(FsType::APFS.supports_mmap());
+        assert!(FsType::NTFS.supports_mmap());
+        assert!(FsType::TMPFS.supports_mmap());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_get_fs_uuid() {
+        // The root filesystem might not have an UUID (ex. in containers),
+        // but the lookup must not fail.
+        if let Some(uuid) = get_fs_uuid("/").unwrap() {
+            assert!(!uuid.is_empty());
+        }
+        // procfs is never backed by a block device.
+        assert_eq!(get_fs_uuid("/proc").unwrap(), None);
+        assert!(get_fs_uuid("/nonexistent/path").is_err());
+    }
+
+    #[cfg(any(target_os = "linux", target_os = "macos"))]
+    #[test]
+    fn test_fstype_from_fd() {
+        use std::os::unix::io::AsRawFd;
+
+        let dir = std::env::temp_dir();
+        let file = std::fs::File::open(&dir).unwrap();
+        assert_eq!(
+            fstype_from_fd(file.as_raw_fd()).unwrap(),
+            fstype(&dir).unwrap()
+        );
+        assert!(fstype_from_fd(-1).is_err());
+    }
+
+    #[test]
+    fn test_cached_fs_info() {
+        let dir = std::env::temp_dir();
+        let cache = CachedFsInfo::default();
+        assert_eq!(cache.lookup(&dir).unwrap(), fstype(&dir).unwrap());
+        assert_eq!(cache.lookup(dir.join(".")).unwrap(), fstype(&dir).unwrap());
+        assert_eq!(cache.entries.lock().unwrap().len(), 1);
+        assert!(cache.lookup("/nonexistent/path").is_err());
+
+        // Expired entries are replaced.
+        let cache = CachedFsInfo::new(Duration::from_secs(0));
+        cache.lookup(&dir).unwrap();
+        let first = cache.entries.lock().unwrap()[&dir.canonicalize().unwrap()].0;
+        std::thread::sleep(Duration::from_millis(10));
+        cache.lookup(&dir).unwrap();
+        let second = cache.entries.lock().unwrap()[&dir.canonicalize().unwrap()].0;
+        assert!(second > first);
+    }
+
+    #[test]
+    fn test_get_repo_fs_stats() {
+        let stats = get_repo_fs_stats(std::env::temp_dir()).unwrap();
+        assert!(stats.total_bytes > 0);
+        assert!(stats.free_bytes <= stats.total_bytes);
+        assert!(stats.available_bytes <= stats.free_bytes);
+        if let (Some(total), Some(free)) = (stats.total_inodes, stats.free_inodes) {
+            assert!(free <= total);
+        }
+        assert!(get_repo_fs_stats("/nonexistent/path").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_register_fs_magic() {
+        const CUSTOM_MAGIC: i64 = 0x1234abcd;
+
+        assert_eq!(
+            FsType::from(CUSTOM_MAGIC),
+            FsType::Unknown("0x1234ABCD".to_string())
+        );
+        register_fs_magic(CUSTOM_MAGIC, "customfs");
+        assert_eq!(
+            FsType::from(CUSTOM_MAGIC),
+            FsType::Unknown("customfs".to_string())
+        );
+        // Known filesystems keep their type.
+        register_fs_magic(libc::EXT4_SUPER_MAGIC, "notext4");
+        assert_eq!(FsType::from(libc::EXT4_SUPER_MAGIC), FsType::EXT4);
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_fstype_from_magic() {
+        assert_eq!(FsType::from(0xff534d42), FsType::CIFS);
+        assert_eq!(
+            FsType::from(0x794c7630),
+            FsType::Unknown("overlay".to_string())
+        );
+        assert_eq!(
+            FsType::from(0x73717368),
+            FsType::Unknown("squashfs".to_string())
+        );
+        assert_eq!(FsType::from(0x2fc12fc1), FsType::Unknown("zfs".to_string()));
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_atime_mode_from_mountinfo() {
+        use std::path::Path;
+
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+40 22 8:17 / /data rw,nosuid,noatime shared:2 - xfs /dev/sdb1 rw,attr2
+41 22 8:33 / /mnt/with\\040space rw,strictatime shared:3 - ext4 /dev/sdc1 rw
+";
+        let mode = |path| super::linux::atime_mode_from_mountinfo(mountinfo, Path::new(path));
+
+        assert_eq!(mode("/data"), AtimeMode::Noatime);
+        assert_eq!(mode("/data/foo/bar"), AtimeMode::Noatime);
+        assert_eq!(mode("/database"), AtimeMode::Relatime);
+        assert_eq!(mode("/home"), AtimeMode::Relatime);
+        assert_eq!(mode("/mnt/with space/foo"), AtimeMode::Strict);
+        assert_eq!(mode("relative"), AtimeMode::Unknown);
+
+        assert!(atime_behavior("/").is_ok());
+        assert!(atime_behavior("/nonexistent/path").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_bind_mount_source_from_mountinfo() {
+        use std::path::Path;
+
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+40 22 8:17 / /data rw,noatime shared:2 - xfs /dev/sdb1 rw,attr2
+41 22 8:17 /repos/with\\040space /home/repos rw,noatime shared:2 - xfs /dev/sdb1 rw,attr2
+42 22 8:33 /exported /srv rw,relatime shared:3 - ext4 /dev/sdc1 rw
+";
+        let source =
+            |path| super::linux::bind_mount_source_from_mountinfo(mountinfo, Path::new(path));
+
+        assert_eq!(source("/"), None);
+        assert_eq!(source("/data/foo"), None);
+        assert_eq!(
+            source("/home/repos/foo"),
+            Some(PathBuf::from("/data/repos/with space"))
+        );
+        // The source filesystem is not mounted anywhere else.
+        assert_eq!(source("/srv"), Some(PathBuf::from("/exported")));
+
+        assert!(get_bind_mount_source("/nonexistent/path").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_filesystems_from_mountinfo() {
+        use std::path::Path;
+
+        let mountinfo = "\
+22 1 8:1 / / rw,relatime shared:1 - ext4 /dev/sda1 rw
+40 22 8:17 / /repo rw,noatime shared:2 - xfs /dev/sdb1 rw,attr2
+41 40 0:50 / /repo/sub\\040module rw,nosuid - fuse.sshfs host:/sub rw
+42 40 0:51 / /repo/build rw master:3 propagation_from:1 - tmpfs tmpfs rw
+43 40 0:52 / /repo/build rw - btrfs /dev/sdc1 rw
+44 22 0:53 / /repository rw - nfs4 host:/ rw
+";
+        let mounts = |root| super::linux::filesystems_from_mountinfo(mountinfo, Path::new(root));
+        let mount = |mount_point: &str, fstype| MountInfo {
+            mount_point: PathBuf::from(mount_point),
+            fstype,
+        };
+
+        assert_eq!(
+            mounts("/repo"),
+            vec![
+                mount("/repo", FsType::XFS),
+                mount(
+                    "/repo/sub module",
+                    FsType::Unknown("fuse.sshfs".to_string())
+                ),
+                // The btrfs mount hides the tmpfs mounted below it.
+                mount("/repo/build", FsType::BTRFS),
+            ]
+        );
+        assert_eq!(mounts("/repo/src"), vec![]);
+        assert_eq!(mounts("/").len(), 5);
+
+        let source = |path| super::linux::mount_source_from_mountinfo(mountinfo, Path::new(path));
+        assert_eq!(source("/repository/foo"), Some("host:/".to_string()));
+        assert_eq!(source("/repo"), Some("/dev/sdb1".to_string()));
+
+        assert!(!list_filesystems_under("/").unwrap().is_empty());
+        assert!(list_filesystems_under("/nonexistent/path").is_err());
+    }
+
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn test_is_dm_crypt() {
+        use std::fs;
+
+        let sys = std::env::temp_dir().join(format!("fsinfo-test-{}", std::process::id()));
+        let lvm = sys.join("dm-1");
+        fs::create_dir_all(lvm.join("dm")).unwrap();
+        fs::write(lvm.join("dm/uuid"), "LVM-abc\n").unwrap();
+        let luks = lvm.join("slaves/dm-0");
+        fs::create_dir_all(luks.join("dm")).unwrap();
+        fs::write(luks.join("dm/uuid"), "CRYPT-LUKS2-def\n").unwrap();
+        let partition = sys.join("8:1");
+        fs::create_dir_all(&partition).unwrap();
+
+        assert_eq!(super::linux::is_dm_crypt(&luks), Some(true));
+        assert_eq!(super::linux::is_dm_crypt(&lvm), Some(true));
+        assert_eq!(super::linux::is_dm_crypt(&partition), Some(false));
+        assert_eq!(super::linux::is_dm_crypt(&sys.join("missing")), None);
+        fs::remove_dir_all(&sys).unwrap();
+
+        // procfs is never backed by a block device.
+        assert_eq!(is_encrypted("/proc").unwrap(), None);
+        assert!(is_encrypted("/").is_ok());
+        assert!(is_encrypted("/nonexistent/path").is_err());
+    }
+
+    #[cfg(target_os = "macos")]
+    #[test]
+    fn test_filevault_from_diskutil_info() {
+        let info =
+            "   Volume Name:               Macintosh HD\n   FileVault:                 Yes\n";
+        assert_eq!(super::macos::filevault_from_diskutil_info(info), Some(true));
+        let info = "   FileVault:                 No\n";
+        assert_eq!(
+            super::macos::filevault_from_diskutil_info(info),
+            Some(false)
+        );
+        assert_eq!(super::macos::filevault_from_diskutil_info(""), None);
+    }
+}
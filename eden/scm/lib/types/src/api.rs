@@ -17,7 +17,7 @@ use crate::{
     path::RepoPathBuf,
 };
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DataRequest {
     pub keys: Vec<Key>,
 }
@@ -44,7 +44,7 @@ impl IntoIterator for DataResponse {
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct HistoryRequest {
     pub keys: Vec<Key>,
     pub depth: Option<u32>,
@@ -87,7 +87,7 @@ impl IntoIterator for HistoryResponse {
 ///
 /// In all cases, trees will be returned in a `DataResponse`, so there is no
 /// `TreeResponse` type to accompany `TreeRequest`.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TreeRequest {
     pub rootdir: RepoPathBuf,
     pub mfnodes: Vec<HgId>,